@@ -0,0 +1,246 @@
+use std::fmt;
+use std::str::FromStr;
+
+use secp256k1::PublicKey;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::primitives;
+
+const PAYLOAD_LEN: usize = 20;
+const CHECKSUM_LEN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    InvalidEncoding(String),
+    InvalidLength(usize),
+    InvalidChecksum,
+    UnknownVersion(u8),
+}
+
+/// What an address's payload commits to, named by its version byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressKind {
+    /// The hash of a compressed public key, spent by signing with that key.
+    PublicKeyHash,
+    /// The hash of a spending script. Reserved: no output paying to one can
+    /// be spent yet.
+    ScriptHash,
+}
+
+impl AddressKind {
+    pub fn version(self) -> u8 {
+        match self {
+            AddressKind::PublicKeyHash => Address::VERSION,
+            AddressKind::ScriptHash => Address::SCRIPT_HASH_VERSION,
+        }
+    }
+
+    pub fn from_version(version: u8) -> Option<AddressKind> {
+        match version {
+            Address::VERSION => Some(AddressKind::PublicKeyHash),
+            Address::SCRIPT_HASH_VERSION => Some(AddressKind::ScriptHash),
+            _ => None,
+        }
+    }
+}
+
+/// A version byte followed by a 20-byte payload, by default the first 20
+/// bytes of the SHA-256 of a compressed secp256k1 public key. Its text form
+/// is base58 with a 4-byte double-SHA-256 checksum appended.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address {
+    version: u8,
+    payload: [u8; PAYLOAD_LEN],
+}
+
+fn sha256(bytes: &[u8]) -> Vec<u8> {
+    primitives::sha256(bytes).to_vec()
+}
+
+fn checksum(bytes: &[u8]) -> Vec<u8> {
+    sha256(&sha256(bytes))[..CHECKSUM_LEN].to_vec()
+}
+
+impl Address {
+    /// Version of `AddressKind::PublicKeyHash` addresses.
+    pub const VERSION: u8 = 0x00;
+
+    /// Version of `AddressKind::ScriptHash` addresses.
+    pub const SCRIPT_HASH_VERSION: u8 = 0x05;
+
+    /// The key hash address of `public_key`, hashed in its 33-byte
+    /// compressed form.
+    pub fn from_public_key(public_key: &PublicKey) -> Address {
+        let digest = sha256(&public_key.serialize());
+        let mut payload = [0; PAYLOAD_LEN];
+        payload.copy_from_slice(&digest[..PAYLOAD_LEN]);
+        Address {
+            version: Address::VERSION,
+            payload,
+        }
+    }
+
+    /// The all-zero address. No known key hashes to it, so it is only used as
+    /// the owner of data outputs, which can't be spent anyway.
+    pub fn null() -> Address {
+        Address {
+            version: Address::VERSION,
+            payload: [0; PAYLOAD_LEN],
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn kind(&self) -> AddressKind {
+        AddressKind::from_version(self.version).expect("addresses are built with a known version")
+    }
+
+    /// Version byte followed by the payload, as committed to in output hashes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + PAYLOAD_LEN);
+        bytes.push(self.version);
+        bytes.extend(&self.payload);
+        bytes
+    }
+
+    /// Read the form written by `to_bytes`. Versions other than those of
+    /// `AddressKind` are rejected.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Address, AddressError> {
+        if bytes.len() != 1 + PAYLOAD_LEN {
+            return Err(AddressError::InvalidLength(bytes.len()));
+        }
+        if AddressKind::from_version(bytes[0]).is_none() {
+            return Err(AddressError::UnknownVersion(bytes[0]));
+        }
+        let mut payload = [0; PAYLOAD_LEN];
+        payload.copy_from_slice(&bytes[1..]);
+        Ok(Address {
+            version: bytes[0],
+            payload,
+        })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bytes = self.to_bytes();
+        bytes.extend(checksum(&bytes));
+        write!(f, "{}", bs58::encode(bytes).into_string())
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Address, AddressError> {
+        let bytes = bs58::decode(s)
+            .into_vec()
+            .map_err(|e| AddressError::InvalidEncoding(e.to_string()))?;
+        if bytes.len() != 1 + PAYLOAD_LEN + CHECKSUM_LEN {
+            return Err(AddressError::InvalidLength(bytes.len()));
+        }
+        let (body, expected_checksum) = bytes.split_at(1 + PAYLOAD_LEN);
+        if checksum(body) != expected_checksum {
+            return Err(AddressError::InvalidChecksum);
+        }
+        Address::from_bytes(body)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Address::from_str(&s).map_err(|e| serde::de::Error::custom(format!("{:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use secp256k1::{rand, PublicKey};
+
+    use super::{checksum, sha256, Address, AddressError, AddressKind, PAYLOAD_LEN};
+
+    fn new_address() -> Address {
+        let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        Address::from_public_key(&public_key)
+    }
+
+    #[test]
+    fn should_round_trip_through_string() {
+        let address = new_address();
+        let parsed = Address::from_str(&address.to_string()).unwrap();
+        assert_eq!(address, parsed);
+        assert_eq!(Address::VERSION, parsed.version());
+    }
+
+    #[test]
+    fn should_hash_compressed_public_key() {
+        let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        let compressed = public_key.serialize();
+        assert_eq!(33, compressed.len());
+
+        let address = Address::from_public_key(&PublicKey::from_slice(&compressed).unwrap());
+        assert_eq!(Address::from_public_key(&public_key), address);
+        assert_eq!(
+            &sha256(&compressed)[..PAYLOAD_LEN],
+            &address.to_bytes()[1..]
+        );
+        assert_eq!(AddressKind::PublicKeyHash, address.kind());
+        assert_eq!(address, Address::from_str(&address.to_string()).unwrap());
+    }
+
+    #[test]
+    fn should_reject_unknown_version() {
+        let encode = |version: u8| {
+            let mut bytes = vec![version];
+            bytes.extend([7; PAYLOAD_LEN]);
+            bytes.extend(checksum(&bytes));
+            bs58::encode(bytes).into_string()
+        };
+
+        let script_hash = Address::from_str(&encode(Address::SCRIPT_HASH_VERSION)).unwrap();
+        assert_eq!(AddressKind::ScriptHash, script_hash.kind());
+        assert_eq!(
+            Err(AddressError::UnknownVersion(0x42)),
+            Address::from_str(&encode(0x42))
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_checksum() {
+        let mut encoded = bs58::decode(new_address().to_string()).into_vec().unwrap();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0x01;
+        let tampered = bs58::encode(encoded).into_string();
+
+        assert_eq!(
+            Err(AddressError::InvalidChecksum),
+            Address::from_str(&tampered)
+        );
+    }
+
+    #[test]
+    fn should_reject_truncated_address() {
+        let encoded = bs58::decode(new_address().to_string()).into_vec().unwrap();
+        let truncated = bs58::encode(&encoded[..encoded.len() - 1]).into_string();
+
+        assert_eq!(
+            Err(AddressError::InvalidLength(24)),
+            Address::from_str(&truncated)
+        );
+        assert!(matches!(
+            Address::from_str("not-base58-0OIl"),
+            Err(AddressError::InvalidEncoding(_))
+        ));
+    }
+}
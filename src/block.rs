@@ -0,0 +1,706 @@
+use crate::check_difficulty;
+use crate::encoding::{self, DecodeError};
+use crate::hashable::HashAlgo;
+use crate::merkle::{merkle_proof_with, merkle_root_with, MerkleProof};
+use primitive_types::U256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+use super::Address;
+use super::Coins;
+use super::Hash;
+use super::Hashable;
+use super::TimeStamp;
+use super::Transaction;
+use super::TxOutput;
+
+/// Fixed timestamp of the genesis block, so every node derives the same hash.
+pub const GENESIS_TIMESTAMP: TimeStamp = 1_723_939_200_000;
+
+/// Largest serialized size of a block accepted by the chain.
+pub const MAX_BLOCK_BYTES: usize = 1_000_000;
+
+/// Largest total weight of the transactions in a block, see
+/// `Transaction::weight`.
+pub const MAX_BLOCK_WEIGHT: u64 = 400_000;
+
+/// Leading hash bytes shown in a block's heading.
+const SHORT_HASH_BYTES: usize = 8;
+
+/// How many hashes `mine_with_cancel` tries between checks of its flag.
+const CANCEL_CHECK_INTERVAL: u64 = 4_096;
+
+/// Coins credited to the genesis miner.
+pub const GENESIS_SUPPLY: Coins = Coins::from_whole(50);
+
+/// Result of a mining run that can be interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MineOutcome {
+    Found { hashes_tried: u64 },
+    Cancelled { hashes_tried: u64 },
+}
+
+/// The part of a block its hash commits to, plus the hash itself. Enough to
+/// follow a chain and check its proof of work without the transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub index: u32,
+    pub timestamp: TimeStamp,
+    pub hash: Hash,
+    pub prev_block_hash: Hash,
+    pub merkle_root: Hash,
+    pub nonce: u64,
+    #[serde(with = "difficulty_bytes")]
+    pub difficulty: U256,
+    /// Hash function for the block hash and Merkle root. Every block of a
+    /// chain uses the one its genesis block names.
+    #[serde(default)]
+    pub hash_algo: HashAlgo,
+    /// Id of the chain the block belongs to, fixed by its genesis block.
+    #[serde(default)]
+    pub chain_id: u32,
+}
+
+impl Hashable for BlockHeader {
+    fn bytes(&self) -> Vec<u8> {
+        // Every field but the hash itself, so none can change once mined
+        let mut block_bytes = vec![];
+        block_bytes.extend(&self.index.to_le_bytes());
+        block_bytes.extend(&self.timestamp.to_le_bytes());
+        block_bytes.extend(self.prev_block_hash.as_bytes());
+        block_bytes.extend(self.merkle_root.as_bytes());
+        block_bytes.extend(&self.nonce.to_le_bytes());
+        let mut difficulty = [0; 32];
+        self.difficulty.to_big_endian(&mut difficulty);
+        block_bytes.extend(&difficulty);
+        block_bytes.push(match self.hash_algo {
+            HashAlgo::Sha256 => 0,
+            HashAlgo::Blake3 => 1,
+        });
+        block_bytes.extend(&self.chain_id.to_le_bytes());
+        block_bytes
+    }
+
+    fn hash(&self) -> Hash {
+        self.hash_with(self.hash_algo)
+    }
+}
+
+impl BlockHeader {
+    /// Search for a nonce whose hash satisfies the difficulty target,
+    /// starting from the current nonce. Returns the number of hashes tried.
+    pub fn mine(&mut self) -> u64 {
+        let mut hashes_tried = 0;
+        loop {
+            let hash = self.hash();
+            hashes_tried += 1;
+            if check_difficulty(&hash, self.difficulty) {
+                self.hash = hash;
+                return hashes_tried;
+            }
+            self.nonce = self.nonce.wrapping_add(1);
+        }
+    }
+
+    /// Like `mine`, but gives up once `cancel` is set. The flag is checked
+    /// every `CANCEL_CHECK_INTERVAL` hashes, starting before the first one.
+    /// A cancelled header keeps the nonce it reached, so mining can resume.
+    pub fn mine_with_cancel(&mut self, cancel: &AtomicBool) -> MineOutcome {
+        let mut hashes_tried = 0;
+        loop {
+            if hashes_tried % CANCEL_CHECK_INTERVAL == 0 && cancel.load(Ordering::Relaxed) {
+                return MineOutcome::Cancelled { hashes_tried };
+            }
+            let hash = self.hash();
+            hashes_tried += 1;
+            if check_difficulty(&hash, self.difficulty) {
+                self.hash = hash;
+                return MineOutcome::Found { hashes_tried };
+            }
+            self.nonce = self.nonce.wrapping_add(1);
+        }
+    }
+
+    /// Like `mine`, but spreads the search over `threads` workers. Worker `t`
+    /// tries every nonce whose offset from the current nonce is `t` modulo
+    /// `threads`. Workers keep going until no lower offset can still win, so
+    /// the header ends up with the same nonce `mine` would have found.
+    /// Returns the number of hashes tried across all workers.
+    pub fn mine_parallel(&mut self, threads: usize) -> u64 {
+        let threads = threads.max(1) as u64;
+        let found = AtomicBool::new(false);
+        let best_offset = AtomicU64::new(u64::MAX);
+        let hashes_tried = AtomicU64::new(0);
+
+        thread::scope(|scope| {
+            for worker in 0..threads {
+                let mut header = self.clone();
+                let (found, best_offset, hashes_tried) = (&found, &best_offset, &hashes_tried);
+                scope.spawn(move || {
+                    let start = header.nonce;
+                    let mut offset = worker;
+                    let mut tried = 0;
+                    loop {
+                        if found.load(Ordering::Relaxed)
+                            && offset > best_offset.load(Ordering::Relaxed)
+                        {
+                            break;
+                        }
+                        header.nonce = start.wrapping_add(offset);
+                        tried += 1;
+                        if check_difficulty(&header.hash(), header.difficulty) {
+                            best_offset.fetch_min(offset, Ordering::Relaxed);
+                            found.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                        offset = offset.wrapping_add(threads);
+                    }
+                    hashes_tried.fetch_add(tried, Ordering::Relaxed);
+                });
+            }
+        });
+
+        self.nonce = self.nonce.wrapping_add(best_offset.load(Ordering::Relaxed));
+        self.hash = self.hash();
+        hashes_tried.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+    /// Set on blocks whose transactions were dropped by
+    /// `Blockchain::prune_below`. Only the header is left.
+    pub pruned: bool,
+}
+
+/// Serialized form of a block: the header fields and the transactions side
+/// by side, as blocks were stored before headers were split out.
+#[derive(Serialize)]
+struct BlockRef<'a> {
+    index: u32,
+    timestamp: TimeStamp,
+    hash: &'a Hash,
+    prev_block_hash: &'a Hash,
+    merkle_root: &'a Hash,
+    nonce: u64,
+    #[serde(with = "difficulty_bytes")]
+    difficulty: U256,
+    hash_algo: HashAlgo,
+    chain_id: u32,
+    transactions: &'a [Transaction],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pruned: bool,
+}
+
+#[derive(Deserialize)]
+struct BlockFile {
+    index: u32,
+    timestamp: TimeStamp,
+    hash: Hash,
+    prev_block_hash: Hash,
+    merkle_root: Hash,
+    nonce: u64,
+    #[serde(with = "difficulty_bytes")]
+    difficulty: U256,
+    #[serde(default)]
+    hash_algo: HashAlgo,
+    #[serde(default)]
+    chain_id: u32,
+    transactions: Vec<Transaction>,
+    #[serde(default)]
+    pruned: bool,
+}
+
+/// Serde for a difficulty as its 32 big-endian bytes, so saved chains do not
+/// depend on how `U256` serializes itself: a hex string of all 64 digits in
+/// human-readable formats such as JSON, the bytes themselves otherwise.
+pub(crate) mod difficulty_bytes {
+    use primitive_types::U256;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::Hash;
+
+    const DIFFICULTY_LEN: usize = 32;
+
+    pub fn serialize<S: Serializer>(difficulty: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bytes = [0; DIFFICULTY_LEN];
+        difficulty.to_big_endian(&mut bytes);
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&Hash::from(bytes.to_vec()).to_hex())
+        } else {
+            bytes.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        if !deserializer.is_human_readable() {
+            let bytes = <[u8; DIFFICULTY_LEN]>::deserialize(deserializer)?;
+            return Ok(U256::from_big_endian(&bytes));
+        }
+        let hex = String::deserialize(deserializer)?;
+        let bytes = Hash::from_hex(&hex).map_err(|e| D::Error::custom(format!("{:?}", e)))?;
+        if bytes.len() != DIFFICULTY_LEN {
+            return Err(D::Error::invalid_length(
+                bytes.len(),
+                &"a difficulty of 32 bytes",
+            ));
+        }
+        Ok(U256::from_big_endian(&bytes))
+    }
+}
+
+impl Serialize for Block {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let header = &self.header;
+        BlockRef {
+            index: header.index,
+            timestamp: header.timestamp,
+            hash: &header.hash,
+            prev_block_hash: &header.prev_block_hash,
+            merkle_root: &header.merkle_root,
+            nonce: header.nonce,
+            difficulty: header.difficulty,
+            hash_algo: header.hash_algo,
+            chain_id: header.chain_id,
+            transactions: &self.transactions,
+            pruned: self.pruned,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Block {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Block, D::Error> {
+        let file = BlockFile::deserialize(deserializer)?;
+        Ok(Block {
+            header: BlockHeader {
+                index: file.index,
+                timestamp: file.timestamp,
+                hash: file.hash,
+                prev_block_hash: file.prev_block_hash,
+                merkle_root: file.merkle_root,
+                nonce: file.nonce,
+                difficulty: file.difficulty,
+                hash_algo: file.hash_algo,
+                chain_id: file.chain_id,
+            },
+            transactions: file.transactions,
+            pruned: file.pruned,
+        })
+    }
+}
+
+impl From<&Block> for BlockHeader {
+    fn from(block: &Block) -> Self {
+        block.header.clone()
+    }
+}
+
+impl Hashable for Block {
+    fn bytes(&self) -> Vec<u8> {
+        self.header.bytes()
+    }
+
+    fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+}
+
+/// A heading with the index and shortened hash, then one field per line and
+/// each transaction indented below.
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = &self.header;
+        let hash = header.hash.to_hex();
+        let short_hash = &hash[..hash.len().min(2 * SHORT_HASH_BYTES)];
+        writeln!(f, "Block #{} {}…", header.index, short_hash)?;
+        writeln!(f, "  previous:     {}", header.prev_block_hash)?;
+        writeln!(f, "  timestamp:    {}", header.timestamp)?;
+        writeln!(f, "  difficulty:   {:#066x}", header.difficulty)?;
+        writeln!(f, "  nonce:        {}", header.nonce)?;
+        write!(f, "  transactions: {}", self.transactions.len())?;
+        for transaction in &self.transactions {
+            write!(f, "\n    {}", transaction)?;
+        }
+        Ok(())
+    }
+}
+
+impl Block {
+    pub fn new(
+        index: u32,
+        timestamp: u128,
+        prev_block_hash: Hash,
+        transactions: Vec<Transaction>,
+        difficulty: U256,
+    ) -> Self {
+        Block {
+            header: BlockHeader {
+                index,
+                timestamp,
+                hash: Hash::zero(),
+                prev_block_hash,
+                merkle_root: Block::compute_merkle_root(&transactions),
+                nonce: 0,
+                difficulty,
+                hash_algo: HashAlgo::Sha256,
+                chain_id: 0,
+            },
+            transactions,
+            pruned: false,
+        }
+    }
+
+    /// Hash of the header fields as they are now, which a valid block stores
+    /// in `header.hash`.
+    pub fn compute_hash(&self) -> Hash {
+        self.header.hash()
+    }
+
+    /// Whether the stored hash matches the header and meets the block's
+    /// difficulty target.
+    pub fn verify_pow(&self) -> bool {
+        self.compute_hash() == self.header.hash
+            && check_difficulty(&self.header.hash, self.header.difficulty)
+    }
+
+    /// The compact binary form sent between nodes, see `encoding`.
+    pub fn encode(&self) -> Vec<u8> {
+        encoding::encode_block(self)
+    }
+
+    /// Read a block written by `encode`.
+    pub fn decode(bytes: &[u8]) -> Result<Block, DecodeError> {
+        encoding::decode_block(bytes)
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// Switch the block to `algo`, recomputing its Merkle root.
+    pub fn with_hash_algo(mut self, algo: HashAlgo) -> Self {
+        self.header.hash_algo = algo;
+        self.header.merkle_root = Block::compute_merkle_root_with(&self.transactions, algo);
+        self
+    }
+
+    /// Mark the block as belonging to chain `chain_id`. Its transactions must
+    /// carry the same id.
+    pub fn with_chain_id(mut self, chain_id: u32) -> Self {
+        self.header.chain_id = chain_id;
+        self
+    }
+
+    /// Bytes carried by the coinbase's data output, such as the tag given
+    /// to `Blockchain::create_candidate_block`.
+    pub fn coinbase_data(&self) -> Option<&[u8]> {
+        self.transactions
+            .first()?
+            .outputs
+            .iter()
+            .find_map(|output| output.data.as_deref())
+    }
+
+    /// Make the coinbase's data output carry `data`, adding one if there is
+    /// none, and update the Merkle root. The block hash changes with it, so
+    /// a miner that has tried every nonce can change part of the data, as an
+    /// extra nonce, and search again; the block has to be mined afresh. The
+    /// chain accepts at most `MAX_DATA_BYTES`. Does nothing without a
+    /// coinbase.
+    pub fn set_coinbase_data(&mut self, data: Vec<u8>) {
+        let coinbase = match self.transactions.first_mut() {
+            Some(coinbase) if coinbase.is_coinbase() => coinbase,
+            _ => return,
+        };
+        match coinbase
+            .outputs
+            .iter_mut()
+            .find(|output| output.data.is_some())
+        {
+            Some(output) => output.data = Some(data),
+            None => coinbase.outputs.push(TxOutput {
+                address: Address::null(),
+                value: Coins::ZERO,
+                data: Some(data),
+            }),
+        }
+        self.header.merkle_root =
+            Block::compute_merkle_root_with(&self.transactions, self.header.hash_algo);
+    }
+
+    /// Build and mine the genesis block: index 0, an all-zero previous hash and
+    /// a single coinbase crediting `GENESIS_SUPPLY` to `miner_address`. The
+    /// result depends only on its arguments.
+    pub fn genesis(miner_address: Address, difficulty: U256) -> Self {
+        Block::genesis_with(miner_address, difficulty, HashAlgo::Sha256, 0)
+    }
+
+    /// Like `genesis`, fixing `algo` as the hash function and `chain_id` as
+    /// the id of the chain.
+    pub fn genesis_with(
+        miner_address: Address,
+        difficulty: U256,
+        algo: HashAlgo,
+        chain_id: u32,
+    ) -> Self {
+        let coinbase = Transaction::builder()
+            .output(miner_address, GENESIS_SUPPLY)
+            .timestamp(GENESIS_TIMESTAMP)
+            .chain_id(chain_id)
+            .build()
+            .expect("coinbase has an output");
+        let mut block = Block::new(
+            0,
+            GENESIS_TIMESTAMP,
+            Hash::zero(),
+            vec![coinbase],
+            difficulty,
+        )
+        .with_hash_algo(algo)
+        .with_chain_id(chain_id);
+        block.mine();
+        block
+    }
+
+    /// Total weight of the block's transactions.
+    pub fn weight(&self) -> u64 {
+        self.transactions.iter().map(Transaction::weight).sum()
+    }
+
+    /// Size of the block in its serialized (JSON) form, in bytes.
+    pub fn serialized_size(&self) -> usize {
+        serde_json::to_vec(self)
+            .expect("block is serializable")
+            .len()
+    }
+
+    /// Merkle root over the hashes of `transactions`, in block order.
+    pub fn compute_merkle_root(transactions: &[Transaction]) -> Hash {
+        Block::compute_merkle_root_with(transactions, HashAlgo::Sha256)
+    }
+
+    /// Like `compute_merkle_root`, hashing the transactions and the tree
+    /// with `algo`.
+    pub fn compute_merkle_root_with(transactions: &[Transaction], algo: HashAlgo) -> Hash {
+        let hashes = transactions
+            .iter()
+            .map(|transaction| transaction.hash_with(algo))
+            .collect::<Vec<Hash>>();
+        merkle_root_with(&hashes, algo)
+    }
+
+    /// Proof that the transaction hashing to `tx_hash` is committed to by
+    /// the header's Merkle root, to be checked with `verify_merkle_proof`.
+    /// `None` if the block does not hold it.
+    pub fn merkle_proof(&self, tx_hash: &Hash) -> Option<MerkleProof> {
+        let algo = self.header.hash_algo;
+        let hashes = self
+            .transactions
+            .iter()
+            .map(|transaction| transaction.hash_with(algo))
+            .collect::<Vec<Hash>>();
+        let index = hashes.iter().position(|hash| hash == tx_hash)?;
+        merkle_proof_with(&hashes, index, algo)
+    }
+
+    /// Mine the header, see `BlockHeader::mine`.
+    pub fn mine(&mut self) -> u64 {
+        self.header.mine()
+    }
+
+    /// Mine the header, see `BlockHeader::mine_with_cancel`.
+    pub fn mine_with_cancel(&mut self, cancel: &AtomicBool) -> MineOutcome {
+        self.header.mine_with_cancel(cancel)
+    }
+
+    /// Mine the header, see `BlockHeader::mine_parallel`.
+    pub fn mine_parallel(&mut self, threads: usize) -> u64 {
+        self.header.mine_parallel(threads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Block, MineOutcome};
+    use crate::Transaction;
+    use crate::{check_difficulty, verify_merkle_proof, Address, Coins, Hash, HashAlgo, Hashable};
+    use primitive_types::U256;
+    use std::sync::atomic::AtomicBool;
+
+    fn easy_block() -> Block {
+        let target = U256::max_value() >> 6;
+        Block::new(1, 0, Hash::zero(), vec![], target)
+    }
+
+    #[test]
+    fn should_serialize_difficulty_as_fixed_big_endian_bytes() {
+        for difficulty in [U256::max_value(), U256::from(5)] {
+            let block = Block::new(1, 0, Hash::zero(), vec![], difficulty);
+            let mut json = serde_json::to_value(&block).unwrap();
+            let hex = json["difficulty"].as_str().unwrap().to_string();
+            assert_eq!(64, hex.len());
+            assert_eq!(difficulty, U256::from_str_radix(&hex, 16).unwrap());
+
+            let decoded: Block = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(difficulty, decoded.header.difficulty);
+            let header: super::BlockHeader =
+                serde_json::from_str(&serde_json::to_string(&block.header).unwrap()).unwrap();
+            assert_eq!(difficulty, header.difficulty);
+            assert_eq!(
+                difficulty,
+                Block::decode(&block.encode()).unwrap().header.difficulty
+            );
+
+            json["difficulty"] = serde_json::Value::from(&hex[2..]);
+            assert!(serde_json::from_value::<Block>(json).is_err());
+        }
+        assert_eq!(
+            format!("\"{}05\"", "0".repeat(62)),
+            serde_json::to_value(Block::new(1, 0, Hash::zero(), vec![], U256::from(5))).unwrap()
+                ["difficulty"]
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn should_display_index_and_hex_hash() {
+        let mut block = easy_block();
+        block.mine();
+        let shown = block.to_string();
+
+        assert!(shown.starts_with(&format!("Block #1 {}", &block.header.hash.to_hex()[..16])));
+        assert!(shown.contains(&format!("previous:     {}", "00".repeat(32))));
+        assert!(shown.contains("transactions: 0"));
+    }
+
+    #[test]
+    fn should_mine_in_parallel() {
+        let mut block = easy_block();
+        block.mine_parallel(4);
+        assert!(check_difficulty(
+            &block.header.hash,
+            block.header.difficulty
+        ));
+    }
+
+    #[test]
+    fn should_find_same_nonce_as_sequential_mining() {
+        let mut sequential = easy_block();
+        sequential.mine();
+        for threads in [1, 3, 8] {
+            let mut parallel = easy_block();
+            parallel.mine_parallel(threads);
+            assert_eq!(sequential.header.nonce, parallel.header.nonce);
+            assert_eq!(sequential.header.hash, parallel.header.hash);
+        }
+    }
+
+    #[test]
+    fn should_stop_mining_when_cancelled() {
+        // A zero target can never be met, so only the flag ends the search
+        let mut block = Block::new(1, 0, Hash::zero(), vec![], U256::zero());
+        let cancel = AtomicBool::new(true);
+        assert_eq!(
+            MineOutcome::Cancelled { hashes_tried: 0 },
+            block.mine_with_cancel(&cancel)
+        );
+    }
+
+    #[test]
+    fn should_report_found_when_not_cancelled() {
+        let mut block = easy_block();
+        let cancel = AtomicBool::new(false);
+        match block.mine_with_cancel(&cancel) {
+            MineOutcome::Found { hashes_tried } => assert!(hashes_tried > 0),
+            other => panic!("expected a block to be found, got {:?}", other),
+        }
+        assert!(check_difficulty(
+            &block.header.hash,
+            block.header.difficulty
+        ));
+    }
+
+    #[test]
+    fn should_hash_header_like_block() {
+        let mut block = easy_block();
+        block.mine();
+        assert_eq!(block.hash(), block.header().hash());
+        assert_eq!(block.header.hash, block.header().hash());
+    }
+
+    #[test]
+    fn should_stop_verifying_once_any_header_field_changes() {
+        let mut block = easy_block();
+        block.mine();
+        assert!(block.verify_pow());
+        let changes: [fn(&mut Block); 7] = [
+            |block| block.header.index += 1,
+            |block| block.header.timestamp += 1,
+            |block| block.header.prev_block_hash = Hash::from(vec![1; 32]),
+            |block| block.header.nonce += 1,
+            |block| block.header.difficulty = U256::max_value(),
+            |block| block.header.hash_algo = HashAlgo::Blake3,
+            |block| block.header.chain_id += 1,
+        ];
+        for change in changes {
+            let mut changed = block.clone();
+            change(&mut changed);
+            assert_ne!(block.header.hash, changed.header.hash());
+            assert!(!changed.verify_pow());
+        }
+    }
+
+    #[test]
+    fn should_keep_flat_serialized_form() {
+        let mut block = easy_block();
+        block.mine();
+        let json = serde_json::to_value(&block).unwrap();
+        assert_eq!(1, json["index"]);
+        assert!(json.get("header").is_none());
+
+        let decoded: Block = serde_json::from_value(json).unwrap();
+        assert_eq!(block.header, decoded.header);
+        assert!(decoded.verify_pow());
+    }
+
+    #[test]
+    fn should_prove_transaction_inclusion() {
+        let transactions: Vec<Transaction> = (1..=3)
+            .map(|value| {
+                Transaction::builder()
+                    .output(Address::null(), Coins(value))
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let target = U256::max_value();
+        let block = Block::new(1, 0, Hash::zero(), transactions.clone(), target);
+
+        for transaction in &transactions {
+            let proof = block.merkle_proof(&transaction.hash()).unwrap();
+            assert!(verify_merkle_proof(
+                &transaction.hash(),
+                &proof,
+                &block.header.merkle_root
+            ));
+        }
+
+        let outsider = Transaction::builder()
+            .output(Address::null(), Coins(4))
+            .build()
+            .unwrap();
+        assert_eq!(None, block.merkle_proof(&outsider.hash()));
+        let proof = block.merkle_proof(&transactions[0].hash()).unwrap();
+        assert!(!verify_merkle_proof(
+            &outsider.hash(),
+            &proof,
+            &block.header.merkle_root
+        ));
+    }
+}
@@ -1,36 +1,469 @@
-use std::cmp;
-use std::collections::HashSet;
+use std::cmp::{self, Reverse};
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::iter;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 
-use primitive_types::U256;
+use log::{debug, warn};
+use primitive_types::{U256, U512};
+use serde::{Deserialize, Serialize};
 
-use crate::check_difficulty;
+use crate::block::{BlockHeader, MAX_BLOCK_BYTES};
+use crate::chain_params::ChainParams;
+use crate::consensus::{Consensus, ProofOfWork};
+use crate::hashable::HashAlgo;
+use crate::transaction::MAX_DATA_BYTES;
 
 use super::Block;
 use super::Hash;
-use super::{now, Transaction, TxOutput};
+use super::Hashable;
+use super::{now, Address, Coins, TimeStamp, Transaction, TxOutput};
+use crate::transaction::OutPoint;
 
 #[derive(Debug)]
 pub enum BlockChainError {
     ProofOfWorkError(String),
     NotACoinBaseError(String),
     InvalidTransactionError(String),
-    InsufficientFundsError(String),
+    InsufficientFundsError {
+        available: Coins,
+        required: Coins,
+    },
     InputNotSpendableError(String),
     DoubleSpendingError(String),
+    MerkleRootMismatch(String),
+    InvalidSignatureError(String),
+    InvalidCoinbaseValue(String),
+    /// The block's serialized size in bytes or its weight is over the limit.
+    BlockTooLarge {
+        size: usize,
+        max: usize,
+    },
+    MempoolFull(String),
+    InvalidBlockIndex(String),
+    PreviousHashMismatch(String),
+    InvalidTimestamp(String),
+    ImmatureCoinbase {
+        created_at: u32,
+    },
+    TransactionLocked {
+        lock_time: u64,
+    },
+    InvalidCoinbaseStructure(String),
+    DuplicateInput(OutPoint),
+    /// A block creates an output that is already unspent, which would
+    /// overwrite it.
+    DuplicateOutput(OutPoint),
+    HashAlgoMismatch {
+        expected: HashAlgo,
+        found: HashAlgo,
+    },
+    ChainIdMismatch {
+        expected: u32,
+        found: u32,
+    },
+    InvalidBlockError {
+        index: u32,
+        cause: Box<BlockChainError>,
+    },
+    /// The hash stored in a block is not the hash of its header.
+    HashMismatch {
+        expected: Hash,
+        found: Hash,
+    },
+    /// A block with this hash is already in the chain or waiting as an
+    /// orphan.
+    DuplicateBlock(Hash),
+    /// The unspent outputs hold a different amount than the blocks issued.
+    SupplyMismatch {
+        expected: Coins,
+        found: Coins,
+    },
+    /// The transaction at this position of a block breaks the canonical
+    /// order, see `Blockchain::canonical_order`.
+    InvalidTransactionOrder(Hash),
+    /// A block at a checkpointed height has another hash than the
+    /// checkpoint. An all-zero `found` means a reorganization would leave no
+    /// block at that height.
+    CheckpointMismatch {
+        height: u32,
+        expected: Hash,
+        found: Hash,
+    },
+    /// The block declares another target than `Blockchain::next_difficulty`
+    /// gives on top of its parent.
+    DifficultyMismatch {
+        expected: U256,
+        found: U256,
+    },
+}
+
+impl fmt::Display for BlockChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockChainError::ProofOfWorkError(message)
+            | BlockChainError::NotACoinBaseError(message)
+            | BlockChainError::InvalidTransactionError(message)
+            | BlockChainError::InputNotSpendableError(message)
+            | BlockChainError::DoubleSpendingError(message)
+            | BlockChainError::MerkleRootMismatch(message)
+            | BlockChainError::InvalidSignatureError(message)
+            | BlockChainError::InvalidCoinbaseValue(message)
+            | BlockChainError::MempoolFull(message)
+            | BlockChainError::InvalidBlockIndex(message)
+            | BlockChainError::PreviousHashMismatch(message)
+            | BlockChainError::InvalidTimestamp(message)
+            | BlockChainError::InvalidCoinbaseStructure(message) => write!(f, "{}", message),
+            BlockChainError::InsufficientFundsError {
+                available,
+                required,
+            } => write!(
+                f,
+                "Inputs hold {} coins but at least {} are required.",
+                available, required
+            ),
+            BlockChainError::BlockTooLarge { size, max } => {
+                write!(f, "Block size {} exceeds the maximum of {}.", size, max)
+            }
+            BlockChainError::ImmatureCoinbase { created_at } => write!(
+                f,
+                "Coinbase output from block {} is not mature yet.",
+                created_at
+            ),
+            BlockChainError::DuplicateInput(outpoint) => {
+                write!(f, "Output {} is spent more than once.", outpoint)
+            }
+            BlockChainError::DuplicateOutput(outpoint) => {
+                write!(f, "Output {} is already unspent.", outpoint)
+            }
+            BlockChainError::TransactionLocked { lock_time } => {
+                write!(f, "Transaction is locked until {}.", lock_time)
+            }
+            BlockChainError::HashAlgoMismatch { expected, found } => write!(
+                f,
+                "Block is hashed with {:?} but the chain uses {:?}.",
+                found, expected
+            ),
+            BlockChainError::ChainIdMismatch { expected, found } => write!(
+                f,
+                "Chain id {} does not match this chain's id {}.",
+                found, expected
+            ),
+            BlockChainError::InvalidBlockError { index, cause } => {
+                write!(f, "Block {} is invalid: {}", index, cause)
+            }
+            BlockChainError::HashMismatch { expected, found } => write!(
+                f,
+                "Block claims hash {} but its header hashes to {}.",
+                found, expected
+            ),
+            BlockChainError::DuplicateBlock(hash) => write!(f, "Block {} is already known.", hash),
+            BlockChainError::SupplyMismatch { expected, found } => write!(
+                f,
+                "Blocks issued {} coins but unspent outputs hold {}.",
+                expected, found
+            ),
+            BlockChainError::InvalidTransactionOrder(hash) => {
+                write!(f, "Transaction {} is out of the canonical order.", hash)
+            }
+            BlockChainError::CheckpointMismatch {
+                height,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Block {} at height {} does not match checkpoint {}.",
+                found, height, expected
+            ),
+            BlockChainError::DifficultyMismatch { expected, found } => write!(
+                f,
+                "Block declares target {:x} but the chain requires {:x}.",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl Error for BlockChainError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BlockChainError::InvalidBlockError { cause, .. } => Some(cause.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Newly issued coins a miner may claim in a block's coinbase before any
+/// halving, on top of the fees of the transactions it includes.
+pub const BLOCK_REWARD: Coins = Coins::from_whole(50);
+
+/// Number of blocks after which the block reward halves.
+pub const HALVING_INTERVAL: usize = 210_000;
+
+/// Number of recent blocks whose median timestamp a new block must exceed.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far ahead of the local clock a block timestamp may be, in milliseconds.
+pub const MAX_FUTURE_BLOCK_TIME: TimeStamp = 2 * 60 * 60 * 1000;
+
+/// Confirmations a coinbase output needs before it can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Most blocks with an unknown parent kept while waiting for that parent.
+pub const MAX_ORPHANS: usize = 100;
+
+/// Default number of transactions the pool holds before evicting.
+pub const DEFAULT_MAX_POOL_SIZE: usize = 5_000;
+
+/// Default age, in seconds, after which `expire_mempool` drops a pooled
+/// transaction.
+pub const DEFAULT_MEMPOOL_EXPIRY_SECS: u64 = 14 * 24 * 60 * 60;
+
+/// Target used when there is no block to derive a difficulty from.
+pub const INITIAL_DIFFICULTY: U256 = U256([u64::MAX, u64::MAX, u64::MAX, 0x000f_ffff_ffff_ffff]);
+
+/// Desired time between blocks, in milliseconds.
+pub const TARGET_BLOCK_TIME: TimeStamp = 60_000;
+
+/// Number of blocks between difficulty adjustments, and the window of block
+/// timestamps each adjustment looks at.
+pub const RETARGET_INTERVAL: usize = 10;
+
+/// Largest factor by which a single adjustment may move the target.
+pub const MAX_RETARGET_FACTOR: TimeStamp = 4;
+
+/// Number of recent blocks whose fee rates `estimate_fee` looks at.
+pub const FEE_HISTORY_BLOCKS: usize = 20;
+
+/// Lowest fee rate `estimate_fee` returns, in units per byte.
+pub const MIN_FEE_RATE: f64 = 1.0;
+
+/// On-disk representation of a chain. Only the params and blocks are stored;
+/// all derived state, including the unspent output set, is rebuilt by
+/// replaying the blocks under the params.
+#[derive(Serialize)]
+struct ChainFileRef<'a> {
+    params: &'a ChainParams,
+    blocks: &'a [Block],
+}
+
+#[derive(Deserialize)]
+struct ChainFile {
+    /// Files written before the params were stored hold mainnet chains.
+    #[serde(default)]
+    params: ChainParams,
+    blocks: Vec<Block>,
+}
+
+/// A snapshot of the chain for monitoring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainStats {
+    pub tip_height: Option<u32>,
+    /// Transactions in all blocks, coinbases included.
+    pub total_transactions: usize,
+    pub utxo_count: usize,
+    /// Sum of all unspent output values.
+    pub total_supply: Coins,
+    pub mempool_size: usize,
+}
+
+/// A pooled transaction as reported by `mempool_info`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolEntry {
+    pub tx_hash: Hash,
+    pub fee: Coins,
+    /// Serialized size in bytes.
+    pub size: usize,
+    /// Fee in units per byte.
+    pub fee_rate: f64,
+    /// Milliseconds since the transaction's timestamp, which stands in for
+    /// its arrival as it does for `expire_mempool`.
+    pub time_in_pool: TimeStamp,
+}
+
+/// The next block as `get_block_template` describes it to an external
+/// miner. A nonce whose header hash is below `target` completes it, see
+/// `header`.
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub index: u32,
+    pub prev_block_hash: Hash,
+    pub merkle_root: Hash,
+    pub chain_id: u32,
+    pub hash_algo: HashAlgo,
+    pub target: U256,
+    /// Earliest timestamp the chain accepts for the block.
+    pub min_timestamp: TimeStamp,
+    /// Timestamp of the block, fixed when the template is made since the
+    /// block hash commits to it.
+    pub timestamp: TimeStamp,
+    pub coinbase: Transaction,
+    /// Pool transactions following the coinbase, in block order.
+    pub transactions: Vec<Transaction>,
+}
+
+impl BlockTemplate {
+    /// Header of the block with `nonce`, its hash filled in.
+    pub fn header(&self, nonce: u64) -> BlockHeader {
+        let mut header = BlockHeader {
+            index: self.index,
+            timestamp: self.timestamp,
+            hash: Hash::zero(),
+            prev_block_hash: self.prev_block_hash.clone(),
+            merkle_root: self.merkle_root.clone(),
+            nonce,
+            difficulty: self.target,
+            hash_algo: self.hash_algo,
+            chain_id: self.chain_id,
+        };
+        header.hash = header.hash();
+        header
+    }
+}
+
+/// Changes to the chain reported to subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    BlockAdded(Hash),
+    TransactionAdded(Hash),
+    /// The tip moved to another branch, disconnecting `depth` blocks. The
+    /// blocks of the new branch follow as `BlockAdded` events.
+    Reorg {
+        depth: u32,
+    },
 }
 
 pub struct Blockchain {
     blocks: Vec<Block>,
+    block_positions: HashMap<Hash, usize>,
+    /// Transaction hash to the index of its block and its position there.
+    tx_index: HashMap<Hash, (u32, usize)>,
+    /// Hashes of the transactions paying to or spending from each address,
+    /// in chain order.
+    address_history: HashMap<Address, Vec<Hash>>,
     transaction_pool: Vec<Transaction>,
-    pub unspent_output: HashSet<Hash>,
+    max_pool_size: usize,
+    mempool_expiry_secs: u64,
+    orphans: HashMap<Hash, Block>,
+    /// Index of the block that created each coinbase output, kept until
+    /// that block is disconnected so reverted spends stay immature.
+    coinbase_heights: HashMap<OutPoint, u32>,
+    /// Outputs spent by each applied transaction, in input order and keyed
+    /// by its hash, so reverting it can make them unspent again.
+    spent_by: HashMap<Hash, Vec<TxOutput>>,
+    subscribers: Vec<Sender<ChainEvent>>,
+    /// Fee rates of the transactions in each of the last
+    /// `FEE_HISTORY_BLOCKS` blocks, oldest first. Coinbases are left out.
+    fee_history: VecDeque<Vec<f64>>,
+    consensus: Arc<dyn Consensus>,
+    params: ChainParams,
+    /// Taken from the genesis block once it is connected, like `chain_id`.
+    hash_algo: HashAlgo,
+    chain_id: u32,
+    pub unspent_output: HashMap<OutPoint, TxOutput>,
 }
 
 impl Blockchain {
+    /// An empty chain with no genesis block, secured by `ProofOfWork`. The
+    /// first block built with `create_candidate_block` gets index 1; prefer
+    /// `with_genesis` outside of tests.
     pub fn new() -> Blockchain {
+        Blockchain::with_consensus(Box::new(ProofOfWork))
+    }
+
+    /// An empty chain accepting blocks by the rules of `consensus`.
+    pub fn with_consensus(consensus: Box<dyn Consensus>) -> Blockchain {
         Blockchain {
             blocks: vec![],
+            block_positions: HashMap::new(),
+            tx_index: HashMap::new(),
+            address_history: HashMap::new(),
             transaction_pool: vec![],
-            unspent_output: HashSet::new(),
+            max_pool_size: DEFAULT_MAX_POOL_SIZE,
+            mempool_expiry_secs: DEFAULT_MEMPOOL_EXPIRY_SECS,
+            orphans: HashMap::new(),
+            coinbase_heights: HashMap::new(),
+            spent_by: HashMap::new(),
+            subscribers: vec![],
+            fee_history: VecDeque::with_capacity(FEE_HISTORY_BLOCKS),
+            consensus: Arc::from(consensus),
+            params: ChainParams::mainnet(),
+            hash_algo: HashAlgo::Sha256,
+            chain_id: 0,
+            unspent_output: HashMap::new(),
+        }
+    }
+
+    /// An empty chain secured by `ProofOfWork` under `params` instead of the
+    /// mainnet rules.
+    pub fn with_params(params: ChainParams) -> Blockchain {
+        let mut blockchain = Blockchain::new();
+        blockchain.params = params;
+        blockchain
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    pub fn consensus(&self) -> &dyn Consensus {
+        self.consensus.as_ref()
+    }
+
+    /// `consensus`, for use once the chain is out of reach.
+    pub(crate) fn shared_consensus(&self) -> Arc<dyn Consensus> {
+        Arc::clone(&self.consensus)
+    }
+
+    /// Hash function for blocks, Merkle roots and transaction hashes, as
+    /// named by the genesis block.
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.hash_algo
+    }
+
+    /// Id every block and transaction of this chain carries, as named by the
+    /// genesis block.
+    pub fn chain_id(&self) -> u32 {
+        self.chain_id
+    }
+
+    /// A chain starting from the deterministic genesis block produced by
+    /// `Block::genesis`.
+    pub fn with_genesis(miner_address: Address, difficulty: U256) -> Blockchain {
+        let mut blockchain = Blockchain::new();
+        blockchain
+            .aggregate_mined_block(Block::genesis(miner_address, difficulty))
+            .expect("genesis block is valid");
+        blockchain
+    }
+
+    /// An independent copy of the blocks, pool, UTXO set and indices, for
+    /// validating or querying on another thread. Subscribers stay with this
+    /// chain; the snapshot starts without any.
+    pub fn snapshot(&self) -> Blockchain {
+        Blockchain {
+            blocks: self.blocks.clone(),
+            block_positions: self.block_positions.clone(),
+            tx_index: self.tx_index.clone(),
+            address_history: self.address_history.clone(),
+            transaction_pool: self.transaction_pool.clone(),
+            max_pool_size: self.max_pool_size,
+            mempool_expiry_secs: self.mempool_expiry_secs,
+            orphans: self.orphans.clone(),
+            coinbase_heights: self.coinbase_heights.clone(),
+            spent_by: self.spent_by.clone(),
+            subscribers: vec![],
+            fee_history: self.fee_history.clone(),
+            consensus: Arc::clone(&self.consensus),
+            params: self.params.clone(),
+            hash_algo: self.hash_algo,
+            chain_id: self.chain_id,
+            unspent_output: self.unspent_output.clone(),
         }
     }
 
@@ -39,180 +472,5142 @@ impl Blockchain {
         transaction: Transaction,
     ) -> Result<(), BlockChainError> {
         // verify transaction
-        match self.verify_transaction(&transaction) {
-            Ok(()) => println!("transaction verified"),
+        let hash = transaction.hash_with(self.hash_algo);
+        let replaced = match self.verify_replacement(&transaction) {
+            Ok(replaced) => replaced,
             Err(e) => {
-                println!("{:?}", e);
+                warn!("Rejected transaction {}: {}", hash, e);
                 return Err(e);
             }
+        };
+        debug!("Transaction {} verified", hash);
+
+        // make room by evicting the cheapest transaction that is not
+        // replaced, along with those spending its outputs, unless the new one
+        // is even cheaper. The pool is only changed once both are decided.
+        let mut evicted = Vec::new();
+        if self.transaction_pool.len() - replaced.len() >= self.max_pool_size {
+            let view = UtxoView::with_pool(self);
+            let cheapest = self
+                .transaction_pool
+                .iter()
+                .enumerate()
+                .filter(|(position, _)| !replaced.contains(position))
+                .map(|(position, pooled)| (position, Blockchain::fee_rate_in(pooled, &view)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+            match cheapest {
+                Some((position, fee_rate))
+                    if Blockchain::fee_rate_in(&transaction, &view) > fee_rate =>
+                {
+                    evicted = self.with_pool_descendants(&[position]);
+                    let evicted_hashes = evicted
+                        .iter()
+                        .map(|position| self.transaction_pool[*position].hash_with(self.hash_algo))
+                        .collect::<HashSet<Hash>>();
+                    if transaction
+                        .outpoints()
+                        .iter()
+                        .any(|outpoint| evicted_hashes.contains(&outpoint.tx_hash))
+                    {
+                        return Err(BlockChainError::MempoolFull(String::from(
+                            "Transaction pool is full and the transaction spends one to evict.",
+                        )));
+                    }
+                }
+                _ => {
+                    return Err(BlockChainError::MempoolFull(String::from(
+                        "Transaction pool is full and the fee rate is too low.",
+                    )))
+                }
+            }
+        }
+
+        let removed = replaced
+            .iter()
+            .chain(&evicted)
+            .copied()
+            .collect::<BTreeSet<usize>>();
+        for position in removed.into_iter().rev() {
+            let old = self.transaction_pool.remove(position);
+            if replaced.contains(&position) {
+                debug!(
+                    "Transaction {} replaced by {}",
+                    old.hash_with(self.hash_algo),
+                    hash
+                );
+            }
         }
 
         //TODO complete the validation process ( see spec document)
         self.transaction_pool.push(transaction);
+        self.emit(ChainEvent::TransactionAdded(hash));
         Ok(())
     }
 
+    /// Receive an event for every block added to the chain, transaction
+    /// added to the pool and reorganization from now on. Each call returns a
+    /// separate receiver; dropping it unsubscribes.
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn emit(&mut self, event: ChainEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Remove the pooled transaction with hash `tx_hash`, returning whether
+    /// one was found.
+    pub fn remove_transaction_from_pool(&mut self, tx_hash: &Hash) -> bool {
+        let pool_len = self.transaction_pool.len();
+        self.transaction_pool
+            .retain(|transaction| &transaction.hash_with(self.hash_algo) != tx_hash);
+        self.transaction_pool.len() != pool_len
+    }
+
+    /// Transactions waiting in the pool, in the order they were added.
+    pub fn mempool(&self) -> &[Transaction] {
+        &self.transaction_pool
+    }
+
+    /// Whether a pooled transaction hashes to `tx_hash` under the chain's
+    /// hash algorithm.
+    pub fn mempool_contains(&self, tx_hash: &Hash) -> bool {
+        self.mempool_transaction(tx_hash).is_some()
+    }
+
+    /// The pooled transaction hashing to `tx_hash` under the chain's hash
+    /// algorithm.
+    pub fn mempool_transaction(&self, tx_hash: &Hash) -> Option<&Transaction> {
+        self.transaction_pool
+            .iter()
+            .find(|transaction| &transaction.hash_with(self.hash_algo) == tx_hash)
+    }
+
+    /// Details of every pooled transaction, highest fee rate first as they
+    /// would be mined. Equal fee rates keep the order they were added in.
+    pub fn mempool_info(&self) -> Vec<MempoolEntry> {
+        let now = now();
+        let view = UtxoView::with_pool(self);
+        let mut entries = self
+            .transaction_pool
+            .iter()
+            .map(|transaction| MempoolEntry {
+                tx_hash: transaction.hash_with(self.hash_algo),
+                fee: Blockchain::fee_in(transaction, &view),
+                size: transaction.serialized_size(),
+                fee_rate: Blockchain::fee_rate_in(transaction, &view),
+                time_in_pool: now.saturating_sub(transaction.timestamp),
+            })
+            .collect::<Vec<MempoolEntry>>();
+        entries.sort_by(|a, b| b.fee_rate.total_cmp(&a.fee_rate));
+        entries
+    }
+
+    pub fn pool_len(&self) -> usize {
+        self.transaction_pool.len()
+    }
+
+    pub fn clear_pool(&mut self) {
+        self.transaction_pool.clear();
+    }
+
+    /// Limit the pool to `max_pool_size` transactions. Transactions already
+    /// pooled are kept; the limit applies to later insertions.
+    pub fn set_max_pool_size(&mut self, max_pool_size: usize) {
+        self.max_pool_size = max_pool_size;
+    }
+
+    /// Age in seconds after which `expire_mempool` drops a transaction.
+    pub fn set_mempool_expiry_secs(&mut self, mempool_expiry_secs: u64) {
+        self.mempool_expiry_secs = mempool_expiry_secs;
+    }
+
+    /// Drop pooled transactions whose timestamp is more than
+    /// `mempool_expiry_secs` before now, returning their hashes. Nothing
+    /// calls this on its own; applications run it periodically.
+    pub fn expire_mempool(&mut self) -> Vec<Hash> {
+        let cutoff = now().saturating_sub(self.mempool_expiry_secs as TimeStamp * 1000);
+        let mut expired = vec![];
+        self.transaction_pool.retain(|transaction| {
+            let keep = transaction.timestamp >= cutoff;
+            if !keep {
+                expired.push(transaction.hash_with(self.hash_algo));
+            }
+            keep
+        });
+        expired
+    }
+
+    /// Build an unmined block on the tip paying the reward and fees to
+    /// `miner_address`. The best paying pooled transactions are included as
+    /// long as their weight stays within `max_weight` and the block within
+    /// the params' `max_block_weight` and `MAX_BLOCK_BYTES`; the rest stay in
+    /// the pool. A `tag` goes into a data output of the coinbase, cut to
+    /// `MAX_DATA_BYTES`; see `Block::set_coinbase_data` for changing it
+    /// while mining.
     pub fn create_candidate_block(
         &mut self,
-        transactions_count: usize,
-        miner_address: String,
-        difficulty: U256,
+        max_weight: u64,
+        miner_address: Address,
+        difficulty: Option<U256>,
+        tag: Option<&str>,
     ) -> Block {
+        let difficulty = difficulty.unwrap_or_else(|| self.next_difficulty());
         let mut candidate_index: u32 = 0;
-        let mut previous_hash: Hash = Vec::new();
+        let mut previous_hash: Hash = Hash::zero();
         if let Some(latest_block) = self.blocks.last().cloned() {
-            candidate_index = latest_block.index;
-            previous_hash = latest_block.hash;
-        }
-        //Get transactions from pool up to transactions count
-        let pool_size = self.transaction_pool.len();
-        let block_transaction_count = cmp::min(pool_size, transactions_count);
-        let mut transactions: Vec<Transaction> = Vec::new();
-
-        // Add coinbase transaction
-        let coinbase = Transaction {
-            inputs: vec![],
-            outputs: vec![TxOutput {
-                address: miner_address,
-                value: 50.0,
-            }],
-            timestamp: now(),
-        };
-        transactions.push(coinbase);
-
-        transactions.extend(
-            self.transaction_pool
-                .drain(..block_transaction_count)
-                .collect::<Vec<Transaction>>(),
-        );
+            candidate_index = latest_block.header.index;
+            previous_hash = latest_block.header.hash;
+        }
+        // The height in the lock time keeps coinbases paying one address apart
+        let mut coinbase = Transaction::builder()
+            .output(miner_address, Coins(u64::MAX))
+            .lock_time(u64::from(candidate_index + 1))
+            .chain_id(self.chain_id);
+        if let Some(tag) = tag {
+            let tag = tag.as_bytes();
+            coinbase = coinbase.data(tag[..cmp::min(tag.len(), MAX_DATA_BYTES)].to_vec());
+        }
+        let coinbase = coinbase.build().expect("coinbase has an output");
+        let (mut positions, transactions) = self.candidate_transactions(max_weight, coinbase);
+        positions.sort_unstable();
+        for position in positions.into_iter().rev() {
+            self.transaction_pool.remove(position);
+        }
         Block::new(
             candidate_index + 1,
-            now(),
+            cmp::max(now(), self.median_time_past() + 1),
             previous_hash,
             transactions,
             difficulty,
         )
+        .with_hash_algo(self.hash_algo)
+        .with_chain_id(self.chain_id)
+    }
+
+    /// A template for the next block, for mining software that searches for
+    /// the nonce itself: the header fields it hashes, the target, and the
+    /// transactions, selected as `create_candidate_block` would but left in
+    /// the pool. The coinbase pays `miner` and carries the height as its lock
+    /// time, so its hash differs between blocks. Only the block timestamp
+    /// depends on the clock: it is now or the earliest allowed time,
+    /// whichever is later, so the same chain and pool always give the same
+    /// transactions.
+    pub fn get_block_template(&self, miner: &Address) -> BlockTemplate {
+        let (index, prev_block_hash) = match self.blocks.last() {
+            Some(tip) => (tip.header.index + 1, tip.header.hash.clone()),
+            None => (1, Hash::zero()),
+        };
+        let min_timestamp = self.median_time_past() + 1;
+        let coinbase = Transaction::builder()
+            .output(miner.clone(), Coins(u64::MAX))
+            .chain_id(self.chain_id)
+            .lock_time(u64::from(index))
+            .timestamp(min_timestamp)
+            .build()
+            .expect("coinbase has an output");
+        let (_, mut transactions) = self.candidate_transactions(u64::MAX, coinbase);
+        let merkle_root = Block::compute_merkle_root_with(&transactions, self.hash_algo);
+        let coinbase = transactions.remove(0);
+        BlockTemplate {
+            index,
+            prev_block_hash,
+            merkle_root,
+            chain_id: self.chain_id,
+            hash_algo: self.hash_algo,
+            target: self.next_difficulty(),
+            min_timestamp,
+            timestamp: cmp::max(now(), min_timestamp),
+            coinbase,
+            transactions,
+        }
+    }
+
+    /// Build the block `template` describes with `nonce` and add it to the
+    /// chain. Its header is exactly `template.header(nonce)`, so the nonce
+    /// found for the template still holds.
+    pub fn submit_block(
+        &mut self,
+        template: &BlockTemplate,
+        nonce: u64,
+    ) -> Result<(), BlockChainError> {
+        let mut transactions = vec![template.coinbase.clone()];
+        transactions.extend(template.transactions.iter().cloned());
+        let block = Block {
+            header: template.header(nonce),
+            transactions,
+            pruned: false,
+        };
+        self.aggregate_mined_block(block)
+    }
+
+    /// Pool positions of the transactions for a candidate block, and the
+    /// block's transactions: `coinbase`, paying the reward plus their fees,
+    /// followed by them in canonical order.
+    fn candidate_transactions(
+        &self,
+        max_weight: u64,
+        mut coinbase: Transaction,
+    ) -> (Vec<usize>, Vec<Transaction>) {
+        // The coinbase still carries the largest value it could, so the
+        // space left for the others is never overestimated
+        let positions = self.select_candidate_transactions(
+            cmp::min(max_weight, self.params.max_block_weight - coinbase.weight()),
+            MAX_BLOCK_BYTES - Blockchain::coinbase_only_block_size(&coinbase),
+        );
+        let selected = positions
+            .iter()
+            .map(|position| self.transaction_pool[*position].clone())
+            .collect::<Vec<Transaction>>();
+        let selected = Blockchain::canonical_order(&selected, self.hash_algo)
+            .into_iter()
+            .map(|position| selected[position].clone())
+            .collect::<Vec<Transaction>>();
+        let pool_view = UtxoView::with_pool(self);
+        let total_fees = Coins::checked_sum(
+            selected
+                .iter()
+                .map(|transaction| Blockchain::fee_in(transaction, &pool_view)),
+        )
+        .expect("pool fees fit in u64");
+
+        // Pay the reward plus fees to the miner
+        coinbase.outputs[0].value = self
+            .block_reward()
+            .checked_add(total_fees)
+            .expect("coinbase value fits in u64");
+        let mut transactions: Vec<Transaction> = vec![coinbase];
+        transactions.extend(selected);
+        (positions, transactions)
+    }
+
+    /// Positions of `transactions` in the order a block must list them after
+    /// its coinbase: by hash under `algo`, smallest first, except that a
+    /// transaction spending another of them comes after it. Miners with the
+    /// same transactions thus build the same Merkle root.
+    pub fn canonical_order(transactions: &[Transaction], algo: HashAlgo) -> Vec<usize> {
+        let hashes = transactions
+            .iter()
+            .map(|transaction| transaction.hash_with(algo))
+            .collect::<Vec<Hash>>();
+        let positions = hashes
+            .iter()
+            .enumerate()
+            .map(|(position, hash)| (hash, position))
+            .collect::<HashMap<&Hash, usize>>();
+        let mut waiting_on = vec![0; transactions.len()];
+        let mut spenders = vec![vec![]; transactions.len()];
+        for (position, transaction) in transactions.iter().enumerate() {
+            let parents = transaction
+                .outpoints()
+                .iter()
+                .filter_map(|outpoint| positions.get(&outpoint.tx_hash).copied())
+                .collect::<HashSet<usize>>();
+            waiting_on[position] = parents.len();
+            for parent in parents {
+                spenders[parent].push(position);
+            }
+        }
+
+        // Repeatedly take the smallest hash among those with no parent left
+        let mut ready = (0..transactions.len())
+            .filter(|position| waiting_on[*position] == 0)
+            .map(|position| Reverse((&hashes[position], position)))
+            .collect::<BinaryHeap<_>>();
+        let mut order = Vec::with_capacity(transactions.len());
+        while let Some(Reverse((_, position))) = ready.pop() {
+            order.push(position);
+            for &spender in &spenders[position] {
+                waiting_on[spender] -= 1;
+                if waiting_on[spender] == 0 {
+                    ready.push(Reverse((&hashes[spender], spender)));
+                }
+            }
+        }
+        order
+    }
+
+    /// Serialized size of a candidate block holding only `coinbase`, with
+    /// every header field at its widest.
+    fn coinbase_only_block_size(coinbase: &Transaction) -> usize {
+        Block::new(
+            u32::MAX,
+            TimeStamp::MAX,
+            Hash::zero(),
+            vec![coinbase.clone()],
+            U256::max_value(),
+        )
+        .serialized_size()
+    }
+
+    /// Pool positions of the transactions to include in a candidate block, in
+    /// inclusion order. Each pooled transaction is ranked together with its
+    /// pooled ancestors not yet included, by the fee rate of that package, so
+    /// a child paying well can carry a parent that pays little in with it.
+    /// The best package goes in whole, parents first, and selection stops
+    /// once the next one would not fit in `available_weight` or
+    /// `available_bytes`. A package that fails to verify is left out.
+    fn select_candidate_transactions(
+        &self,
+        available_weight: u64,
+        available_bytes: usize,
+    ) -> Vec<usize> {
+        let pool = &self.transaction_pool;
+        let pool_view = UtxoView::with_pool(self);
+        let fees = pool
+            .iter()
+            .map(|transaction| Blockchain::fee_in(transaction, &pool_view))
+            .collect::<Vec<Coins>>();
+        let sizes = pool
+            .iter()
+            .map(Transaction::serialized_size)
+            .collect::<Vec<usize>>();
+        let ancestors = self.pool_ancestors();
+        // Topological rank, so each package lists parents before children
+        let mut rank = vec![0; pool.len()];
+        for (order, position) in Blockchain::canonical_order(pool, self.hash_algo)
+            .into_iter()
+            .enumerate()
+        {
+            rank[position] = order;
+        }
+
+        let mut done = vec![false; pool.len()];
+        let mut view = UtxoView::new(self);
+        let mut used_weight = 0;
+        let mut used_bytes = 0;
+        let mut selected = Vec::new();
+        loop {
+            // Equal rates go to the transaction added first
+            let mut best: Option<(Vec<usize>, f64)> = None;
+            for position in (0..pool.len()).filter(|position| !done[*position]) {
+                let package = ancestors[position]
+                    .iter()
+                    .copied()
+                    .filter(|ancestor| !done[*ancestor])
+                    .chain(iter::once(position))
+                    .collect::<Vec<usize>>();
+                let fee = package
+                    .iter()
+                    .map(|member| fees[*member].units())
+                    .sum::<u64>();
+                let size = package.iter().map(|member| sizes[*member]).sum::<usize>();
+                let fee_rate = fee as f64 / size as f64;
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best_rate)| fee_rate > *best_rate)
+                {
+                    best = Some((package, fee_rate));
+                }
+            }
+            let mut package = match best {
+                Some((package, _)) => package,
+                None => break,
+            };
+            package.sort_unstable_by_key(|member| rank[*member]);
+
+            let weight = used_weight
+                + package
+                    .iter()
+                    .map(|member| pool[*member].weight())
+                    .sum::<u64>();
+            // one extra byte for the separator between transactions
+            let bytes = used_bytes
+                + package
+                    .iter()
+                    .map(|member| sizes[*member] + 1)
+                    .sum::<usize>();
+            if weight > available_weight || bytes > available_bytes {
+                break;
+            }
+            for member in &package {
+                done[*member] = true;
+            }
+            // the selection has to pass as a batch, so skip anything that
+            // conflicts with what is already in it
+            let mut trial = view.clone();
+            let verified = package.iter().all(|member| {
+                let transaction = &pool[*member];
+                let valid = self.verify_transaction_in(transaction, &trial).is_ok();
+                trial.apply(transaction);
+                valid
+            });
+            if !verified {
+                continue;
+            }
+            view = trial;
+            used_weight = weight;
+            used_bytes = bytes;
+            selected.extend(package);
+        }
+        selected
+    }
+
+    /// `positions` and the positions of the pooled transactions spending
+    /// from them, directly or through others, in pool order.
+    fn with_pool_descendants(&self, positions: &[usize]) -> Vec<usize> {
+        let ancestors = self.pool_ancestors();
+        (0..self.transaction_pool.len())
+            .filter(|position| {
+                positions.contains(position)
+                    || ancestors[*position]
+                        .iter()
+                        .any(|ancestor| positions.contains(ancestor))
+            })
+            .collect()
+    }
+
+    /// For each pooled transaction, the positions of the pooled transactions
+    /// it spends from, directly or through others, in no particular order.
+    fn pool_ancestors(&self) -> Vec<Vec<usize>> {
+        let pool = &self.transaction_pool;
+        let positions = pool
+            .iter()
+            .enumerate()
+            .map(|(position, transaction)| (transaction.hash_with(self.hash_algo), position))
+            .collect::<HashMap<Hash, usize>>();
+        let mut ancestors = vec![Vec::new(); pool.len()];
+        // Parents come first in canonical order, so theirs are complete
+        for position in Blockchain::canonical_order(pool, self.hash_algo) {
+            let mut found = HashSet::new();
+            for outpoint in pool[position].outpoints() {
+                if let Some(&parent) = positions.get(&outpoint.tx_hash) {
+                    found.insert(parent);
+                    found.extend(ancestors[parent].iter().copied());
+                }
+            }
+            ancestors[position] = found.into_iter().collect();
+        }
+        ancestors
+    }
+
+    /// Fee rate, in units per byte, likely to get a transaction mined within
+    /// `target_blocks` blocks, from the fee rates paid in the last
+    /// `FEE_HISTORY_BLOCKS` blocks. A target of one block picks the 90th
+    /// percentile of those rates, and every further block lowers it by ten
+    /// points down to the 10th. Never below `MIN_FEE_RATE`, which is also
+    /// the answer while no transaction has been mined.
+    pub fn estimate_fee(&self, target_blocks: u32) -> f64 {
+        let mut rates = self
+            .fee_history
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<f64>>();
+        if rates.is_empty() {
+            return MIN_FEE_RATE;
+        }
+        rates.sort_by(f64::total_cmp);
+        let percentile = 90 - 10 * cmp::min(target_blocks.saturating_sub(1), 8) as usize;
+        let rank = (rates.len() - 1) * percentile / 100;
+        rates[rank].max(MIN_FEE_RATE)
+    }
+
+    /// Reward for the next block, halved every `halving_interval` blocks of
+    /// the chain's params.
+    pub fn block_reward(&self) -> Coins {
+        self.params.block_reward_at(self.blocks.len())
     }
 
+    /// Mainnet reward for a block with `height` blocks before it, see
+    /// `ChainParams::block_reward_at`.
+    pub fn block_reward_at(height: usize) -> Coins {
+        ChainParams::mainnet().block_reward_at(height)
+    }
+
+    /// Target for the next block.
+    ///
+    /// Every `RETARGET_INTERVAL` blocks the tip's target is scaled by how long
+    /// the last `RETARGET_INTERVAL` blocks actually took compared with the
+    /// params' `target_block_time` per block, limited to `MAX_RETARGET_FACTOR` either
+    /// way. The target is an upper bound on the block hash, so a larger value
+    /// means easier mining. Between adjustments, or always if the params turn
+    /// `retarget` off, the tip's target is reused.
+    pub fn next_difficulty(&self) -> U256 {
+        let tip = match self.blocks.last() {
+            Some(tip) => tip,
+            None => return self.params.initial_difficulty,
+        };
+        if !self.params.retarget || !self.blocks.len().is_multiple_of(RETARGET_INTERVAL) {
+            return tip.header.difficulty;
+        }
+        let first = &self.blocks[self.blocks.len() - RETARGET_INTERVAL];
+        let target_span = self.params.target_block_time * (RETARGET_INTERVAL as TimeStamp - 1);
+        let actual_span = tip
+            .header
+            .timestamp
+            .saturating_sub(first.header.timestamp)
+            .clamp(
+                target_span / MAX_RETARGET_FACTOR,
+                target_span * MAX_RETARGET_FACTOR,
+            );
+        let scaled =
+            tip.header.difficulty.full_mul(U256::from(actual_span)) / U512::from(target_span);
+        U256::try_from(scaled).unwrap_or_else(|_| U256::max_value())
+    }
+
+    /// Target of the tip, or the params' `initial_difficulty` for an empty
+    /// chain.
+    pub fn current_difficulty(&self) -> U256 {
+        self.blocks
+            .last()
+            .map_or(self.params.initial_difficulty, |tip| tip.header.difficulty)
+    }
+
+    /// Hashes per second the network spent on the last `RETARGET_INTERVAL`
+    /// blocks: the expected work of every block but the first, divided by
+    /// the seconds from the first block to the tip. Zero until there are two
+    /// blocks with different timestamps.
+    pub fn estimated_hashrate(&self) -> f64 {
+        let recent = &self.blocks[self.blocks.len().saturating_sub(RETARGET_INTERVAL)..];
+        let (first, tip) = match (recent.first(), recent.last()) {
+            (Some(first), Some(tip)) => (first, tip),
+            _ => return 0.0,
+        };
+        let elapsed = tip.header.timestamp.saturating_sub(first.header.timestamp);
+        if elapsed == 0 {
+            return 0.0;
+        }
+        let work = Blockchain::work_of(&recent[1..]);
+        // U256 has no float conversion, so add up its limbs
+        let work = work
+            .0
+            .iter()
+            .rev()
+            .fold(0.0, |total, limb| total * 2f64.powi(64) + *limb as f64);
+        work * 1000.0 / elapsed as f64
+    }
+
+    /// Add a mined block on top of the chain.
+    ///
+    /// A block whose parent is unknown is kept as an orphan, provided its
+    /// proof of work holds, and connected once the parent arrives. After each
+    /// block is connected, orphans building on the new tip are connected too.
+    /// A block already in the chain or among the orphans is refused with
+    /// `DuplicateBlock`.
     pub fn aggregate_mined_block(&mut self, block: Block) -> Result<(), BlockChainError> {
-        if !check_difficulty(&block.hash, block.difficulty) {
-            return Err(BlockChainError::ProofOfWorkError(String::from(
-                "Block is not correctly mined",
-            )));
+        if self.block_positions.contains_key(&block.header.hash)
+            || self.orphans.contains_key(&block.header.hash)
+        {
+            return Err(BlockChainError::DuplicateBlock(block.header.hash));
         }
-        if let Some((coinbase, transactions)) = block.transactions.split_first() {
-            if !coinbase.is_coinbase() {
-                return Err(BlockChainError::NotACoinBaseError(String::from(
-                    "First transaction in block must be a coinbase.",
+        self.verify_checkpoint(&block.header)?;
+        let parent_known = self
+            .block_positions
+            .contains_key(&block.header.prev_block_hash)
+            || (self.blocks.is_empty() && block.header.prev_block_hash == Hash::zero());
+        if !parent_known {
+            return self.add_orphan(block);
+        }
+        self.connect_block(block)?;
+        self.connect_orphans();
+        Ok(())
+    }
+
+    /// Add a run of blocks, the first building on the tip and each on the one
+    /// before it, all or nothing. The blocks are validated and applied one
+    /// after the other against the chain's own unspent output set; if one
+    /// fails, those applied before it are disconnected again and the pool is
+    /// restored. Subscribers hear about the blocks once all are in. Returns
+    /// how many blocks were added.
+    pub fn import_blocks(&mut self, blocks: Vec<Block>) -> Result<usize, BlockChainError> {
+        let mut previous_hash = self.tip_hash().unwrap_or_else(Hash::zero);
+        for block in &blocks {
+            if block.header.prev_block_hash != previous_hash {
+                return Err(BlockChainError::PreviousHashMismatch(format!(
+                    "Block {} does not build on {}.",
+                    block.header.index, previous_hash
                 )));
             }
+            previous_hash = block.header.hash.clone();
+        }
+
+        let kept = self.blocks.len();
+        let subscribers = std::mem::take(&mut self.subscribers);
+        let pool = self.transaction_pool.clone();
+        let fee_history = self.fee_history.clone();
+        let mut result = Ok(());
+        for block in &blocks {
+            let index = block.header.index;
+            result =
+                self.connect_block(block.clone())
+                    .map_err(|e| BlockChainError::InvalidBlockError {
+                        index,
+                        cause: Box::new(e),
+                    });
+            if result.is_err() {
+                break;
+            }
+        }
+        self.subscribers = subscribers;
+        if let Err(e) = result {
+            while self.blocks.len() > kept {
+                self.disconnect_tip();
+            }
+            self.transaction_pool = pool;
+            self.fee_history = fee_history;
+            return Err(e);
+        }
 
-            let mut output_spent = Vec::new();
-            let mut output_created = Vec::new();
-            // Add coinbase output
-            output_created.extend(coinbase.output_hashes());
+        for block in &blocks {
+            self.emit(ChainEvent::BlockAdded(block.header.hash.clone()));
+        }
+        self.connect_orphans();
+        Ok(blocks.len())
+    }
 
-            for transaction in transactions {
-                match self.verify_transaction(transaction) {
-                    Ok(()) => println!("transaction verified"),
-                    Err(e) => return Err(e),
-                }
-                output_spent.extend(transaction.input_hashes());
-                output_created.extend(transaction.output_hashes());
+    fn add_orphan(&mut self, block: Block) -> Result<(), BlockChainError> {
+        Blockchain::verify_block_hash(&block)?;
+        self.consensus.validate_block(&block, self)?;
+        if self.orphans.len() >= MAX_ORPHANS {
+            return Err(BlockChainError::PreviousHashMismatch(String::from(
+                "Parent block is unknown and the orphan pool is full.",
+            )));
+        }
+        self.orphans.insert(block.header.hash.clone(), block);
+        Ok(())
+    }
+
+    /// Connect orphans that build on the tip, one after the other. Orphans
+    /// that turn out to be invalid are dropped.
+    fn connect_orphans(&mut self) {
+        while let Some(tip_hash) = self.blocks.last().map(|tip| tip.header.hash.clone()) {
+            let child_hash = self
+                .orphans
+                .values()
+                .find(|orphan| orphan.header.prev_block_hash == tip_hash)
+                .map(|orphan| orphan.header.hash.clone());
+            let child = match child_hash.and_then(|hash| self.orphans.remove(&hash)) {
+                Some(child) => child,
+                None => break,
+            };
+            let hash = child.header.hash.clone();
+            if let Err(e) = self.connect_block(child) {
+                warn!("Dropped invalid orphan {}: {}", hash, e);
             }
+        }
+    }
 
-            // Update unspent output vector
-            self.unspent_output
-                .retain(|output| !output_spent.contains(output));
-            self.unspent_output.extend(output_created);
-            self.blocks.push(block);
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Drop the transactions of blocks with an index below `height` whose
+    /// outputs are all spent, keeping their headers marked as `pruned`.
+    /// Returns how many blocks were pruned.
+    ///
+    /// New blocks are still validated against the tip and the unspent output
+    /// set. `validate` replays the chain from genesis, though, and refuses
+    /// pruned blocks as their transactions are gone, so it fails once a block
+    /// is pruned, and `save_to_file` refuses to write a chain that could not
+    /// be loaded. `try_reorg` fails if it would have to disconnect a pruned
+    /// block.
+    pub fn prune_below(&mut self, height: u32) -> usize {
+        let mut pruned = 0;
+        let mut pruned_hashes = HashSet::new();
+        for block in self.blocks.iter_mut() {
+            if block.pruned || block.header.index >= height {
+                continue;
+            }
+            let fully_spent = block.transactions.iter().all(|transaction| {
+                transaction
+                    .spendable_outputs(block.header.hash_algo)
+                    .iter()
+                    .all(|(outpoint, _)| !self.unspent_output.contains_key(outpoint))
+            });
+            if !fully_spent {
+                continue;
+            }
+            for transaction in &block.transactions {
+                let tx_hash = transaction.hash_with(block.header.hash_algo);
+                self.spent_by.remove(&tx_hash);
+                self.tx_index.remove(&tx_hash);
+                pruned_hashes.insert(tx_hash);
+            }
+            block.transactions.clear();
+            block.pruned = true;
+            pruned += 1;
         }
+        self.address_history.retain(|_, history| {
+            history.retain(|tx_hash| !pruned_hashes.contains(tx_hash));
+            !history.is_empty()
+        });
+        pruned
+    }
+
+    /// Validate `block` against the tip and append it. Nothing changes
+    /// unless every check passes.
+    fn connect_block(&mut self, block: Block) -> Result<(), BlockChainError> {
+        let fee_rates = self.check_block(&block)?;
+        self.commit_block(block, fee_rates);
         Ok(())
     }
 
-    fn verify_transaction(&self, transaction: &Transaction) -> Result<(), BlockChainError> {
-        // check if transaction is spendable
-        if !transaction.is_spendable() {
-            return Err(BlockChainError::InsufficientFundsError(String::from(
-                "Transaction output is grater than input.",
+    /// Every check `connect_block` makes, against a scratch view of the
+    /// unspent output set so the chain itself is left alone. Returns the fee
+    /// rates of the transactions after the coinbase.
+    fn check_block(&self, block: &Block) -> Result<Vec<f64>, BlockChainError> {
+        self.verify_checkpoint(&block.header)?;
+        let size = block.serialized_size();
+        if size > MAX_BLOCK_BYTES {
+            return Err(BlockChainError::BlockTooLarge {
+                size,
+                max: MAX_BLOCK_BYTES,
+            });
+        }
+        let weight = block.weight();
+        if weight > self.params.max_block_weight {
+            return Err(BlockChainError::BlockTooLarge {
+                size: weight as usize,
+                max: self.params.max_block_weight as usize,
+            });
+        }
+        if !self.blocks.is_empty() && block.header.hash_algo != self.hash_algo {
+            return Err(BlockChainError::HashAlgoMismatch {
+                expected: self.hash_algo,
+                found: block.header.hash_algo,
+            });
+        }
+        if !self.blocks.is_empty() && block.header.chain_id != self.chain_id {
+            return Err(BlockChainError::ChainIdMismatch {
+                expected: self.chain_id,
+                found: block.header.chain_id,
+            });
+        }
+        // The genesis block sets the starting target, like its hash algorithm
+        if !self.blocks.is_empty() && block.header.difficulty != self.next_difficulty() {
+            return Err(BlockChainError::DifficultyMismatch {
+                expected: self.next_difficulty(),
+                found: block.header.difficulty,
+            });
+        }
+        if let Some(transaction) = block
+            .transactions
+            .iter()
+            .find(|transaction| transaction.chain_id != block.header.chain_id)
+        {
+            return Err(BlockChainError::ChainIdMismatch {
+                expected: block.header.chain_id,
+                found: transaction.chain_id,
+            });
+        }
+        Blockchain::verify_block_hash(block)?;
+        self.consensus.validate_block(block, self)?;
+        if Block::compute_merkle_root_with(&block.transactions, block.header.hash_algo)
+            != block.header.merkle_root
+        {
+            return Err(BlockChainError::MerkleRootMismatch(String::from(
+                "Merkle root does not match block transactions.",
             )));
         }
-        // check inputs are valid (unspent output in block)
-        let input_hashes = transaction.input_hashes();
-        for hash in input_hashes {
-            if !self.unspent_output.contains(&hash) {
-                return Err(BlockChainError::InputNotSpendableError(String::from(
-                    "Input is not spendable.",
+        self.verify_block_link(block)?;
+        self.verify_block_timestamp(block)?;
+        // Only pruning leaves a block without its coinbase
+        if block.transactions.is_empty() && !block.pruned {
+            return Err(BlockChainError::InvalidCoinbaseStructure(String::from(
+                "Block must hold a coinbase.",
+            )));
+        }
+        let mut fee_rates = Vec::new();
+        if let Some((coinbase, transactions)) = block.transactions.split_first() {
+            if !coinbase.is_coinbase() {
+                return Err(BlockChainError::InvalidCoinbaseStructure(String::from(
+                    "First transaction in block must be a coinbase without inputs.",
                 )));
             }
-            let tx_pool_hashes = self
-                .transaction_pool
+            if transactions.iter().any(Transaction::is_coinbase) {
+                return Err(BlockChainError::InvalidCoinbaseStructure(String::from(
+                    "Only the first transaction in a block may be a coinbase.",
+                )));
+            }
+            if !coinbase.has_valid_data_outputs() {
+                return Err(BlockChainError::InvalidCoinbaseStructure(format!(
+                    "Coinbase data outputs must hold no value and at most {} bytes.",
+                    MAX_DATA_BYTES
+                )));
+            }
+            let order = Blockchain::canonical_order(transactions, block.header.hash_algo);
+            if let Some(position) = (0..order.len()).find(|position| order[*position] != *position)
+            {
+                return Err(BlockChainError::InvalidTransactionOrder(
+                    transactions[position].hash_with(block.header.hash_algo),
+                ));
+            }
+
+            // Two transactions with one hash would share their outpoints, and
+            // the later one would overwrite the coins of the earlier
+            if let Some((outpoint, _)) = block
+                .transactions
                 .iter()
-                .flat_map(|transaction| transaction.input_hashes())
-                .collect::<HashSet<Hash>>();
-            if tx_pool_hashes.contains(&hash) {
-                return Err(BlockChainError::DoubleSpendingError(String::from(
-                    "Double spending attempt.",
+                .flat_map(|transaction| transaction.spendable_outputs(block.header.hash_algo))
+                .find(|(outpoint, _)| self.unspent_output.contains_key(outpoint))
+            {
+                return Err(BlockChainError::DuplicateOutput(outpoint));
+            }
+
+            // Transactions are checked one after the other, so one may spend
+            // what an earlier one in the block created
+            let spent = self.verify_batch(transactions, UtxoView::for_block(self, block))?;
+            let mut total_fees = Coins::ZERO;
+            for (transaction, spent) in transactions.iter().zip(&spent) {
+                total_fees = total_fees
+                    .checked_add(transaction.fee(spent))
+                    .ok_or_else(|| {
+                        BlockChainError::InvalidCoinbaseValue(String::from("Block fees overflow."))
+                    })?;
+                fee_rates.push(transaction.fee_rate(spent));
+            }
+
+            // Coinbase may claim at most the block reward plus fees
+            let coinbase_value = Coins::checked_sum(coinbase.outputs.iter().map(|o| o.value));
+            let allowed_value = self.block_reward().checked_add(total_fees);
+            if coinbase_value.is_none() || coinbase_value > allowed_value {
+                return Err(BlockChainError::InvalidCoinbaseValue(String::from(
+                    "Coinbase pays more than the block reward plus fees.",
                 )));
             }
         }
-        return Ok(());
+        Ok(fee_rates)
     }
 
-    pub fn len(&self) -> usize {
-        self.blocks.len()
+    /// Append a block `check_block` accepted, updating the unspent output set,
+    /// pool and indices.
+    fn commit_block(&mut self, block: Block, fee_rates: Vec<f64>) {
+        if self.blocks.is_empty() {
+            // The first block names the hash algorithm and chain id, which
+            // its own transactions were already checked with
+            self.hash_algo = block.header.hash_algo;
+            self.chain_id = block.header.chain_id;
+        }
+        if let Some((coinbase, transactions)) = block.transactions.split_first() {
+            for transaction in transactions {
+                self.apply_verified_transaction(transaction);
+            }
+            // The coinbase outputs are added last as they may not be spent in
+            // their own block.
+            for (outpoint, _) in coinbase.spendable_outputs(self.hash_algo) {
+                self.coinbase_heights.insert(outpoint, block.header.index);
+            }
+            self.apply_verified_transaction(coinbase);
+            // Drop pooled transactions this block confirmed or conflicts with
+            let output_spent = transactions
+                .iter()
+                .flat_map(Transaction::outpoints)
+                .collect::<HashSet<OutPoint>>();
+            self.transaction_pool.retain(|pooled| {
+                !pooled
+                    .outpoints()
+                    .iter()
+                    .any(|outpoint| output_spent.contains(outpoint))
+            });
+        }
+        self.block_positions
+            .insert(block.header.hash.clone(), self.blocks.len());
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            let tx_hash = transaction.hash_with(block.header.hash_algo);
+            for address in self.addresses_touched(transaction) {
+                self.address_history
+                    .entry(address)
+                    .or_default()
+                    .push(tx_hash.clone());
+            }
+            self.tx_index
+                .insert(tx_hash, (block.header.index, position));
+        }
+        if self.fee_history.len() == FEE_HISTORY_BLOCKS {
+            self.fee_history.pop_front();
+        }
+        self.fee_history.push_back(fee_rates);
+        let hash = block.header.hash.clone();
+        self.blocks.push(block);
+        self.emit(ChainEvent::BlockAdded(hash));
     }
-}
 
-//Testing
+    /// Spend the inputs of `transaction` and add its spendable outputs to the
+    /// unspent output set, after verifying it against that set. Coinbases are
+    /// applied without checks, as their rules depend on the block holding
+    /// them. `revert_transaction` undoes this.
+    pub fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), BlockChainError> {
+        if !transaction.is_coinbase() {
+            self.verify_transaction(transaction)?;
+        }
+        self.apply_verified_transaction(transaction);
+        Ok(())
+    }
 
-#[cfg(test)]
-mod tests {
-    use primitive_types::U256;
+    /// `apply_transaction` for a transaction already verified against the
+    /// unspent output set.
+    fn apply_verified_transaction(&mut self, transaction: &Transaction) {
+        if !transaction.is_coinbase() {
+            let spent = transaction
+                .outpoints()
+                .iter()
+                .map(|outpoint| {
+                    self.unspent_output
+                        .remove(outpoint)
+                        .expect("verified inputs are unspent")
+                })
+                .collect();
+            self.spent_by
+                .insert(transaction.hash_with(self.hash_algo), spent);
+        }
+        self.unspent_output
+            .extend(transaction.spendable_outputs(self.hash_algo));
+    }
 
-    use crate::{now, Blockchain, Hashable, Transaction, TxOutput};
+    /// Undo `apply_transaction`: drop the outputs of `transaction` from the
+    /// unspent output set and make the outputs its inputs spent unspent
+    /// again. Transactions applied later must be reverted first.
+    pub fn revert_transaction(&mut self, transaction: &Transaction) {
+        for (outpoint, _) in transaction.spendable_outputs(self.hash_algo) {
+            self.unspent_output.remove(&outpoint);
+        }
+        if let Some(spent) = self.spent_by.remove(&transaction.hash_with(self.hash_algo)) {
+            self.unspent_output
+                .extend(transaction.outpoints().into_iter().zip(spent));
+        }
+    }
 
-    #[test]
-    fn add_transaction_to_pool() {
-        // Given
-        let mut blockchain: Blockchain = Blockchain::new();
-        let unspent_outputs = vec![
-            TxOutput {
-                address: String::from("Alice"),
-                value: 10.0,
+    /// The outputs `transaction` spends, in input order, looked up in the
+    /// unspent output set.
+    pub fn resolve_inputs(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Vec<TxOutput>, BlockChainError> {
+        UtxoView::new(self).resolve_inputs(transaction)
+    }
+
+    /// Fee `transaction` pays given the unspent output set and the outputs
+    /// of pooled transactions, or zero if it spends outputs in neither.
+    pub fn fee_of(&self, transaction: &Transaction) -> Coins {
+        Blockchain::fee_in(transaction, &UtxoView::with_pool(self))
+    }
+
+    /// Fee rate of `transaction` in units per byte, see `fee_of`.
+    pub fn fee_rate_of(&self, transaction: &Transaction) -> f64 {
+        Blockchain::fee_rate_in(transaction, &UtxoView::with_pool(self))
+    }
+
+    fn fee_in(transaction: &Transaction, view: &UtxoView) -> Coins {
+        view.resolve_inputs(transaction)
+            .map_or(Coins::ZERO, |spent| transaction.fee(&spent))
+    }
+
+    fn fee_rate_in(transaction: &Transaction, view: &UtxoView) -> f64 {
+        view.resolve_inputs(transaction)
+            .map_or(0.0, |spent| transaction.fee_rate(&spent))
+    }
+
+    /// Revert `transactions`, last one first.
+    fn revert_transactions(&mut self, transactions: &[Transaction]) {
+        for transaction in transactions.iter().rev() {
+            self.revert_transaction(transaction);
+        }
+    }
+
+    /// Remove the tip and revert its transactions. The tip must not be
+    /// pruned, as the outputs it spent are gone.
+    fn disconnect_tip(&mut self) -> Option<Block> {
+        let block = self.blocks.pop()?;
+        // The tip's transactions are the latest of every address they touch
+        for transaction in &block.transactions {
+            for address in self.addresses_touched(transaction) {
+                if let Some(history) = self.address_history.get_mut(&address) {
+                    history.pop();
+                    if history.is_empty() {
+                        self.address_history.remove(&address);
+                    }
+                }
+            }
+        }
+        self.revert_transactions(&block.transactions);
+        if let Some(coinbase) = block.transactions.first() {
+            for (outpoint, _) in coinbase.spendable_outputs(self.hash_algo) {
+                self.coinbase_heights.remove(&outpoint);
+            }
+        }
+        for transaction in &block.transactions {
+            self.tx_index
+                .remove(&transaction.hash_with(block.header.hash_algo));
+        }
+        self.block_positions.remove(&block.header.hash);
+        self.fee_history.pop_back();
+        // The block that dropped out of the fee history is back in it
+        if self.blocks.len() > self.fee_history.len() {
+            let returning = &self.blocks[self.blocks.len() - self.fee_history.len() - 1];
+            let fee_rates = returning.transactions[cmp::min(1, returning.transactions.len())..]
+                .iter()
+                .map(|transaction| {
+                    let spent = &self.spent_by[&transaction.hash_with(self.hash_algo)];
+                    transaction.fee_rate(spent)
+                })
+                .collect();
+            self.fee_history.push_front(fee_rates);
+        }
+        if self.blocks.is_empty() {
+            self.hash_algo = HashAlgo::default();
+            self.chain_id = 0;
+        }
+        Some(block)
+    }
+
+    /// Addresses an applied transaction pays to or spends from, each once.
+    /// Data outputs are left out.
+    fn addresses_touched(&self, transaction: &Transaction) -> Vec<Address> {
+        let spent = self.spent_by.get(&transaction.hash_with(self.hash_algo));
+        let mut addresses = Vec::new();
+        for output in spent
+            .into_iter()
+            .flatten()
+            .chain(&transaction.outputs)
+            .filter(|output| !output.is_unspendable())
+        {
+            if !addresses.contains(&output.address) {
+                addresses.push(output.address.clone());
+            }
+        }
+        addresses
+    }
+
+    /// Check that the hash stored in `block` is its header's, so it can't
+    /// claim work it did not do or an identity that isn't its own.
+    fn verify_block_hash(block: &Block) -> Result<(), BlockChainError> {
+        let expected = block.compute_hash();
+        if expected != block.header.hash {
+            return Err(BlockChainError::HashMismatch {
+                expected,
+                found: block.header.hash.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that `block` extends the tip: its index follows the tip's and its
+    /// previous hash is the tip's hash. The first block of an empty chain may
+    /// have index 0 or 1 and must have an all-zero previous hash.
+    fn verify_block_link(&self, block: &Block) -> Result<(), BlockChainError> {
+        let (index_valid, expected_previous_hash) = match self.blocks.last() {
+            Some(tip) => (
+                Some(block.header.index) == tip.header.index.checked_add(1),
+                tip.header.hash.clone(),
+            ),
+            None => (block.header.index <= 1, Hash::zero()),
+        };
+        if !index_valid {
+            return Err(BlockChainError::InvalidBlockIndex(format!(
+                "Block index {} does not follow the chain tip.",
+                block.header.index
+            )));
+        }
+        if block.header.prev_block_hash != expected_previous_hash {
+            return Err(BlockChainError::PreviousHashMismatch(String::from(
+                "Previous hash does not match the chain tip.",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Median timestamp of the last `MEDIAN_TIME_SPAN` blocks, or zero for an
+    /// empty chain.
+    pub fn median_time_past(&self) -> TimeStamp {
+        let recent = &self.blocks[self.blocks.len().saturating_sub(MEDIAN_TIME_SPAN)..];
+        let mut timestamps = recent
+            .iter()
+            .map(|block| block.header.timestamp)
+            .collect::<Vec<TimeStamp>>();
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+    }
+
+    /// Reject a header at a checkpointed height unless it has the
+    /// checkpoint's hash.
+    fn verify_checkpoint(&self, header: &BlockHeader) -> Result<(), BlockChainError> {
+        match self.params.checkpoint_at(header.index) {
+            Some(expected) if *expected != header.hash => {
+                Err(BlockChainError::CheckpointMismatch {
+                    height: header.index,
+                    expected: expected.clone(),
+                    found: header.hash.clone(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reject timestamps that do not move past the median time past, or that
+    /// are more than `MAX_FUTURE_BLOCK_TIME` ahead of the local clock.
+    fn verify_block_timestamp(&self, block: &Block) -> Result<(), BlockChainError> {
+        if !self.blocks.is_empty() && block.header.timestamp <= self.median_time_past() {
+            return Err(BlockChainError::InvalidTimestamp(String::from(
+                "Block timestamp is not after the median time past.",
+            )));
+        }
+        if block.header.timestamp > now() + MAX_FUTURE_BLOCK_TIME {
+            return Err(BlockChainError::InvalidTimestamp(String::from(
+                "Block timestamp is too far in the future.",
+            )));
+        }
+        Ok(())
+    }
+
+    fn verify_transaction(&self, transaction: &Transaction) -> Result<(), BlockChainError> {
+        self.verify_transaction_in(transaction, &UtxoView::new(self))
+            .map(|_| ())
+    }
+
+    /// Verify `transactions` in order against a single view of the unspent
+    /// output set, as if each were applied before the next is checked. A
+    /// transaction may spend outputs created earlier in the batch, but no
+    /// output may be spent twice across the batch.
+    pub fn verify_transactions(&self, transactions: &[Transaction]) -> Result<(), BlockChainError> {
+        self.verify_batch(transactions, UtxoView::new(self))
+            .map(|_| ())
+    }
+
+    /// `verify_transactions` starting from `view`, returning the outputs each
+    /// transaction spends.
+    fn verify_batch(
+        &self,
+        transactions: &[Transaction],
+        mut view: UtxoView,
+    ) -> Result<Vec<Vec<TxOutput>>, BlockChainError> {
+        transactions
+            .iter()
+            .map(|transaction| {
+                let spent = self.verify_transaction_in(transaction, &view)?;
+                view.apply(transaction);
+                Ok(spent)
+            })
+            .collect()
+    }
+
+    /// Verify `transaction` against the unspent outputs in `view`, returning
+    /// the outputs it spends.
+    fn verify_transaction_in(
+        &self,
+        transaction: &Transaction,
+        view: &UtxoView,
+    ) -> Result<Vec<TxOutput>, BlockChainError> {
+        // check what doesn't depend on the chain first
+        transaction.verify_structure()?;
+        // check the transaction was made for this chain
+        if transaction.chain_id != view.chain_id {
+            return Err(BlockChainError::ChainIdMismatch {
+                expected: view.chain_id,
+                found: transaction.chain_id,
+            });
+        }
+        // check the lock time has passed for the next block
+        let (height, time) = match self.blocks.last() {
+            Some(tip) => (tip.header.index + 1, (tip.header.timestamp / 1000) as u64),
+            None => (0, 0),
+        };
+        if !transaction.is_final(height, time) {
+            return Err(BlockChainError::TransactionLocked {
+                lock_time: transaction.lock_time,
+            });
+        }
+        // check inputs spend unspent, mature outputs, whose values are what
+        // the transaction has to spend
+        let spent = view.resolve_inputs(transaction)?;
+        for outpoint in transaction.outpoints() {
+            if !self.is_mature(&outpoint) {
+                return Err(BlockChainError::ImmatureCoinbase {
+                    created_at: self.coinbase_heights[&outpoint],
+                });
+            }
+        }
+        // check if transaction is spendable
+        if !transaction.is_spendable(&spent) {
+            let total = |outputs: &[TxOutput]| {
+                outputs.iter().fold(Coins::ZERO, |total, output| {
+                    total.saturating_add(output.value)
+                })
+            };
+            return Err(BlockChainError::InsufficientFundsError {
+                available: total(&spent),
+                required: total(&transaction.outputs).saturating_add(Coins(1)),
+            });
+        }
+        // check every input is signed by the owner of the output it spends
+        if !transaction.has_valid_signatures(&spent) {
+            return Err(BlockChainError::InvalidSignatureError(String::from(
+                "Transaction input is not signed by its owner.",
+            )));
+        }
+        Ok(spent)
+    }
+
+    /// Whether the output at `outpoint` may be spent in the next block. Only
+    /// coinbase outputs younger than the params' `coinbase_maturity` blocks
+    /// may not.
+    pub fn is_mature(&self, outpoint: &OutPoint) -> bool {
+        match (self.coinbase_heights.get(outpoint), self.blocks.last()) {
+            (Some(created_at), Some(tip)) => {
+                tip.header.index - created_at + 1 >= self.params.coinbase_maturity
+            }
+            _ => true,
+        }
+    }
+
+    /// Verify `transaction` for the pool: against the unspent output set
+    /// and the outputs of pooled transactions, which it may spend, and
+    /// without spending what a pooled transaction already spends.
+    fn verify_for_pool(&self, transaction: &Transaction) -> Result<(), BlockChainError> {
+        self.verify_transaction_in(transaction, &UtxoView::with_pool(self))?;
+        self.verify_against_pool(transaction)
+    }
+
+    /// Verify `transaction` for the pool like `verify_for_pool`, except that
+    /// with the params' `replace_by_fee` it may spend what pooled
+    /// transactions spend if its fee rate is strictly higher than each of
+    /// theirs. Returns the pool positions, in increasing order, of the
+    /// transactions it replaces: those it conflicts with and the pooled
+    /// transactions spending from them, which it is verified without.
+    fn verify_replacement(&self, transaction: &Transaction) -> Result<Vec<usize>, BlockChainError> {
+        let outpoints = transaction.outpoints();
+        let conflicts = self
+            .transaction_pool
+            .iter()
+            .enumerate()
+            .filter(|(_, pooled)| {
+                pooled
+                    .outpoints()
+                    .iter()
+                    .any(|outpoint| outpoints.contains(outpoint))
+            })
+            .map(|(position, _)| position)
+            .collect::<Vec<usize>>();
+        let replaced = if conflicts.is_empty() {
+            conflicts.clone()
+        } else {
+            self.with_pool_descendants(&conflicts)
+        };
+        let view = UtxoView::with_pool_except(self, &replaced);
+        self.verify_transaction_in(transaction, &view)?;
+        if conflicts.is_empty() {
+            return Ok(replaced);
+        }
+
+        if !self.params.replace_by_fee {
+            return Err(BlockChainError::DoubleSpendingError(String::from(
+                "Double spending attempt.",
+            )));
+        }
+        let pool_view = UtxoView::with_pool(self);
+        let fee_rate = Blockchain::fee_rate_in(transaction, &view);
+        if conflicts.iter().any(|position| {
+            fee_rate <= Blockchain::fee_rate_in(&self.transaction_pool[*position], &pool_view)
+        }) {
+            return Err(BlockChainError::DoubleSpendingError(String::from(
+                "Replacement does not pay a higher fee rate than the transactions it conflicts with.",
+            )));
+        }
+        Ok(replaced)
+    }
+
+    /// Reject a transaction spending an output that a pooled transaction
+    /// already spends.
+    fn verify_against_pool(&self, transaction: &Transaction) -> Result<(), BlockChainError> {
+        let tx_pool_hashes = self
+            .transaction_pool
+            .iter()
+            .flat_map(|transaction| transaction.outpoints())
+            .collect::<HashSet<OutPoint>>();
+        if transaction
+            .outpoints()
+            .iter()
+            .any(|outpoint| tx_pool_hashes.contains(outpoint))
+        {
+            return Err(BlockChainError::DoubleSpendingError(String::from(
+                "Double spending attempt.",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Look up a block by its `index` field.
+    ///
+    /// Block indices are not vector positions: a chain started from
+    /// `create_candidate_block` on an empty chain begins at index 1, while a
+    /// hand-built genesis block starts at index 0. The position is therefore
+    /// derived from the index of the first block in the chain.
+    pub fn get_block_by_index(&self, index: u32) -> Option<&Block> {
+        let first_index = self.blocks.first()?.header.index;
+        let position = index.checked_sub(first_index)? as usize;
+        self.blocks
+            .get(position)
+            .filter(|block| block.header.index == index)
+    }
+
+    pub fn get_block_by_hash(&self, hash: &Hash) -> Option<&Block> {
+        self.block_positions
+            .get(hash)
+            .and_then(|position| self.blocks.get(*position))
+    }
+
+    /// The outputs the block with hash `block_hash` removed from the unspent
+    /// output set, in the order its inputs spent them: what rolling it back
+    /// puts back. Kept by the node alongside the block, outside anything
+    /// the block hash commits to. `None` for an unknown or pruned block.
+    pub fn undo_data(&self, block_hash: &Hash) -> Option<Vec<(OutPoint, TxOutput)>> {
+        let block = self
+            .get_block_by_hash(block_hash)
+            .filter(|block| !block.pruned)?;
+        let mut undo = Vec::new();
+        for transaction in block.transactions.iter().skip(1) {
+            let spent = self
+                .spent_by
+                .get(&transaction.hash_with(block.header.hash_algo))?;
+            undo.extend(
+                transaction
+                    .outpoints()
+                    .into_iter()
+                    .zip(spent.iter().cloned()),
+            );
+        }
+        Some(undo)
+    }
+
+    /// Number of blocks from the one with hash `block_hash` up to the tip,
+    /// counting both: the tip itself has one confirmation.
+    pub fn block_confirmations(&self, block_hash: &Hash) -> Option<u32> {
+        let block = self.get_block_by_hash(block_hash)?;
+        let tip = self.blocks.last()?;
+        Some(tip.header.index - block.header.index + 1)
+    }
+
+    /// Confirmations of the block containing the transaction `tx_hash`.
+    /// A transaction waiting in the pool has `Some(0)`; one that is neither
+    /// pooled nor in a block has `None`.
+    pub fn confirmations_of(&self, tx_hash: &Hash) -> Option<u32> {
+        match self.tx_index.get(tx_hash) {
+            Some((block_index, _)) => {
+                let block = self.get_block_by_index(*block_index)?;
+                self.block_confirmations(&block.header.hash)
+            }
+            None => self
+                .transaction_pool
+                .iter()
+                .any(|transaction| &transaction.hash_with(self.hash_algo) == tx_hash)
+                .then_some(0),
+        }
+    }
+
+    /// Look up a transaction included in a block by its hash.
+    pub fn get_transaction(&self, tx_hash: &Hash) -> Option<&Transaction> {
+        let (block_index, position) = self.tx_index.get(tx_hash)?;
+        self.get_block_by_index(*block_index)?
+            .transactions
+            .get(*position)
+    }
+
+    pub fn latest_block(&self) -> Option<&Block> {
+        self.blocks.last()
+    }
+
+    /// Hash of the latest block, or `None` on an empty chain.
+    pub fn tip_hash(&self) -> Option<Hash> {
+        self.blocks.last().map(|block| block.header.hash.clone())
+    }
+
+    /// Hashes of the tip and of the blocks 1, 2, 4, 8, … below it, always
+    /// ending with the first block. Sparser than `block_locator`, for
+    /// finding a common ancestor in few round trips.
+    pub fn locator(&self) -> Vec<Hash> {
+        let mut locator = vec![];
+        let tip = match self.blocks.len().checked_sub(1) {
+            Some(tip) => tip,
+            None => return locator,
+        };
+        let mut offset = 0;
+        loop {
+            locator.push(self.blocks[tip - offset].header.hash.clone());
+            if offset == tip {
+                return locator;
+            }
+            offset = (offset * 2).max(1).min(tip);
+        }
+    }
+
+    /// Hashes walking back from the tip: the ten latest blocks one by one,
+    /// then doubling the step each time, always ending with the first block.
+    /// A peer can find the latest block both chains share from it.
+    pub fn block_locator(&self) -> Vec<Hash> {
+        let mut locator = vec![];
+        let mut position = match self.blocks.len().checked_sub(1) {
+            Some(position) => position,
+            None => return locator,
+        };
+        let mut step = 1;
+        loop {
+            locator.push(self.blocks[position].header.hash.clone());
+            if position == 0 {
+                return locator;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            position = position.saturating_sub(step);
+        }
+    }
+
+    /// The first hash of `other_locator` naming a block of this chain. For a
+    /// locator listing another chain's blocks tip first, that is the latest
+    /// block both chains share. `None` if not even the first block matches,
+    /// so the chains have no history in common.
+    pub fn find_common_ancestor(&self, other_locator: &[Hash]) -> Option<Hash> {
+        other_locator
+            .iter()
+            .find(|hash| self.block_positions.contains_key(*hash))
+            .cloned()
+    }
+
+    /// Index of the latest block, or `None` on an empty chain.
+    pub fn tip_height(&self) -> Option<u32> {
+        self.blocks.last().map(|block| block.header.index)
+    }
+
+    /// Blocks in chain order, from genesis to tip.
+    pub fn iter(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter()
+    }
+
+    /// Write the chain to `path` as JSON.
+    ///
+    /// Fails with `ErrorKind::InvalidInput`, writing nothing, once
+    /// `prune_below` dropped the transactions of a block, as
+    /// `load_from_file` replays every block.
+    pub fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        if let Some(block) = self.blocks.iter().find(|block| block.pruned) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("block {} is pruned", block.header.index),
+            ));
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(
+            writer,
+            &ChainFileRef {
+                params: &self.params,
+                blocks: &self.blocks,
+            },
+        )
+        .map_err(io::Error::from)
+    }
+
+    /// Load a chain previously written by `save_to_file`.
+    ///
+    /// Every block is re-validated under `ProofOfWork` and the params saved
+    /// with the chain and replayed, so the unspent output set is derived from
+    /// the blocks rather than read from the file.
+    pub fn load_from_file(path: &Path) -> io::Result<Blockchain> {
+        Blockchain::load_from_file_with(path, Box::new(ProofOfWork))
+    }
+
+    /// `load_from_file` for a chain secured by `consensus`, which is not
+    /// saved with it.
+    pub fn load_from_file_with(
+        path: &Path,
+        consensus: Box<dyn Consensus>,
+    ) -> io::Result<Blockchain> {
+        let reader = BufReader::new(File::open(path)?);
+        let chain_file: ChainFile = serde_json::from_reader(reader).map_err(io::Error::from)?;
+
+        Blockchain::replay(Arc::from(consensus), chain_file.params, chain_file.blocks).map_err(
+            |e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid chain file: {:?}", e),
+                )
             },
+        )
+    }
+
+    /// Build a chain from scratch by connecting `blocks` in order, each on
+    /// top of the one before. A block that does not connect is never kept
+    /// as an orphan: the first failure is reported as `InvalidBlockError`
+    /// carrying the block index.
+    fn replay<I: IntoIterator<Item = Block>>(
+        consensus: Arc<dyn Consensus>,
+        params: ChainParams,
+        blocks: I,
+    ) -> Result<Blockchain, BlockChainError> {
+        let mut blockchain = Blockchain::with_params(params);
+        blockchain.consensus = consensus;
+        let mut count = 0;
+        for block in blocks {
+            let index = block.header.index;
+            if block.pruned {
+                return Err(BlockChainError::InvalidBlockError {
+                    index,
+                    cause: Box::new(BlockChainError::InvalidBlockIndex(format!(
+                        "Block {} is pruned and cannot be replayed.",
+                        index
+                    ))),
+                });
+            }
+            blockchain
+                .connect_block(block)
+                .map_err(|e| BlockChainError::InvalidBlockError {
+                    index,
+                    cause: Box::new(e),
+                })?;
+            count += 1;
+        }
+        if blockchain.len() != count {
+            return Err(BlockChainError::InvalidBlockIndex(format!(
+                "Replayed {} blocks into a chain of {}.",
+                count,
+                blockchain.len()
+            )));
+        }
+        Ok(blockchain)
+    }
+
+    /// Amount of work a block with target `difficulty` represents: the
+    /// expected number of hashes needed to find it.
+    pub fn block_work(difficulty: U256) -> U256 {
+        if difficulty == U256::max_value() {
+            return U256::one();
+        }
+        (!difficulty / (difficulty + 1)) + 1
+    }
+
+    /// Total work of all blocks in the chain.
+    pub fn cumulative_work(&self) -> U256 {
+        Blockchain::work_of(&self.blocks)
+    }
+
+    fn work_of(blocks: &[Block]) -> U256 {
+        blocks.iter().fold(U256::zero(), |work, block| {
+            work.saturating_add(Blockchain::block_work(block.header.difficulty))
+        })
+    }
+
+    /// Undo the tip: its created outputs leave the unspent output set, the
+    /// ones it spent, as `undo_data` lists them, return, and every index
+    /// forgets it. Returns the removed
+    /// block; its transactions are not put back in the pool. The first block
+    /// of the chain, and a pruned tip whose spent outputs are gone, can't be
+    /// rolled back.
+    pub fn rollback_block(&mut self) -> Result<Block, BlockChainError> {
+        match self.blocks.last() {
+            None => {
+                return Err(BlockChainError::InvalidBlockIndex(String::from(
+                    "There is no block to roll back.",
+                )))
+            }
+            Some(_) if self.blocks.len() == 1 => {
+                return Err(BlockChainError::InvalidBlockIndex(String::from(
+                    "The genesis block cannot be rolled back.",
+                )))
+            }
+            Some(tip) if tip.pruned => {
+                return Err(BlockChainError::InvalidBlockIndex(format!(
+                    "Block {} is pruned and cannot be disconnected.",
+                    tip.header.index
+                )))
+            }
+            Some(_) => {}
+        }
+        Ok(self.disconnect_tip().expect("the chain has a tip"))
+    }
+
+    /// Switch to `competing` if it represents more work than the current
+    /// chain from the fork point on.
+    ///
+    /// `competing` must be a contiguous branch whose first block builds on a
+    /// block of this chain (or, with an all-zero previous hash, replaces the
+    /// whole chain). When it wins, the blocks above the fork point are
+    /// disconnected, reverting their transactions, and the new branch is
+    /// connected block by block. If a new block is invalid, the abandoned
+    /// blocks are connected again and the chain is left as it was. After a
+    /// switch, transactions of the abandoned blocks that the new branch
+    /// didn't confirm return to the pool. Returns whether the switch happened.
+    pub fn try_reorg(&mut self, competing: &[Block]) -> Result<bool, BlockChainError> {
+        let first = match competing.first() {
+            Some(first) => first,
+            None => return Ok(false),
+        };
+        let kept = match self.block_positions.get(&first.header.prev_block_hash) {
+            Some(position) => position + 1,
+            None if first.header.prev_block_hash == Hash::zero() => 0,
+            None => {
+                return Err(BlockChainError::PreviousHashMismatch(String::from(
+                    "Competing branch does not build on this chain.",
+                )))
+            }
+        };
+        for block in competing {
+            self.verify_checkpoint(&block.header)?;
+        }
+        if Blockchain::work_of(competing) <= Blockchain::work_of(&self.blocks[kept..]) {
+            return Ok(false);
+        }
+        if let Some(pruned) = self.blocks[kept..].iter().find(|block| block.pruned) {
+            return Err(BlockChainError::InvalidBlockIndex(format!(
+                "Block {} is pruned and cannot be disconnected.",
+                pruned.header.index
+            )));
+        }
+        // The competing blocks passed their checkpoints, so this branch is
+        // too short to put any block at the checkpointed height
+        if let Some((checkpointed, checkpoint)) = self.blocks[kept..].iter().find_map(|block| {
+            self.params
+                .checkpoint_at(block.header.index)
+                .map(|checkpoint| (block, checkpoint))
+        }) {
+            return Err(BlockChainError::CheckpointMismatch {
+                height: checkpointed.header.index,
+                expected: checkpoint.clone(),
+                found: Hash::zero(),
+            });
+        }
+
+        // Subscribers hear about the switch once it is complete, and pooled
+        // transactions are checked against the new branch afterwards.
+        let subscribers = std::mem::take(&mut self.subscribers);
+        let pool = std::mem::take(&mut self.transaction_pool);
+        let mut abandoned = vec![];
+        while self.blocks.len() > kept {
+            abandoned.extend(self.disconnect_tip());
+        }
+        let mut result = Ok(());
+        for block in competing {
+            let index = block.header.index;
+            result =
+                self.connect_block(block.clone())
+                    .map_err(|e| BlockChainError::InvalidBlockError {
+                        index,
+                        cause: Box::new(e),
+                    });
+            if result.is_err() {
+                break;
+            }
+        }
+        if let Err(e) = result {
+            while self.blocks.len() > kept {
+                self.disconnect_tip();
+            }
+            for block in abandoned.into_iter().rev() {
+                self.connect_block(block)
+                    .expect("abandoned blocks were valid on this chain");
+            }
+            self.subscribers = subscribers;
+            self.transaction_pool = pool;
+            return Err(e);
+        }
+
+        let depth = abandoned.len() as u32;
+        self.subscribers = subscribers;
+        // Transactions of the abandoned blocks go back to the pool ahead of
+        // the pooled ones, oldest first, as long as they are still valid on
+        // the new branch and don't conflict with what is readmitted already.
+        // Those the new branch confirmed fail as their inputs are spent.
+        let readmitted = abandoned
+            .iter()
+            .rev()
+            .flat_map(|block| block.transactions.iter().skip(1).cloned())
+            .chain(pool)
+            .collect::<Vec<Transaction>>();
+        for transaction in readmitted {
+            if self.transaction_pool.len() < self.max_pool_size
+                && self.verify_for_pool(&transaction).is_ok()
+            {
+                self.transaction_pool.push(transaction);
+            }
+        }
+        self.emit(ChainEvent::Reorg { depth });
+        for block in competing {
+            self.emit(ChainEvent::BlockAdded(block.header.hash.clone()));
+        }
+        Ok(true)
+    }
+
+    /// Check the whole chain end to end: proof of work, linkage, coinbase
+    /// rules, Merkle roots and every transaction against an unspent output
+    /// set rebuilt from genesis. `self.unspent_output` is not consulted.
+    pub fn validate(&self) -> Result<(), BlockChainError> {
+        Blockchain::replay(
+            Arc::clone(&self.consensus),
+            self.params.clone(),
+            self.blocks.iter().cloned(),
+        )
+        .map(|_| ())
+    }
+
+    /// Total value of the unspent outputs owned by `address`.
+    pub fn balance_of(&self, address: &Address) -> Coins {
+        self.unspent_output
+            .values()
+            .filter(|output| &output.address == address)
+            .fold(Coins::ZERO, |balance, output| {
+                balance.saturating_add(output.value)
+            })
+    }
+
+    /// Transactions paying to or spending from `address`, in chain order.
+    /// Those of pruned blocks are left out.
+    pub fn history_of(&self, address: &Address) -> Vec<&Transaction> {
+        self.address_history
+            .get(address)
+            .into_iter()
+            .flatten()
+            .filter_map(|tx_hash| self.get_transaction(tx_hash))
+            .collect()
+    }
+
+    /// Balance of every address owning unspent value. Addresses whose
+    /// outputs were all spent, or that hold no value, are left out.
+    pub fn all_balances(&self) -> HashMap<Address, Coins> {
+        let mut balances = HashMap::new();
+        for output in self.unspent_output.values() {
+            if output.value == Coins::ZERO {
+                continue;
+            }
+            let balance = balances
+                .entry(output.address.clone())
+                .or_insert(Coins::ZERO);
+            *balance = balance.saturating_add(output.value);
+        }
+        balances
+    }
+
+    /// The `top_n` largest balances, largest first. Equal balances are
+    /// ordered by address so the list does not depend on map iteration.
+    pub fn rich_list(&self, top_n: usize) -> Vec<(Address, Coins)> {
+        let mut balances = self.all_balances().into_iter().collect::<Vec<_>>();
+        balances.sort_by(|(a_address, a), (b_address, b)| b.cmp(a).then(a_address.cmp(b_address)));
+        balances.truncate(top_n);
+        balances
+    }
+
+    /// Whether some unspent output hashes to `output_hash`. Outputs paying
+    /// the same value to the same address hash alike, so `get_utxo` is the
+    /// way to ask about one particular output.
+    pub fn is_unspent(&self, output_hash: &Hash) -> bool {
+        self.unspent_output
+            .values()
+            .any(|output| output.hash() == *output_hash)
+    }
+
+    /// The output `outpoint` names, if it is unspent.
+    pub fn get_utxo(&self, outpoint: &OutPoint) -> Option<&TxOutput> {
+        self.unspent_output.get(outpoint)
+    }
+
+    /// Unspent outputs owned by `address`, largest value first. Ties are
+    /// broken by outpoint so the order does not depend on map iteration.
+    pub fn utxos_for(&self, address: &Address) -> Vec<(OutPoint, TxOutput)> {
+        let mut utxos = self
+            .unspent_output
+            .iter()
+            .filter(|(_, output)| &output.address == address)
+            .map(|(outpoint, output)| (outpoint.clone(), output.clone()))
+            .collect::<Vec<(OutPoint, TxOutput)>>();
+        utxos.sort_by(|(a_outpoint, a), (b_outpoint, b)| {
+            b.value.cmp(&a.value).then(a_outpoint.cmp(b_outpoint))
+        });
+        utxos
+    }
+
+    pub fn stats(&self) -> ChainStats {
+        ChainStats {
+            tip_height: self.tip_height(),
+            total_transactions: self
+                .blocks
+                .iter()
+                .map(|block| block.transactions.len())
+                .sum(),
+            utxo_count: self.unspent_output.len(),
+            total_supply: self.total_supply(),
+            mempool_size: self.transaction_pool.len(),
+        }
+    }
+
+    /// Sum of all unspent output values.
+    pub fn total_supply(&self) -> Coins {
+        self.unspent_output
+            .values()
+            .fold(Coins::ZERO, |supply, output| {
+                supply.saturating_add(output.value)
+            })
+    }
+
+    /// Check that the unspent outputs hold exactly the coins the blocks
+    /// issued, counted again from the block contents alone: every coinbase
+    /// claims at most its scheduled reward plus its block's fees, and the
+    /// supply is what the coinbases paid minus all fees. Like `validate`,
+    /// this fails once a block is pruned.
+    pub fn audit_supply(&self) -> Result<(), BlockChainError> {
+        let invalid = |block: &Block, cause: BlockChainError| BlockChainError::InvalidBlockError {
+            index: block.header.index,
+            cause: Box::new(cause),
+        };
+        let overflow = || BlockChainError::InvalidCoinbaseValue(String::from("Supply overflows."));
+        let spendable_values = |transaction: &Transaction| {
+            transaction
+                .spendable_outputs(self.hash_algo)
+                .into_iter()
+                .map(|(outpoint, output)| (outpoint, output.value))
+        };
+        let mut outputs = HashMap::new();
+        let mut minted = Coins::ZERO;
+        let mut fees = Coins::ZERO;
+        for (height, block) in self.blocks.iter().enumerate() {
+            let mut block_fees = Coins::ZERO;
+            for transaction in block.transactions.iter().skip(1) {
+                let mut available = Coins::ZERO;
+                for outpoint in transaction.outpoints() {
+                    let value = outputs.remove(&outpoint).ok_or_else(|| {
+                        invalid(
+                            block,
+                            BlockChainError::InputNotSpendableError(format!(
+                                "Input {} spends no earlier output.",
+                                outpoint
+                            )),
+                        )
+                    })?;
+                    available = available.checked_add(value).ok_or_else(overflow)?;
+                }
+                let required =
+                    Coins::checked_sum(transaction.outputs.iter().map(|output| output.value))
+                        .ok_or_else(overflow)?;
+                let fee = available.checked_sub(required).ok_or_else(|| {
+                    invalid(
+                        block,
+                        BlockChainError::InsufficientFundsError {
+                            available,
+                            required,
+                        },
+                    )
+                })?;
+                block_fees = block_fees.checked_add(fee).ok_or_else(overflow)?;
+                outputs.extend(spendable_values(transaction));
+            }
+            if let Some(coinbase) = block.transactions.first() {
+                let claimed =
+                    Coins::checked_sum(coinbase.outputs.iter().map(|output| output.value))
+                        .ok_or_else(overflow)?;
+                let allowed = self
+                    .params
+                    .block_reward_at(height)
+                    .checked_add(block_fees)
+                    .ok_or_else(overflow)?;
+                if claimed > allowed {
+                    return Err(invalid(
+                        block,
+                        BlockChainError::InvalidCoinbaseValue(format!(
+                            "Coinbase claims {} coins but at most {} are allowed.",
+                            claimed, allowed
+                        )),
+                    ));
+                }
+                minted = minted.checked_add(claimed).ok_or_else(overflow)?;
+                outputs.extend(spendable_values(coinbase));
+            }
+            fees = fees.checked_add(block_fees).ok_or_else(overflow)?;
+        }
+
+        let expected = minted.checked_sub(fees).ok_or_else(overflow)?;
+        let found = self.total_supply();
+        if expected != found {
+            return Err(BlockChainError::SupplyMismatch { expected, found });
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+/// The unspent output set of a chain as it would be after applying a batch
+/// of transactions, without changing the chain.
+#[derive(Clone)]
+struct UtxoView<'a> {
+    chain: &'a Blockchain,
+    spent: HashSet<OutPoint>,
+    created: HashMap<OutPoint, TxOutput>,
+    /// Hash function and chain id the batch is checked with, the chain's
+    /// own unless the batch comes with its first block.
+    hash_algo: HashAlgo,
+    chain_id: u32,
+}
+
+impl<'a> UtxoView<'a> {
+    fn new(chain: &'a Blockchain) -> Self {
+        UtxoView {
+            chain,
+            spent: HashSet::new(),
+            created: HashMap::new(),
+            hash_algo: chain.hash_algo,
+            chain_id: chain.chain_id,
+        }
+    }
+
+    /// A view in which the outputs of pooled transactions are unspent too,
+    /// so pooled transactions can spend from each other.
+    fn with_pool(chain: &'a Blockchain) -> Self {
+        UtxoView::with_pool_except(chain, &[])
+    }
+
+    /// Like `with_pool`, leaving out the pooled transactions at `excluded`.
+    fn with_pool_except(chain: &'a Blockchain, excluded: &[usize]) -> Self {
+        let mut view = UtxoView::new(chain);
+        for (position, transaction) in chain.transaction_pool.iter().enumerate() {
+            if !excluded.contains(&position) {
+                view.created
+                    .extend(transaction.spendable_outputs(view.hash_algo));
+            }
+        }
+        view
+    }
+
+    /// A view for the transactions of `block`, which names the hash
+    /// function and chain id if it is the first block of `chain`.
+    fn for_block(chain: &'a Blockchain, block: &Block) -> Self {
+        let mut view = UtxoView::new(chain);
+        if chain.blocks.is_empty() {
+            view.hash_algo = block.header.hash_algo;
+            view.chain_id = block.header.chain_id;
+        }
+        view
+    }
+
+    /// The outputs `transaction` spends, in input order.
+    fn resolve_inputs(&self, transaction: &Transaction) -> Result<Vec<TxOutput>, BlockChainError> {
+        transaction
+            .outpoints()
+            .into_iter()
+            .map(|outpoint| {
+                if self.spent.contains(&outpoint) {
+                    return Err(BlockChainError::DoubleSpendingError(format!(
+                        "Input {} is already spent by an earlier transaction in the batch.",
+                        outpoint
+                    )));
+                }
+                self.created
+                    .get(&outpoint)
+                    .or_else(|| self.chain.unspent_output.get(&outpoint))
+                    .cloned()
+                    .ok_or_else(|| {
+                        BlockChainError::InputNotSpendableError(format!(
+                            "Input {} is not spendable.",
+                            outpoint
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    fn apply(&mut self, transaction: &Transaction) {
+        for outpoint in transaction.outpoints() {
+            self.created.remove(&outpoint);
+            self.spent.insert(outpoint);
+        }
+        self.created
+            .extend(transaction.spendable_outputs(self.hash_algo));
+    }
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> IntoIterator for &'a Blockchain {
+    type Item = &'a Block;
+    type IntoIter = std::slice::Iter<'a, Block>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.blocks.iter()
+    }
+}
+
+//Testing
+
+#[cfg(test)]
+mod tests {
+    use primitive_types::U256;
+
+    use std::collections::{HashMap, VecDeque};
+    use std::iter;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use secp256k1::{rand, PublicKey, Secp256k1, SecretKey};
+    use std::sync::Mutex;
+
+    use crate::block::{MAX_BLOCK_BYTES, MAX_BLOCK_WEIGHT};
+    use crate::blockchain::{
+        BlockChainError, ChainEvent, BLOCK_REWARD, COINBASE_MATURITY, FEE_HISTORY_BLOCKS,
+        HALVING_INTERVAL, INITIAL_DIFFICULTY, MAX_FUTURE_BLOCK_TIME, MIN_FEE_RATE,
+        RETARGET_INTERVAL, TARGET_BLOCK_TIME,
+    };
+    use crate::chain_params::ChainParams;
+    use crate::consensus::AlwaysValid;
+    use crate::transaction::{OutPoint, MAX_TX_INPUTS, MAX_TX_OUTPUTS, OUTPUT_WEIGHT};
+    use crate::{
+        check_difficulty, now, Address, Block, Blockchain, Coins, Hash, HashAlgo, Hashable,
+        TimeStamp, Transaction, TxOutput,
+    };
+
+    fn new_key() -> (SecretKey, Address) {
+        let (secret_key, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        (secret_key, Address::from_public_key(&public_key))
+    }
+
+    fn address_of(key: &SecretKey) -> Address {
+        Address::from_public_key(&PublicKey::from_secret_key(&Secp256k1::new(), key))
+    }
+
+    /// A chain holding two unspent outputs owned by a fresh key, plus an
+    /// unsigned transaction spending both of them.
+    fn funded_chain_and_transaction() -> (Blockchain, SecretKey, Transaction) {
+        let mut blockchain: Blockchain = regtest_chain();
+        let (alice_key, alice) = new_key();
+        let (_, bob) = new_key();
+        let unspent_outputs = vec![
+            TxOutput {
+                address: alice.clone(),
+                value: Coins::from_whole(10),
+                data: None,
+            },
+            TxOutput {
+                address: alice,
+                value: Coins::from_whole(20),
+                data: None,
+            },
+        ];
+        let outpoints = (0..unspent_outputs.len() as u32)
+            .map(|index| OutPoint {
+                tx_hash: Hash::zero(),
+                index,
+            })
+            .collect::<Vec<_>>();
+        blockchain
+            .unspent_output
+            .extend(outpoints.iter().cloned().zip(unspent_outputs));
+        let transaction = outpoints
+            .into_iter()
+            .fold(Transaction::builder(), |builder, outpoint| {
+                builder.input(outpoint)
+            })
+            .output(bob.clone(), Coins::from_whole(25))
+            .output(bob, Coins(499_500_000))
+            .build()
+            .unwrap();
+        (blockchain, alice_key, transaction)
+    }
+
+    #[test]
+    fn add_transaction_to_pool() {
+        // Given
+        let (mut blockchain, alice_key, mut transaction) = funded_chain_and_transaction();
+        transaction.sign(&alice_key);
+
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        assert_eq!(1, blockchain.transaction_pool.len());
+        assert_eq!(2, blockchain.unspent_output.len());
+    }
+
+    #[test]
+    fn should_restore_unspent_outputs_when_reverting_transaction() {
+        let (mut blockchain, alice_key, mut transaction) = funded_chain_and_transaction();
+        transaction.sign(&alice_key);
+        let before = blockchain.unspent_output.clone();
+
+        blockchain.apply_transaction(&transaction).unwrap();
+        assert_eq!(
+            transaction
+                .spendable_outputs(HashAlgo::Sha256)
+                .into_iter()
+                .collect::<HashMap<_, _>>(),
+            blockchain.unspent_output
+        );
+        assert!(blockchain.apply_transaction(&transaction).is_err());
+
+        blockchain.revert_transaction(&transaction);
+        assert_eq!(before, blockchain.unspent_output);
+    }
+
+    #[test]
+    fn should_keep_snapshot_unchanged_by_later_blocks() {
+        let (mut blockchain, alice_key, mut transaction) = funded_chain_and_transaction();
+        mine_block(&mut blockchain, &new_key().1);
+        let snapshot = blockchain.snapshot();
+        let unspent_output = snapshot.unspent_output.clone();
+        transaction.sign(&alice_key);
+        let tx_hash = transaction.hash();
+
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        mine_block(&mut blockchain, &new_key().1);
+        assert!(blockchain.get_transaction(&tx_hash).is_some());
+
+        assert_eq!(1, snapshot.len());
+        assert_eq!(0, snapshot.pool_len());
+        assert_eq!(unspent_output, snapshot.unspent_output);
+        assert!(snapshot.get_transaction(&tx_hash).is_none());
+        assert!(snapshot
+            .get_block_by_hash(&blockchain.latest_block().unwrap().header.hash)
+            .is_none());
+        assert!(snapshot.validate().is_ok());
+    }
+
+    #[test]
+    fn should_detect_double_spends_against_a_large_pool() {
+        let mut blockchain = regtest_chain();
+        let (key, owner) = new_key();
+        let (_, bob) = new_key();
+        let outputs = (1..=300)
+            .map(|value| TxOutput {
+                address: owner.clone(),
+                value: Coins::from_whole(value),
+                data: None,
+            })
+            .enumerate()
+            .map(|(index, output)| {
+                let outpoint = OutPoint {
+                    tx_hash: Hash::zero(),
+                    index: index as u32,
+                };
+                (outpoint, output)
+            })
+            .collect::<Vec<_>>();
+        blockchain.unspent_output.extend(outputs.iter().cloned());
+        for output in &outputs[..299] {
+            blockchain
+                .add_transaction_to_pool(spend(&key, output, &bob, Coins(1_000)))
+                .unwrap();
+        }
+        assert_eq!(299, blockchain.pool_len());
+
+        let conflicting = spend(&key, &outputs[150], &owner, Coins(2_000));
+        match blockchain.add_transaction_to_pool(conflicting) {
+            Err(BlockChainError::DoubleSpendingError(_)) => {}
+            other => panic!("expected double spend, got {:?}", other),
+        }
+        blockchain
+            .add_transaction_to_pool(spend(&key, &outputs[299], &bob, Coins(1_000)))
+            .unwrap();
+        assert_eq!(300, blockchain.pool_len());
+    }
+
+    #[test]
+    fn should_hash_with_the_algorithm_named_by_genesis() {
+        let (_, miner) = new_key();
+        let mut blockchain = regtest_chain();
+        blockchain
+            .aggregate_mined_block(Block::genesis_with(
+                miner.clone(),
+                U256::max_value(),
+                HashAlgo::Blake3,
+                0,
+            ))
+            .unwrap();
+        assert_eq!(HashAlgo::Blake3, blockchain.hash_algo());
+
+        let block = mine_block(&mut blockchain, &miner);
+        let coinbase = &block.transactions[0];
+        assert_eq!(HashAlgo::Blake3, block.header.hash_algo);
+        assert_eq!(block.hash_with(HashAlgo::Blake3), block.header.hash);
+        assert_eq!(
+            Block::compute_merkle_root_with(&block.transactions, HashAlgo::Blake3),
+            block.header.merkle_root
+        );
+        assert!(blockchain
+            .get_transaction(&coinbase.hash_with(HashAlgo::Blake3))
+            .is_some());
+        assert!(blockchain.get_transaction(&coinbase.hash()).is_none());
+
+        let sha256_block = block_on_tip(&blockchain, vec![coinbase_to(&miner, BLOCK_REWARD)]);
+        match blockchain.aggregate_mined_block(sha256_block) {
+            Err(BlockChainError::HashAlgoMismatch { expected, found }) => {
+                assert_eq!((HashAlgo::Blake3, HashAlgo::Sha256), (expected, found))
+            }
+            other => panic!("expected hash algorithm mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_reject_transactions_and_blocks_for_another_chain() {
+        let (key, owner) = new_key();
+        let mut blockchain = regtest_chain();
+        blockchain
+            .aggregate_mined_block(Block::genesis_with(
+                owner.clone(),
+                U256::max_value(),
+                HashAlgo::Sha256,
+                2,
+            ))
+            .unwrap();
+        assert_eq!(2, blockchain.chain_id());
+        let output = TxOutput {
+            address: owner.clone(),
+            value: Coins::from_whole(10),
+            data: None,
+        };
+        let outpoint = OutPoint {
+            tx_hash: Hash::zero(),
+            index: 0,
+        };
+        blockchain.unspent_output.insert(outpoint.clone(), output);
+
+        let signed_for = |chain_id| {
+            let mut transaction = Transaction::builder()
+                .input(outpoint.clone())
+                .output(new_key().1, Coins::from_whole(9))
+                .chain_id(chain_id)
+                .build()
+                .unwrap();
+            transaction.sign(&key);
+            transaction
+        };
+        match blockchain.add_transaction_to_pool(signed_for(1)) {
+            Err(BlockChainError::ChainIdMismatch { expected, found }) => {
+                assert_eq!((2, 1), (expected, found))
+            }
+            other => panic!("expected chain id mismatch, got {:?}", other),
+        }
+        blockchain.add_transaction_to_pool(signed_for(2)).unwrap();
+
+        let coinbase = Transaction::builder()
+            .output(owner, BLOCK_REWARD)
+            .chain_id(1)
+            .build()
+            .unwrap();
+        let foreign = block_on_tip(&blockchain, vec![coinbase]).with_chain_id(1);
+        match blockchain.aggregate_mined_block(foreign) {
+            Err(BlockChainError::ChainIdMismatch { expected, found }) => {
+                assert_eq!((2, 1), (expected, found))
+            }
+            other => panic!("expected chain id mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_connect_unmined_blocks_under_always_valid_consensus() {
+        let mut blockchain = Blockchain::with_consensus(Box::new(AlwaysValid));
+        let (_, miner) = new_key();
+        for _ in 0..3 {
+            // No hash is below a zero target, so these can't pass proof of work
+            let mut block = blockchain.create_candidate_block(
+                u64::MAX,
+                miner.clone(),
+                Some(U256::zero()),
+                None,
+            );
+            blockchain.consensus().prepare_candidate(&mut block);
+            blockchain.aggregate_mined_block(block).unwrap();
+        }
+        assert_eq!(3, blockchain.len());
+        assert!(blockchain.validate().is_ok());
+
+        let unmined = blockchain.get_block_by_index(1).unwrap().clone();
+        match regtest_chain().aggregate_mined_block(unmined) {
+            Err(BlockChainError::ProofOfWorkError(_)) => {}
+            other => panic!("expected proof of work error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_track_balances_from_unspent_outputs() {
+        let (blockchain, alice_key, transaction) = funded_chain_and_transaction();
+        let alice = address_of(&alice_key);
+        let bob = &transaction.outputs[0].address;
+
+        assert_eq!(Coins::from_whole(30), blockchain.balance_of(&alice));
+        assert_eq!(Coins::ZERO, blockchain.balance_of(bob));
+    }
+
+    /// An empty chain whose blocks are mined with their first hash, see
+    /// `ChainParams::regtest`.
+    fn regtest_chain() -> Blockchain {
+        Blockchain::with_params(ChainParams::regtest())
+    }
+
+    /// Build, mine and aggregate a block paying the coinbase to `miner`.
+    fn mine_block(blockchain: &mut Blockchain, miner: &Address) -> Block {
+        let mut block = blockchain.create_candidate_block(u64::MAX, miner.clone(), None, None);
+        block.mine();
+        blockchain.aggregate_mined_block(block.clone()).unwrap();
+        block
+    }
+
+    #[test]
+    fn should_reflect_transfer_in_balances() {
+        let mut blockchain = regtest_chain();
+        let (alice_key, alice) = new_key();
+        let (_, bob) = new_key();
+        let (_, miner) = new_key();
+        let (coinbase, _) = coinbase_utxo(&mine_block(&mut blockchain, &alice));
+        assert_eq!(Coins::from_whole(50), blockchain.balance_of(&alice));
+        mature_coinbases(&mut blockchain);
+
+        let mut transaction = Transaction::builder()
+            .input(coinbase)
+            .output(bob.clone(), Coins::from_whole(20))
+            .output(alice.clone(), Coins::from_whole(29))
+            .build()
+            .unwrap();
+        transaction.sign(&alice_key);
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        mine_block(&mut blockchain, &miner);
+
+        assert_eq!(Coins::from_whole(29), blockchain.balance_of(&alice));
+        assert_eq!(Coins::from_whole(20), blockchain.balance_of(&bob));
+        let alice_utxos = blockchain.utxos_for(&alice);
+        assert_eq!(1, alice_utxos.len());
+        assert_eq!(Coins::from_whole(29), alice_utxos[0].1.value);
+    }
+
+    /// A mined block on the tip holding `transactions`, those after the
+    /// first put in canonical order.
+    fn block_on_tip(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let order = Blockchain::canonical_order(&transactions[1..], HashAlgo::Sha256);
+        let transactions = iter::once(transactions[0].clone())
+            .chain(
+                order
+                    .into_iter()
+                    .map(|position| transactions[position + 1].clone()),
+            )
+            .collect();
+        block_on_tip_in_order(blockchain, transactions)
+    }
+
+    /// Like `block_on_tip`, keeping the order of `transactions`.
+    fn block_on_tip_in_order(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let tip = blockchain.latest_block().unwrap();
+        let mut block = Block::new(
+            tip.header.index + 1,
+            tip.header.timestamp + 1,
+            tip.header.hash.clone(),
+            transactions,
+            U256::max_value(),
+        );
+        block.mine();
+        block
+    }
+
+    fn coinbase_to(miner: &Address, value: Coins) -> Transaction {
+        Transaction::builder()
+            .output(miner.clone(), value)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_estimate_hashrate_from_block_intervals() {
+        let mut blockchain = Blockchain::with_consensus(Box::new(AlwaysValid));
+        assert_eq!(INITIAL_DIFFICULTY, blockchain.current_difficulty());
+        assert_eq!(0.0, blockchain.estimated_hashrate());
+
+        // Each block takes about 2^20 hashes and comes a minute after the last
+        let difficulty = U256::max_value() >> 20;
+        let (_, miner) = new_key();
+        let start = now() - 30 * TARGET_BLOCK_TIME;
+        for index in 0..15 {
+            let prev_block_hash = blockchain
+                .latest_block()
+                .map_or(Hash::zero(), |tip| tip.header.hash.clone());
+            let mut coinbase = coinbase_to(&miner, BLOCK_REWARD);
+            coinbase.lock_time = u64::from(index);
+            let mut block = Block::new(
+                index,
+                start + index as TimeStamp * TARGET_BLOCK_TIME,
+                prev_block_hash,
+                vec![coinbase],
+                difficulty,
+            );
+            blockchain.consensus().prepare_candidate(&mut block);
+            blockchain.aggregate_mined_block(block).unwrap();
+            if index == 0 {
+                assert_eq!(0.0, blockchain.estimated_hashrate());
+            }
+        }
+
+        assert_eq!(difficulty, blockchain.current_difficulty());
+        let expected = (1u64 << 20) as f64 / 60.0;
+        let estimate = blockchain.estimated_hashrate();
+        assert!(
+            (estimate - expected).abs() < expected / 100.0,
+            "estimated {} hashes per second, expected about {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn should_reject_block_with_tampered_hash() {
+        let mut blockchain = regtest_chain();
+        let (_, miner) = new_key();
+        mine_block(&mut blockchain, &miner);
+        let block = block_on_tip(&blockchain, vec![coinbase_to(&miner, BLOCK_REWARD)]);
+        assert!(block.verify_pow());
+
+        // A hash meeting any target, but not the one the header hashes to
+        let mut tampered = block.clone();
+        tampered.header.hash = Hash::zero();
+        assert!(!tampered.verify_pow());
+        match blockchain.aggregate_mined_block(tampered) {
+            Err(BlockChainError::HashMismatch { expected, found }) => {
+                assert_eq!(block.header.hash, expected);
+                assert_eq!(Hash::zero(), found);
+            }
+            other => panic!("expected hash mismatch, got {:?}", other),
+        }
+        // Also for blocks that would be kept as orphans
+        let mut orphan = block.clone();
+        orphan.header.prev_block_hash = Hash::from(vec![7; 32]);
+        assert!(matches!(
+            blockchain.aggregate_mined_block(orphan),
+            Err(BlockChainError::HashMismatch { .. })
+        ));
+        assert_eq!(0, blockchain.orphan_count());
+
+        blockchain.aggregate_mined_block(block).unwrap();
+        assert_eq!(2, blockchain.len());
+    }
+
+    #[test]
+    fn should_leave_chain_unchanged_when_a_later_transaction_is_invalid() {
+        let mut blockchain = regtest_chain();
+        let mut funded = funded_keys(&mut blockchain, 3);
+        let (_, bob) = new_key();
+        let mut transactions = vec![coinbase_to(&bob, BLOCK_REWARD)];
+        for (key, utxo) in &funded[..2] {
+            transactions.push(spend(key, utxo, &bob, Coins(1_000)));
+        }
+        // Signed by the wrong key
+        let (_, utxo) = funded.remove(2);
+        transactions.push(spend(&new_key().0, &utxo, &bob, Coins(1_000)));
+        let block = block_on_tip(&blockchain, transactions);
+
+        let state = |blockchain: &Blockchain| {
+            (
+                serde_json::to_vec(&blockchain.blocks).unwrap(),
+                blockchain.unspent_output.clone(),
+                blockchain.spent_by.clone(),
+                blockchain.tx_index.clone(),
+                blockchain.block_positions.clone(),
+                blockchain.coinbase_heights.clone(),
+                blockchain.fee_history.clone(),
+            )
+        };
+        let before = state(&blockchain);
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::InvalidSignatureError(_)) => {}
+            other => panic!("expected invalid signature, got {:?}", other),
+        }
+        assert!(before == state(&blockchain));
+    }
+
+    #[test]
+    fn should_keep_defaults_when_first_block_is_rejected() {
+        let mut blockchain = regtest_chain();
+        let (_, miner) = new_key();
+        let coinbase = Transaction::builder()
+            .output(miner, BLOCK_REWARD.checked_add(Coins(1)).unwrap())
+            .chain_id(5)
+            .build()
+            .unwrap();
+        let mut block = Block::new(0, now(), Hash::zero(), vec![coinbase], U256::max_value())
+            .with_hash_algo(HashAlgo::Blake3)
+            .with_chain_id(5);
+        block.mine();
+
+        assert!(matches!(
+            blockchain.aggregate_mined_block(block),
+            Err(BlockChainError::InvalidCoinbaseValue(_))
+        ));
+        assert_eq!(HashAlgo::Sha256, blockchain.hash_algo());
+        assert_eq!(0, blockchain.chain_id());
+        assert!(blockchain.unspent_output.is_empty());
+    }
+
+    #[test]
+    fn should_list_richest_addresses_first() {
+        let mut blockchain = regtest_chain();
+        let (_, miner) = new_key();
+        let funded = [new_key().1, new_key().1, new_key().1];
+        let coinbase = [5, 30, 15]
+            .iter()
+            .zip(&funded)
+            .fold(Transaction::builder(), |builder, (coins, address)| {
+                builder.output(address.clone(), Coins::from_whole(*coins))
+            })
+            // A coinbase may pay nothing to an address
+            .output(miner.clone(), Coins::ZERO)
+            .build()
+            .unwrap();
+        let mut genesis = Block::new(0, now(), Hash::zero(), vec![coinbase], U256::max_value());
+        genesis.mine();
+        blockchain.aggregate_mined_block(genesis).unwrap();
+
+        let balances = blockchain.all_balances();
+        assert_eq!(3, balances.len());
+        assert_eq!(Some(&Coins::from_whole(30)), balances.get(&funded[1]));
+        assert!(!balances.contains_key(&miner));
+        assert_eq!(
+            vec![
+                (funded[1].clone(), Coins::from_whole(30)),
+                (funded[2].clone(), Coins::from_whole(15)),
+                (funded[0].clone(), Coins::from_whole(5)),
+            ],
+            blockchain.rich_list(3)
+        );
+        assert_eq!(1, blockchain.rich_list(1).len());
+        assert_eq!(3, blockchain.rich_list(10).len());
+    }
+
+    #[test]
+    fn should_reject_coinbase_with_inputs() {
+        let mut blockchain = regtest_chain();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let fake_coinbase = spend(&key, &output, &new_key().1, Coins(1_000));
+        let block = block_on_tip(&blockchain, vec![fake_coinbase]);
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::InvalidCoinbaseStructure(_)) => {}
+            other => panic!("expected invalid coinbase structure, got {:?}", other),
+        }
+        assert_eq!(COINBASE_MATURITY as usize, blockchain.len());
+    }
+
+    #[test]
+    fn should_reject_block_without_coinbase() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        let block = block_on_tip_in_order(&blockchain, vec![]);
+
+        for _ in 0..2 {
+            match blockchain.aggregate_mined_block(block.clone()) {
+                Err(BlockChainError::InvalidCoinbaseStructure(_)) => {}
+                other => panic!("expected invalid coinbase structure, got {:?}", other),
+            }
+        }
+        assert_eq!(1, blockchain.len());
+        assert!(blockchain.get_block_by_hash(&block.header.hash).is_none());
+    }
+
+    #[test]
+    fn should_reject_second_coinbase() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        let block = block_on_tip(
+            &blockchain,
+            vec![
+                coinbase_to(&new_key().1, Coins::from_whole(25)),
+                coinbase_to(&new_key().1, Coins::from_whole(25)),
+            ],
+        );
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::InvalidCoinbaseStructure(_)) => {}
+            other => panic!("expected invalid coinbase structure, got {:?}", other),
+        }
+        assert_eq!(1, blockchain.len());
+    }
+
+    #[test]
+    fn should_reject_transaction_without_outputs() {
+        let (blockchain, alice_key, mut transaction) = funded_chain_and_transaction();
+        transaction.outputs.clear();
+        transaction.sign(&alice_key);
+
+        match blockchain.verify_transaction(&transaction) {
+            Err(BlockChainError::InvalidTransactionError(_)) => {}
+            other => panic!("expected an invalid transaction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_reject_zero_value_output() {
+        let (blockchain, alice_key, mut transaction) = funded_chain_and_transaction();
+        transaction.outputs[1].value = Coins::ZERO;
+        transaction.sign(&alice_key);
+
+        match blockchain.verify_transaction(&transaction) {
+            Err(BlockChainError::InvalidTransactionError(_)) => {}
+            other => panic!("expected an invalid transaction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_reject_transaction_listing_an_input_twice() {
+        let mut blockchain = regtest_chain();
+        let (key, (outpoint, _)) = funded_keys(&mut blockchain, 1).remove(0);
+        let mut transaction = Transaction::builder()
+            .input(outpoint.clone())
+            .input(outpoint.clone())
+            .output(new_key().1, Coins::from_whole(90))
+            .build()
+            .unwrap();
+        transaction.sign(&key);
+
+        match blockchain.add_transaction_to_pool(transaction) {
+            Err(BlockChainError::DuplicateInput(duplicate)) => assert_eq!(outpoint, duplicate),
+            other => panic!("expected duplicate input, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_reject_block_spending_an_output_spent_earlier_in_the_block() {
+        let mut blockchain = regtest_chain();
+        let (key, utxo) = funded_keys(&mut blockchain, 1).remove(0);
+        let first = spend(&key, &utxo, &new_key().1, Coins(1_000));
+        let second = spend(&key, &utxo, &new_key().1, Coins(2_000));
+        let block = block_on_tip(
+            &blockchain,
+            vec![coinbase_to(&new_key().1, BLOCK_REWARD), first, second],
+        );
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::DoubleSpendingError(_)) => {}
+            other => panic!("expected double spend, got {:?}", other),
+        }
+        let (_, output) = utxo;
+        assert_eq!(output.value, blockchain.balance_of(&output.address));
+    }
+
+    #[test]
+    fn should_report_conflicting_pair_in_batch() {
+        let mut blockchain = regtest_chain();
+        let mut funded = funded_keys(&mut blockchain, 2);
+        let (other_key, other_utxo) = funded.remove(1);
+        let (key, utxo) = funded.remove(0);
+        let (bob_key, bob) = new_key();
+        let first = spend(&key, &utxo, &bob, Coins(1_000));
+        let chained = spend(
+            &bob_key,
+            &first.spendable_outputs(HashAlgo::Sha256)[0],
+            &new_key().1,
+            Coins(1_000),
+        );
+        let unrelated = spend(&other_key, &other_utxo, &bob, Coins(1_000));
+        let conflicting = spend(&key, &utxo, &new_key().1, Coins(2_000));
+
+        assert!(blockchain
+            .verify_transactions(&[first.clone(), chained.clone(), unrelated.clone()])
+            .is_ok());
+        match blockchain.verify_transactions(&[first, unrelated, chained, conflicting]) {
+            Err(BlockChainError::DoubleSpendingError(message)) => {
+                assert!(message.contains(&utxo.0.to_string()))
+            }
+            other => panic!("expected double spend, got {:?}", other),
+        }
+        assert_eq!(0, blockchain.pool_len());
+    }
+
+    #[test]
+    fn should_prune_fully_spent_blocks_to_headers() {
+        let mut blockchain = regtest_chain();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let owner = output.1.address.clone();
+        let spent_block = blockchain.get_block_by_index(1).unwrap().clone();
+        let spend = spend(&key, &output, &new_key().1, Coins(1_000));
+        let spend_hash = spend.hash();
+        let block = block_on_tip(
+            &blockchain,
+            vec![coinbase_to(&new_key().1, BLOCK_REWARD), spend],
+        );
+        blockchain.aggregate_mined_block(block).unwrap();
+        let unspent_output = blockchain.unspent_output.clone();
+        let tip = blockchain.tip_height().unwrap();
+
+        // only the first block has nothing left unspent
+        assert_eq!(1, blockchain.prune_below(tip));
+        assert_eq!(0, blockchain.prune_below(tip));
+        assert_eq!(unspent_output, blockchain.unspent_output);
+
+        let pruned = blockchain.get_block_by_index(1).unwrap();
+        assert!(pruned.pruned);
+        assert!(pruned.transactions.is_empty());
+        assert_eq!(spent_block.header.hash, pruned.header.hash);
+        assert_eq!(spent_block.header.merkle_root, pruned.header.merkle_root);
+        assert!(blockchain
+            .get_transaction(&spent_block.transactions[0].hash())
+            .is_none());
+        assert!(!blockchain.get_block_by_index(2).unwrap().pruned);
+        assert_eq!(
+            Some(&vec![spend_hash]),
+            blockchain.address_history.get(&owner)
+        );
+
+        mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(Some(tip + 1), blockchain.tip_height());
+
+        // Replaying needs the dropped transactions
+        match blockchain.validate() {
+            Err(BlockChainError::InvalidBlockError { index, cause }) => {
+                assert_eq!(1, index);
+                assert!(matches!(*cause, BlockChainError::InvalidBlockIndex(_)));
+            }
+            other => panic!("expected invalid block error, got {:?}", other),
+        }
+        // Neither is a saved file loaded, so none is written
+        let path = std::env::temp_dir().join(format!("ccore-pruned-{}.json", now()));
+        match blockchain.save_to_file(&path) {
+            Err(error) => assert_eq!(std::io::ErrorKind::InvalidInput, error.kind()),
+            other => panic!("expected invalid input, got {:?}", other),
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn should_keep_data_outputs_out_of_unspent_set() {
+        let mut blockchain = regtest_chain();
+        let (key, (outpoint, output)) = funded_keys(&mut blockchain, 1).remove(0);
+        let (_, bob) = new_key();
+        let mut transaction = Transaction::builder()
+            .input(outpoint)
+            .output(bob.clone(), output.value.checked_sub(Coins(1_000)).unwrap())
+            .data(b"commitment".to_vec())
+            .build()
+            .unwrap();
+        transaction.sign(&key);
+        let utxo_count = blockchain.unspent_output.len();
+
+        let block = block_on_tip(
+            &blockchain,
+            vec![coinbase_to(&new_key().1, BLOCK_REWARD), transaction.clone()],
+        );
+        blockchain.aggregate_mined_block(block).unwrap();
+
+        // one output spent, one payment and one coinbase output created
+        assert_eq!(utxo_count + 1, blockchain.unspent_output.len());
+        assert!(blockchain
+            .unspent_output
+            .values()
+            .all(|output| !output.is_unspendable()));
+        let confirmed = blockchain.get_transaction(&transaction.hash()).unwrap();
+        assert_eq!(Some(b"commitment".to_vec()), confirmed.outputs[1].data);
+    }
+
+    #[test]
+    fn should_accept_block_spending_an_output_created_earlier_in_the_block() {
+        let mut blockchain = regtest_chain();
+        let (key, (outpoint, output)) = funded_keys(&mut blockchain, 1).remove(0);
+        let (bob_key, bob) = new_key();
+        let (_, carol) = new_key();
+        let first = spend(&key, &(outpoint, output.clone()), &bob, Coins(1_000));
+        let second = spend(
+            &bob_key,
+            &first.spendable_outputs(HashAlgo::Sha256)[0],
+            &carol,
+            Coins(1_000),
+        );
+        let block = block_on_tip(
+            &blockchain,
+            vec![coinbase_to(&new_key().1, BLOCK_REWARD), first, second],
+        );
+
+        blockchain.aggregate_mined_block(block).unwrap();
+        assert_eq!(Coins::ZERO, blockchain.balance_of(&bob));
+        assert_eq!(
+            output.value.checked_sub(Coins(2_000)).unwrap(),
+            blockchain.balance_of(&carol)
+        );
+    }
+
+    #[test]
+    fn should_reject_block_spending_an_output_created_later_in_the_block() {
+        let mut blockchain = regtest_chain();
+        let (key, utxo) = funded_keys(&mut blockchain, 1).remove(0);
+        let (bob_key, bob) = new_key();
+        let first = spend(&key, &utxo, &bob, Coins(1_000));
+        let second = spend(
+            &bob_key,
+            &first.spendable_outputs(HashAlgo::Sha256)[0],
+            &new_key().1,
+            Coins(1_000),
+        );
+        let block = block_on_tip_in_order(
+            &blockchain,
+            vec![
+                coinbase_to(&new_key().1, BLOCK_REWARD),
+                second.clone(),
+                first,
+            ],
+        );
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::InvalidTransactionOrder(hash)) => assert_eq!(second.hash(), hash),
+            other => panic!("expected invalid transaction order, got {:?}", other),
+        }
+        let (_, output) = utxo;
+        assert_eq!(output.value, blockchain.balance_of(&output.address));
+    }
+
+    /// A signed transaction moving `utxo` to `to`, leaving `fee` unclaimed.
+    fn spend(
+        key: &SecretKey,
+        utxo: &(OutPoint, TxOutput),
+        to: &Address,
+        fee: Coins,
+    ) -> Transaction {
+        let (outpoint, output) = utxo;
+        let mut transaction = Transaction::builder()
+            .input(outpoint.clone())
+            .output(to.clone(), output.value.checked_sub(fee).unwrap())
+            .build()
+            .unwrap();
+        transaction.sign(key);
+        transaction
+    }
+
+    /// A signed transaction spending `utxo` into `count` equal outputs to
+    /// `to`, leaving the remainder as fee.
+    fn fan_out(
+        key: &SecretKey,
+        utxo: &(OutPoint, TxOutput),
+        to: &Address,
+        count: usize,
+    ) -> Transaction {
+        let value = Coins(utxo.1.value.units() / (count as u64 + 1));
+        let mut transaction = (0..count)
+            .fold(
+                Transaction::builder().input(utxo.0.clone()),
+                |builder, _| builder.output(to.clone(), value),
+            )
+            .build()
+            .unwrap();
+        transaction.sign(key);
+        transaction
+    }
+
+    #[test]
+    fn should_limit_outputs_per_transaction() {
+        let mut blockchain = regtest_chain();
+        let (key, utxo) = funded_keys(&mut blockchain, 1).remove(0);
+        let (_, bob) = new_key();
+
+        let at_limit = fan_out(&key, &utxo, &bob, MAX_TX_OUTPUTS);
+        assert!(blockchain.verify_transaction(&at_limit).is_ok());
+        let over_limit = fan_out(&key, &utxo, &bob, MAX_TX_OUTPUTS + 1);
+        assert!(matches!(
+            blockchain.verify_transaction(&over_limit),
+            Err(BlockChainError::InvalidTransactionError(message)) if message.contains("outputs")
+        ));
+    }
+
+    #[test]
+    fn should_limit_inputs_per_transaction() {
+        let mut blockchain = regtest_chain();
+        let mut funded = funded_keys(&mut blockchain, 2);
+        let (other_key, other_utxo) = funded.pop().unwrap();
+        let (key, utxo) = funded.pop().unwrap();
+        let owner = address_of(&key);
+        let fan_out = fan_out(&key, &utxo, &owner, MAX_TX_INPUTS);
+        let utxos = fan_out.spendable_outputs(HashAlgo::Sha256);
+        blockchain.add_transaction_to_pool(fan_out).unwrap();
+        mine_block(&mut blockchain, &new_key().1);
+
+        let (_, bob) = new_key();
+        let spend_all = |extra: Option<&(OutPoint, TxOutput)>| {
+            let mut transaction = utxos
+                .iter()
+                .chain(extra)
+                .fold(Transaction::builder(), |builder, (outpoint, _)| {
+                    builder.input(outpoint.clone())
+                })
+                .output(bob.clone(), utxos[0].1.value)
+                .build()
+                .unwrap();
+            transaction.sign(&key);
+            if extra.is_some() {
+                transaction.sign_input(MAX_TX_INPUTS, &other_key);
+            }
+            transaction
+        };
+        assert!(blockchain.verify_transaction(&spend_all(None)).is_ok());
+        assert!(matches!(
+            blockchain.verify_transaction(&spend_all(Some(&other_utxo))),
+            Err(BlockChainError::InvalidTransactionError(message)) if message.contains("inputs")
+        ));
+    }
+
+    /// Keeps every record logged by any test from the time it is installed.
+    struct CapturingLogger(Mutex<Vec<(Level, String)>>);
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CapturingLogger = CapturingLogger(Mutex::new(Vec::new()));
+
+    #[test]
+    fn should_log_rejected_transaction_as_warning() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Debug);
+        let mut blockchain = regtest_chain();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let transaction = spend(&key, &output, &new_key().1, Coins::ZERO);
+        let tx_hash = transaction.hash();
+
+        let error = blockchain.add_transaction_to_pool(transaction).unwrap_err();
+        assert!(LOGGER.0.lock().unwrap().iter().any(|(level, message)| {
+            *level == Level::Warn
+                && message.contains(&tx_hash.to_string())
+                && message.contains(&error.to_string())
+        }));
+    }
+
+    #[test]
+    fn should_describe_insufficient_funds() {
+        let mut blockchain = regtest_chain();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let transaction = spend(&key, &output, &new_key().1, Coins::ZERO);
+
+        let error = blockchain.add_transaction_to_pool(transaction).unwrap_err();
+        match &error {
+            BlockChainError::InsufficientFundsError {
+                available,
+                required,
+            } => {
+                assert_eq!(output.1.value, *available);
+                assert_eq!(Coins(output.1.value.units() + 1), *required);
+            }
+            other => panic!("expected insufficient funds, got {:?}", other),
+        }
+        assert_eq!(
+            "Inputs hold 50.00000000 coins but at least 50.00000001 are required.",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn should_value_inputs_by_the_outputs_they_reference() {
+        let (mut blockchain, alice_key, transaction) = funded_chain_and_transaction();
+        // Spend only the 10 coin output while still paying out 25 coins
+        let mut transaction = Transaction::builder()
+            .input(transaction.inputs[0].outpoint())
+            .output(new_key().1, Coins::from_whole(25))
+            .build()
+            .unwrap();
+        transaction.sign(&alice_key);
+
+        match blockchain.add_transaction_to_pool(transaction) {
+            Err(BlockChainError::InsufficientFundsError { available, .. }) => {
+                assert_eq!(Coins::from_whole(10), available)
+            }
+            other => panic!("expected insufficient funds, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_pay_fees_to_miner() {
+        let mut blockchain = regtest_chain();
+        let (alice_key, alice) = new_key();
+        let (carol_key, carol) = new_key();
+        let (_, bob) = new_key();
+        let (_, miner) = new_key();
+        let first = coinbase_utxo(&mine_block(&mut blockchain, &alice));
+        let second = coinbase_utxo(&mine_block(&mut blockchain, &carol));
+        mature_coinbases(&mut blockchain);
+        let first_spend = spend(&alice_key, &first, &bob, Coins::from_whole(1));
+        let second_spend = spend(&carol_key, &second, &bob, Coins::from_whole(2));
+        assert_eq!(Coins::from_whole(1), blockchain.fee_of(&first_spend));
+        blockchain.add_transaction_to_pool(first_spend).unwrap();
+        blockchain.add_transaction_to_pool(second_spend).unwrap();
+
+        let block = mine_block(&mut blockchain, &miner);
+
+        assert_eq!(
+            Coins::from_whole(53),
+            block.transactions[0].outputs[0].value
+        );
+        assert_eq!(Coins::from_whole(53), blockchain.balance_of(&miner));
+        assert_eq!(Coins::from_whole(97), blockchain.balance_of(&bob));
+    }
+
+    #[test]
+    fn should_stop_filling_candidate_at_size_limit() {
+        let mut blockchain = regtest_chain();
+        let (alice_key, alice) = new_key();
+        let (carol_key, carol) = new_key();
+        let (_, bob) = new_key();
+        let first = coinbase_utxo(&mine_block(&mut blockchain, &alice));
+        let second = coinbase_utxo(&mine_block(&mut blockchain, &carol));
+        mature_coinbases(&mut blockchain);
+        let first_spend = spend(&alice_key, &first, &bob, Coins::from_whole(1));
+        let second_spend = spend(&carol_key, &second, &bob, Coins::from_whole(1));
+        // signature encodings vary by a byte or two, so leave room for either
+        let sizes = [
+            first_spend.serialized_size(),
+            second_spend.serialized_size(),
+        ];
+        let room_for_one = sizes.iter().max().unwrap() + 1;
+        let room_for_none = sizes.iter().min().unwrap();
+        blockchain.add_transaction_to_pool(first_spend).unwrap();
+        blockchain.add_transaction_to_pool(second_spend).unwrap();
+
+        assert_eq!(
+            2,
+            blockchain
+                .select_candidate_transactions(MAX_BLOCK_WEIGHT, MAX_BLOCK_BYTES)
+                .len()
+        );
+        assert_eq!(
+            1,
+            blockchain
+                .select_candidate_transactions(MAX_BLOCK_WEIGHT, room_for_one)
+                .len()
+        );
+        assert_eq!(
+            0,
+            blockchain
+                .select_candidate_transactions(MAX_BLOCK_WEIGHT, *room_for_none)
+                .len()
+        );
+    }
+
+    #[test]
+    fn should_fill_candidate_up_to_weight() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let spends = funded_keys(&mut blockchain, 3)
+            .iter()
+            .map(|(key, output)| spend(key, output, &bob, Coins::from_whole(1)))
+            .collect::<Vec<Transaction>>();
+        let weight = spends[0].weight();
+        for transaction in spends {
+            blockchain.add_transaction_to_pool(transaction).unwrap();
+        }
+
+        assert_eq!(
+            2,
+            blockchain
+                .select_candidate_transactions(2 * weight, MAX_BLOCK_BYTES)
+                .len()
+        );
+        assert_eq!(
+            1,
+            blockchain
+                .select_candidate_transactions(2 * weight - 1, MAX_BLOCK_BYTES)
+                .len()
+        );
+        let block =
+            blockchain.create_candidate_block(2 * weight, bob, Some(U256::max_value()), None);
+        assert_eq!(3, block.transactions.len());
+        assert_eq!(1, blockchain.pool_len());
+    }
+
+    #[test]
+    fn should_reject_overweight_block() {
+        let mut blockchain = regtest_chain();
+        let (_, miner) = new_key();
+        let coinbase = (0..MAX_BLOCK_WEIGHT / OUTPUT_WEIGHT)
+            .fold(Transaction::builder(), |builder, _| {
+                builder.output(miner.clone(), Coins::ZERO)
+            })
+            .build()
+            .unwrap();
+        let mut block = Block::new(0, now(), Hash::zero(), vec![coinbase], U256::max_value());
+        block.mine();
+        assert!(block.serialized_size() <= MAX_BLOCK_BYTES);
+        assert!(block.weight() > MAX_BLOCK_WEIGHT);
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::BlockTooLarge { size, max }) => {
+                assert_eq!(MAX_BLOCK_WEIGHT as usize, max);
+                assert!(size > max);
+            }
+            other => panic!("expected block too large, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_estimate_fee_from_recent_blocks() {
+        let mut blockchain = regtest_chain();
+        assert_eq!(MIN_FEE_RATE, blockchain.estimate_fee(1));
+
+        blockchain
+            .fee_history
+            .extend([vec![2.0, 40.0], vec![], vec![10.0, 5.0, 20.0]]);
+        let fast = blockchain.estimate_fee(1);
+        let slow = blockchain.estimate_fee(10);
+        assert!((2.0..=40.0).contains(&fast));
+        assert!((2.0..=40.0).contains(&slow));
+        assert!(fast > slow);
+
+        blockchain.fee_history = VecDeque::from([vec![0.1]]);
+        assert_eq!(MIN_FEE_RATE, blockchain.estimate_fee(1));
+    }
+
+    #[test]
+    fn should_record_fee_rates_of_mined_blocks() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let transaction = spend(&key, &output, &bob, Coins(100_000));
+        let fee_rate = blockchain.fee_rate_of(&transaction);
+        let block = block_on_tip(
+            &blockchain,
+            vec![coinbase_to(&bob, BLOCK_REWARD), transaction],
+        );
+        blockchain.aggregate_mined_block(block).unwrap();
+
+        assert_eq!(FEE_HISTORY_BLOCKS, blockchain.fee_history.len());
+        assert_eq!(Some(&vec![fee_rate]), blockchain.fee_history.back());
+        assert_eq!(fee_rate.max(MIN_FEE_RATE), blockchain.estimate_fee(1));
+    }
+
+    #[test]
+    fn should_prefer_higher_fee_rate() {
+        let mut blockchain = regtest_chain();
+        let (alice_key, alice) = new_key();
+        let (carol_key, carol) = new_key();
+        let (_, bob) = new_key();
+        let first = coinbase_utxo(&mine_block(&mut blockchain, &alice));
+        let second = coinbase_utxo(&mine_block(&mut blockchain, &carol));
+        mature_coinbases(&mut blockchain);
+        let low_fee = spend(&alice_key, &first, &bob, Coins(1_000));
+        let high_fee = spend(&carol_key, &second, &bob, Coins(100_000));
+        assert!(blockchain.fee_rate_of(&high_fee) > blockchain.fee_rate_of(&low_fee));
+        blockchain.add_transaction_to_pool(low_fee.clone()).unwrap();
+        blockchain
+            .add_transaction_to_pool(high_fee.clone())
+            .unwrap();
+
+        let block = blockchain.create_candidate_block(
+            high_fee.weight(),
+            bob,
+            Some(U256::max_value()),
+            None,
+        );
+
+        assert_eq!(2, block.transactions.len());
+        assert_eq!(high_fee.hash(), block.transactions[1].hash());
+        assert_eq!(1, blockchain.transaction_pool.len());
+        assert_eq!(low_fee.hash(), blockchain.transaction_pool[0].hash());
+    }
+
+    /// Mine one block per new key so each key owns a distinct coinbase
+    /// output, then enough blocks for all of them to mature.
+    fn funded_keys(
+        blockchain: &mut Blockchain,
+        count: usize,
+    ) -> Vec<(SecretKey, (OutPoint, TxOutput))> {
+        let funded = (0..count)
+            .map(|_| {
+                let (key, address) = new_key();
+                (key, coinbase_utxo(&mine_block(blockchain, &address)))
+            })
+            .collect();
+        mature_coinbases(blockchain);
+        funded
+    }
+
+    /// The output a block pays to its miner, with the outpoint spending it.
+    fn coinbase_utxo(block: &Block) -> (OutPoint, TxOutput) {
+        let coinbase = &block.transactions[0];
+        let outpoint = OutPoint {
+            tx_hash: coinbase.hash(),
+            index: 0,
+        };
+        (outpoint, coinbase.outputs[0].clone())
+    }
+
+    /// Mine enough blocks for the coinbase of the current tip to be spendable.
+    fn mature_coinbases(blockchain: &mut Blockchain) {
+        for _ in 1..COINBASE_MATURITY {
+            mine_block(blockchain, &new_key().1);
+        }
+    }
+
+    #[test]
+    fn should_evict_cheapest_transaction_when_pool_is_full() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let funded = funded_keys(&mut blockchain, 4);
+        let fees = [Coins(5_000), Coins(1_000), Coins(9_000), Coins(500)];
+        let transactions = funded
+            .iter()
+            .zip(fees)
+            .map(|((key, output), fee)| spend(key, output, &bob, fee))
+            .collect::<Vec<Transaction>>();
+        blockchain.set_max_pool_size(2);
+        blockchain
+            .add_transaction_to_pool(transactions[0].clone())
+            .unwrap();
+        blockchain
+            .add_transaction_to_pool(transactions[1].clone())
+            .unwrap();
+
+        blockchain
+            .add_transaction_to_pool(transactions[2].clone())
+            .unwrap();
+        assert_eq!(2, blockchain.pool_len());
+        let pooled = blockchain
+            .transaction_pool
+            .iter()
+            .map(|transaction| transaction.hash())
+            .collect::<Vec<Hash>>();
+        assert_eq!(vec![transactions[0].hash(), transactions[2].hash()], pooled);
+
+        match blockchain.add_transaction_to_pool(transactions[3].clone()) {
+            Err(BlockChainError::MempoolFull(_)) => {}
+            other => panic!("expected mempool full, got {:?}", other),
+        }
+        assert_eq!(2, blockchain.pool_len());
+
+        blockchain.clear_pool();
+        assert_eq!(0, blockchain.pool_len());
+    }
+
+    #[test]
+    fn should_evict_descendants_along_with_cheapest_transaction() {
+        let mut blockchain = regtest_chain();
+        let mut funded = funded_keys(&mut blockchain, 3);
+        let (bob_key, bob) = new_key();
+        let (carol_key, carol) = new_key();
+        let parent = spend(&funded[0].0, &funded[0].1, &bob, Coins(500));
+        let child = spend(
+            &bob_key,
+            &parent.spendable_outputs(HashAlgo::Sha256)[0],
+            &carol,
+            Coins(2_000),
+        );
+        let other = spend(&funded[1].0, &funded[1].1, &bob, Coins(5_000));
+        blockchain.set_max_pool_size(3);
+        for transaction in [&parent, &child, &other] {
+            blockchain
+                .add_transaction_to_pool(transaction.clone())
+                .unwrap();
+        }
+
+        // Evicting the parent would leave this one without its input
+        let grandchild = spend(
+            &carol_key,
+            &child.spendable_outputs(HashAlgo::Sha256)[0],
+            &bob,
+            Coins(9_000),
+        );
+        match blockchain.add_transaction_to_pool(grandchild) {
+            Err(BlockChainError::MempoolFull(_)) => {}
+            other => panic!("expected mempool full, got {:?}", other),
+        }
+        assert_eq!(3, blockchain.pool_len());
+
+        let (key, output) = funded.pop().unwrap();
+        let newcomer = spend(&key, &output, &bob, Coins(9_000));
+        blockchain
+            .add_transaction_to_pool(newcomer.clone())
+            .unwrap();
+        let pooled = blockchain
+            .mempool()
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<Hash>>();
+        assert_eq!(vec![other.hash(), newcomer.hash()], pooled);
+        let block = mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(3, block.transactions.len());
+    }
+
+    #[test]
+    fn should_expire_old_pooled_transactions() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let mut funded = funded_keys(&mut blockchain, 2);
+        let fresh = spend(&funded[0].0, &funded[0].1, &bob, Coins(1_000));
+        let (old_key, (outpoint, output)) = funded.pop().unwrap();
+        let mut old = Transaction::builder()
+            .input(outpoint)
+            .output(bob.clone(), Coins(output.value.units() - 1_000))
+            .timestamp(now() - 2 * 60 * 60 * 1000)
+            .build()
+            .unwrap();
+        old.sign(&old_key);
+        blockchain.add_transaction_to_pool(fresh.clone()).unwrap();
+        blockchain.add_transaction_to_pool(old.clone()).unwrap();
+
+        assert!(blockchain.expire_mempool().is_empty());
+        blockchain.set_mempool_expiry_secs(60 * 60);
+        assert_eq!(vec![old.hash()], blockchain.expire_mempool());
+        assert_eq!(1, blockchain.pool_len());
+        assert!(blockchain.mempool_contains(&fresh.hash()));
+    }
+
+    #[test]
+    fn should_describe_pooled_transactions_by_fee_rate() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let funded = funded_keys(&mut blockchain, 2);
+        let cheap = spend(&funded[0].0, &funded[0].1, &bob, Coins(1_000));
+        let dear = spend(&funded[1].0, &funded[1].1, &bob, Coins(5_000));
+        blockchain.add_transaction_to_pool(cheap.clone()).unwrap();
+        blockchain.add_transaction_to_pool(dear.clone()).unwrap();
+
+        let info = blockchain.mempool_info();
+        assert_eq!(
+            vec![dear.hash(), cheap.hash()],
+            info.iter()
+                .map(|entry| entry.tx_hash.clone())
+                .collect::<Vec<Hash>>()
+        );
+        assert_eq!(Coins(5_000), info[0].fee);
+        assert_eq!(Coins(1_000), info[1].fee);
+        assert_eq!(dear.serialized_size(), info[0].size);
+        assert_eq!(5_000.0 / dear.serialized_size() as f64, info[0].fee_rate);
+        assert!(info[0].time_in_pool < 60_000);
+    }
+
+    #[test]
+    fn should_remove_pooled_transactions_mined_elsewhere() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let transaction = spend(&key, &output, &bob, Coins(1_000));
+        blockchain
+            .add_transaction_to_pool(transaction.clone())
+            .unwrap();
+
+        // Another node mines the same transaction into its own block
+        let coinbase = coinbase_to(&new_key().1, Coins::from_whole(50));
+        let block = block_on_tip(&blockchain, vec![coinbase, transaction]);
+        blockchain.aggregate_mined_block(block).unwrap();
+
+        assert_eq!(0, blockchain.pool_len());
+        assert_eq!(
+            Coins(output.1.value.units() - 1_000),
+            blockchain.balance_of(&bob)
+        );
+    }
+
+    #[test]
+    fn should_find_pooled_transaction_by_hash() {
+        let (mut blockchain, alice_key, mut transaction) = funded_chain_and_transaction();
+        transaction.sign(&alice_key);
+        let hash = transaction.hash();
+        assert!(!blockchain.mempool_contains(&hash));
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+
+        assert!(blockchain.mempool_contains(&hash));
+        assert_eq!(
+            Some(hash.clone()),
+            blockchain.mempool_transaction(&hash).map(Hashable::hash)
+        );
+        assert!(blockchain.mempool_transaction(&Hash::zero()).is_none());
+        assert_eq!(1, blockchain.mempool().len());
+    }
+
+    #[test]
+    fn should_remove_transaction_from_pool_by_hash() {
+        let (mut blockchain, alice_key, mut transaction) = funded_chain_and_transaction();
+        transaction.sign(&alice_key);
+        let hash = transaction.hash();
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+
+        assert!(!blockchain.remove_transaction_from_pool(&Hash::zero()));
+        assert!(blockchain.remove_transaction_from_pool(&hash));
+        assert_eq!(0, blockchain.pool_len());
+        assert!(!blockchain.remove_transaction_from_pool(&hash));
+    }
+
+    #[test]
+    fn should_accept_correctly_linked_blocks() {
+        let mut blockchain = regtest_chain();
+        let first = mine_block(&mut blockchain, &new_key().1);
+        let second = mine_block(&mut blockchain, &new_key().1);
+
+        assert_eq!(Hash::zero(), first.header.prev_block_hash);
+        assert_eq!(first.header.index + 1, second.header.index);
+        assert_eq!(first.header.hash, second.header.prev_block_hash);
+        assert_eq!(2, blockchain.len());
+    }
+
+    #[test]
+    fn should_reject_skipped_index() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        let mut block =
+            blockchain.create_candidate_block(0, new_key().1, Some(U256::max_value()), None);
+        block.header.index += 1;
+        block.mine();
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::InvalidBlockIndex(_)) => {}
+            other => panic!("expected invalid block index, got {:?}", other),
+        }
+        assert_eq!(1, blockchain.len());
+    }
+
+    #[test]
+    fn should_reject_wrong_previous_hash() {
+        let mut blockchain = regtest_chain();
+        let first = mine_block(&mut blockchain, &new_key().1);
+        mine_block(&mut blockchain, &new_key().1);
+        let mut block =
+            blockchain.create_candidate_block(0, new_key().1, Some(U256::max_value()), None);
+        block.header.prev_block_hash = first.header.hash;
+        block.mine();
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::PreviousHashMismatch(_)) => {}
+            other => panic!("expected previous hash mismatch, got {:?}", other),
+        }
+        assert_eq!(2, blockchain.len());
+    }
+
+    #[test]
+    fn should_compute_median_time_past() {
+        let mut blockchain = chain_with_block_interval(1_000, INITIAL_DIFFICULTY);
+        assert_eq!(1_000_000 + 5 * 1_000, blockchain.median_time_past());
+
+        // only the last MEDIAN_TIME_SPAN blocks count
+        let mut extra = chain_with_block_interval(1_000, INITIAL_DIFFICULTY).blocks;
+        for block in extra.iter_mut() {
+            block.header.timestamp += 100_000;
+        }
+        blockchain.blocks.extend(extra);
+        assert_eq!(1_100_000 + 4 * 1_000, blockchain.median_time_past());
+        assert_eq!(0, regtest_chain().median_time_past());
+    }
+
+    #[test]
+    fn should_reject_backdated_block() {
+        let mut blockchain = regtest_chain();
+        for _ in 0..3 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+        let mut block =
+            blockchain.create_candidate_block(0, new_key().1, Some(U256::max_value()), None);
+        block.header.timestamp = blockchain.median_time_past();
+        block.mine();
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::InvalidTimestamp(_)) => {}
+            other => panic!("expected invalid timestamp, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_reject_easy_block_on_retargeted_chain() {
+        let easy = U256::max_value() >> 2;
+        let mut blockchain = Blockchain::with_params(ChainParams {
+            initial_difficulty: easy,
+            retarget: true,
+            ..ChainParams::regtest()
+        });
+        for _ in 0..RETARGET_INTERVAL {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+        // Blocks this fast make the next target as hard as it may get
+        let hard = blockchain.next_difficulty();
+        assert_eq!(easy / 4, hard);
+
+        let mut block = blockchain.create_candidate_block(u64::MAX, new_key().1, Some(easy), None);
+        block.mine();
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::DifficultyMismatch { expected, found }) => {
+                assert_eq!(hard, expected);
+                assert_eq!(easy, found);
+            }
+            other => panic!("expected difficulty mismatch, got {:?}", other),
+        }
+        assert_eq!(RETARGET_INTERVAL, blockchain.len());
+
+        let block = mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(hard, block.header.difficulty);
+        assert!(blockchain.validate().is_ok());
+    }
+
+    #[test]
+    fn should_reject_far_future_block() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        let mut block =
+            blockchain.create_candidate_block(0, new_key().1, Some(U256::max_value()), None);
+        block.header.timestamp = now() + MAX_FUTURE_BLOCK_TIME + 60_000;
+        block.mine();
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::InvalidTimestamp(_)) => {}
+            other => panic!("expected invalid timestamp, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_validate_whole_chain() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        blockchain
+            .add_transaction_to_pool(spend(&key, &output, &bob, Coins(1_000)))
+            .unwrap();
+        mine_block(&mut blockchain, &new_key().1);
+        mine_block(&mut blockchain, &new_key().1);
+        blockchain.validate().unwrap();
+
+        // Corrupt the transaction in the block that spends the coinbase
+        let position = COINBASE_MATURITY as usize;
+        blockchain.blocks[position].transactions[1].outputs[0].value = Coins::from_whole(10);
+
+        match blockchain.validate() {
+            Err(BlockChainError::InvalidBlockError { index, cause }) => {
+                assert_eq!(COINBASE_MATURITY + 1, index);
+                assert!(matches!(*cause, BlockChainError::MerkleRootMismatch(_)));
+            }
+            other => panic!("expected invalid block error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_not_trust_unspent_output_set_when_validating() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        blockchain.unspent_output.clear();
+
+        blockchain.validate().unwrap();
+    }
+
+    /// Mine `count` blocks on top of a copy of `blockchain`'s first `keep`
+    /// blocks, returning only the new blocks.
+    fn fork_blocks(blockchain: &Blockchain, keep: usize, count: usize) -> Vec<Block> {
+        let mut fork = Blockchain::replay(
+            Arc::clone(&blockchain.consensus),
+            blockchain.params.clone(),
+            blockchain.blocks[..keep].iter().cloned(),
+        )
+        .unwrap();
+        (0..count)
+            .map(|_| mine_block(&mut fork, &new_key().1))
+            .collect()
+    }
+
+    #[test]
+    fn should_notify_each_subscriber() {
+        let mut blockchain = regtest_chain();
+        let first = blockchain.subscribe();
+        let second = blockchain.subscribe();
+
+        let block = mine_block(&mut blockchain, &new_key().1);
+        for receiver in [&first, &second] {
+            assert_eq!(
+                ChainEvent::BlockAdded(block.header.hash.clone()),
+                receiver.try_recv().unwrap()
+            );
+        }
+
+        drop(second);
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let events = first.try_iter().count();
+        assert_eq!(COINBASE_MATURITY as usize, events);
+        let transaction = spend(&key, &output, &new_key().1, Coins(1_000));
+        let hash = transaction.hash();
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        assert_eq!(
+            ChainEvent::TransactionAdded(hash),
+            first.try_recv().unwrap()
+        );
+        assert_eq!(1, blockchain.subscribers.len());
+    }
+
+    #[test]
+    fn should_notify_reorg_depth() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        mine_block(&mut blockchain, &new_key().1);
+        let competing = fork_blocks(&blockchain, 1, 2);
+        let events = blockchain.subscribe();
+
+        assert!(blockchain.try_reorg(&competing).unwrap());
+        assert_eq!(
+            vec![
+                ChainEvent::Reorg { depth: 1 },
+                ChainEvent::BlockAdded(competing[0].header.hash.clone()),
+                ChainEvent::BlockAdded(competing[1].header.hash.clone()),
+            ],
+            events.try_iter().collect::<Vec<ChainEvent>>()
+        );
+    }
+
+    #[test]
+    fn should_report_chain_stats() {
+        let mut blockchain = regtest_chain();
+        let (alice_key, alice) = new_key();
+        let coinbase = coinbase_utxo(&mine_block(&mut blockchain, &alice));
+        mine_block(&mut blockchain, &new_key().1);
+
+        let stats = blockchain.stats();
+        assert_eq!(Some(2), stats.tip_height);
+        assert_eq!(2, stats.total_transactions);
+        assert_eq!(2, stats.utxo_count);
+        assert_eq!(Coins::from_whole(100), stats.total_supply);
+        assert_eq!(0, stats.mempool_size);
+
+        // Fees move to the next miner, so spending leaves the supply as is
+        mature_coinbases(&mut blockchain);
+        blockchain
+            .add_transaction_to_pool(spend(&alice_key, &coinbase, &new_key().1, Coins(1_000)))
+            .unwrap();
+        assert_eq!(1, blockchain.stats().mempool_size);
+        mine_block(&mut blockchain, &new_key().1);
+        let stats = blockchain.stats();
+        assert_eq!(
+            Coins::from_whole(50 * u64::from(stats.tip_height.unwrap())),
+            stats.total_supply
+        );
+        assert_eq!(
+            stats.tip_height.unwrap() as usize + 1,
+            stats.total_transactions
+        );
+    }
+
+    #[test]
+    fn should_audit_supply_against_issuance() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        for (key, utxo) in funded_keys(&mut blockchain, 3) {
+            blockchain
+                .add_transaction_to_pool(spend(&key, &utxo, &bob, Coins(1_000)))
+                .unwrap();
+        }
+        mine_block(&mut blockchain, &new_key().1);
+        assert!(blockchain.audit_supply().is_ok());
+        assert_eq!(
+            Coins::from_whole(50 * blockchain.len() as u64),
+            blockchain.total_supply()
+        );
+
+        let (outpoint, output) = coinbase_utxo(blockchain.latest_block().unwrap());
+        let inflated = Coins(output.value.units() + 1);
+        blockchain.unspent_output.insert(
+            outpoint,
             TxOutput {
-                address: String::from("Alice"),
-                value: 20.0,
+                value: inflated,
+                ..output
             },
-        ];
+        );
+        match blockchain.audit_supply() {
+            Err(BlockChainError::SupplyMismatch { expected, found }) => {
+                assert_eq!(Coins(expected.units() + 1), found)
+            }
+            other => panic!("expected supply mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_keep_every_coinbase_paying_one_address() {
+        let mut blockchain = regtest_chain();
+        let (_, miner) = new_key();
+        for _ in 0..200 {
+            mine_block(&mut blockchain, &miner);
+        }
+
+        assert!(blockchain.audit_supply().is_ok());
+        assert_eq!(200, blockchain.unspent_output.len());
+        assert_eq!(Coins::from_whole(50 * 200), blockchain.total_supply());
+    }
+
+    #[test]
+    fn should_reject_block_recreating_unspent_output() {
+        let mut blockchain = regtest_chain();
+        let (_, miner) = new_key();
+        mine_block(&mut blockchain, &miner);
+        let coinbase = blockchain.latest_block().unwrap().transactions[0].clone();
+        let (outpoint, _) = coinbase_utxo(blockchain.latest_block().unwrap());
+
+        let block = block_on_tip(&blockchain, vec![coinbase]);
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::DuplicateOutput(duplicate)) => assert_eq!(outpoint, duplicate),
+            other => panic!("expected duplicate output, got {:?}", other),
+        }
+        assert_eq!(1, blockchain.len());
+        assert!(blockchain.audit_supply().is_ok());
+    }
+
+    #[test]
+    fn should_measure_block_work() {
+        assert_eq!(U256::one(), Blockchain::block_work(U256::max_value()));
+        assert_eq!(U256::from(2), Blockchain::block_work(U256::max_value() / 2));
+        assert_eq!(U256::from(4096), Blockchain::block_work(INITIAL_DIFFICULTY));
+    }
+
+    #[test]
+    fn should_reorg_to_heavier_branch() {
+        let mut blockchain = regtest_chain();
+        let genesis = mine_block(&mut blockchain, &new_key().1);
+        let (_, stale_miner) = new_key();
+        let stale = mine_block(&mut blockchain, &stale_miner);
+        let competing = fork_blocks(&blockchain, 1, 2);
+
+        assert!(blockchain.try_reorg(&competing).unwrap());
+
+        assert_eq!(3, blockchain.len());
+        assert_eq!(genesis.header.hash, blockchain.blocks[0].header.hash);
+        assert_eq!(
+            competing[1].header.hash,
+            blockchain.latest_block().unwrap().header.hash
+        );
+        assert!(blockchain.get_block_by_hash(&stale.header.hash).is_none());
+        assert_eq!(Coins::ZERO, blockchain.balance_of(&stale_miner));
+        assert_eq!(U256::from(3), blockchain.cumulative_work());
+        blockchain.validate().unwrap();
+    }
+
+    #[test]
+    fn should_return_transactions_of_abandoned_blocks_to_pool() {
+        let mut blockchain = regtest_chain();
+        let (key, utxo) = funded_keys(&mut blockchain, 1).remove(0);
+        let (_, bob) = new_key();
+        let transaction = spend(&key, &utxo, &bob, Coins(1_000));
+        let tx_hash = transaction.hash();
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        let stale = mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(2, stale.transactions.len());
+        assert_eq!(0, blockchain.pool_len());
+
+        let competing = fork_blocks(&blockchain, blockchain.len() - 1, 2);
+        assert!(blockchain.try_reorg(&competing).unwrap());
+
+        assert!(blockchain.get_transaction(&tx_hash).is_none());
+        assert!(blockchain.mempool_contains(&tx_hash));
+        assert_eq!(1, blockchain.pool_len());
+        assert_eq!(Coins::ZERO, blockchain.balance_of(&bob));
+        // The readmitted transaction can be mined on the new branch
+        mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(Some(1), blockchain.confirmations_of(&tx_hash));
+        assert_eq!(
+            Coins(utxo.1.value.units() - 1_000),
+            blockchain.balance_of(&bob)
+        );
+    }
+
+    #[test]
+    fn should_keep_chain_with_equal_or_more_work() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        let tip = mine_block(&mut blockchain, &new_key().1);
+        let competing = fork_blocks(&blockchain, 1, 1);
+
+        assert!(!blockchain.try_reorg(&competing).unwrap());
+        assert!(!blockchain.try_reorg(&[]).unwrap());
+        assert_eq!(
+            tip.header.hash,
+            blockchain.latest_block().unwrap().header.hash
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_competing_branch() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        let tip = mine_block(&mut blockchain, &new_key().1);
+        let mut competing = fork_blocks(&blockchain, 1, 2);
+        competing[1].transactions[0].outputs[0].value = Coins::from_whole(500);
+
+        assert!(blockchain.try_reorg(&competing).is_err());
+        assert_eq!(
+            tip.header.hash,
+            blockchain.latest_block().unwrap().header.hash
+        );
+        assert_eq!(2, blockchain.len());
+    }
+
+    #[test]
+    fn should_restore_unspent_outputs_after_failed_reorg() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        mine_block(&mut blockchain, &new_key().1);
+        let unspent_output = blockchain.unspent_output.clone();
+        let mut competing = fork_blocks(&blockchain, 1, 3);
+        competing[2].transactions[0].outputs[0].value = Coins::from_whole(500);
+
+        assert!(matches!(
+            blockchain.try_reorg(&competing),
+            Err(BlockChainError::InvalidBlockError { .. })
+        ));
+        assert_eq!(unspent_output, blockchain.unspent_output);
+        blockchain.validate().unwrap();
+    }
+
+    #[test]
+    fn should_connect_orphan_once_parent_arrives() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        let branch = fork_blocks(&blockchain, 1, 2);
+
+        blockchain.aggregate_mined_block(branch[1].clone()).unwrap();
+        assert_eq!(1, blockchain.len());
+        assert_eq!(1, blockchain.orphan_count());
+
+        blockchain.aggregate_mined_block(branch[0].clone()).unwrap();
+        assert_eq!(3, blockchain.len());
+        assert_eq!(0, blockchain.orphan_count());
+        assert_eq!(
+            branch[1].header.hash,
+            blockchain.latest_block().unwrap().header.hash
+        );
+    }
+
+    #[test]
+    fn should_reject_unmined_orphan() {
+        let mut blockchain = regtest_chain();
+        mine_block(&mut blockchain, &new_key().1);
+        let mut orphan = fork_blocks(&blockchain, 1, 2).remove(1);
+        orphan.header.difficulty = U256::zero();
+
+        assert!(blockchain.aggregate_mined_block(orphan).is_err());
+        assert_eq!(0, blockchain.orphan_count());
+    }
+
+    #[test]
+    fn should_count_confirmations() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let transaction = spend(&key, &output, &bob, Coins(1_000));
+        let tx_hash = transaction.hash();
+        assert_eq!(None, blockchain.confirmations_of(&tx_hash));
+
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        assert_eq!(Some(0), blockchain.confirmations_of(&tx_hash));
+
+        let block = mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(Some(1), blockchain.confirmations_of(&tx_hash));
+        assert_eq!(Some(1), blockchain.block_confirmations(&block.header.hash));
+
+        mine_block(&mut blockchain, &new_key().1);
+        mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(Some(3), blockchain.confirmations_of(&tx_hash));
+        assert_eq!(Some(3), blockchain.block_confirmations(&block.header.hash));
+        assert_eq!(None, blockchain.block_confirmations(&Hash::zero()));
+    }
+
+    #[test]
+    fn should_get_mined_transaction_by_hash() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let transaction = spend(&key, &output, &bob, Coins(1_000));
+        let tx_hash = transaction.hash();
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        assert!(blockchain.get_transaction(&tx_hash).is_none());
+
+        let block = mine_block(&mut blockchain, &new_key().1);
+
+        let found = blockchain.get_transaction(&tx_hash).unwrap();
+        assert_eq!(tx_hash, found.hash());
+        assert_eq!(
+            Some(&(block.header.index, 1)),
+            blockchain.tx_index.get(&tx_hash)
+        );
+        let coinbase_hash = block.transactions[0].hash();
+        assert!(blockchain.get_transaction(&coinbase_hash).is_some());
+    }
+
+    #[test]
+    fn rejected_block_is_not_indexed_by_transaction() {
+        let mut blockchain = regtest_chain();
+        let mut block =
+            blockchain.create_candidate_block(0, new_key().1, Some(U256::max_value()), None);
+        block.mine();
+        block.header.difficulty = U256::zero();
+        let coinbase_hash = block.transactions[0].hash();
+
+        assert!(blockchain.aggregate_mined_block(block).is_err());
+        assert!(blockchain.get_transaction(&coinbase_hash).is_none());
+    }
+
+    #[test]
+    fn should_reject_immature_coinbase_spend() {
+        let mut blockchain = regtest_chain();
+        let (alice_key, alice) = new_key();
+        let (_, bob) = new_key();
+        let coinbase = coinbase_utxo(&mine_block(&mut blockchain, &alice));
+        let transaction = spend(&alice_key, &coinbase, &bob, Coins(1_000));
+
+        match blockchain.add_transaction_to_pool(transaction.clone()) {
+            Err(BlockChainError::ImmatureCoinbase { .. }) => {}
+            other => panic!("expected immature coinbase, got {:?}", other),
+        }
+
+        for _ in 2..COINBASE_MATURITY {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+        assert!(matches!(
+            blockchain.add_transaction_to_pool(transaction.clone()),
+            Err(BlockChainError::ImmatureCoinbase { .. })
+        ));
+
+        mine_block(&mut blockchain, &new_key().1);
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(
+            Coins(coinbase.1.value.units() - 1_000),
+            blockchain.balance_of(&bob)
+        );
+    }
+
+    #[test]
+    fn should_spend_regular_outputs_immediately() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let (carol_key, carol) = new_key();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        blockchain
+            .add_transaction_to_pool(spend(&key, &output, &carol, Coins(1_000)))
+            .unwrap();
+        let block = mine_block(&mut blockchain, &new_key().1);
+
+        let received = block.transactions[1].spendable_outputs(HashAlgo::Sha256)[0].clone();
+        blockchain
+            .add_transaction_to_pool(spend(&carol_key, &received, &bob, Coins(1_000)))
+            .unwrap();
+    }
+
+    #[test]
+    fn should_reject_transaction_until_lock_height() {
+        let mut blockchain = regtest_chain();
+        let (_, bob) = new_key();
+        let (key, output) = funded_keys(&mut blockchain, 1).remove(0);
+        let mut transaction = spend(&key, &output, &bob, Coins(1_000));
+        transaction.lock_time = u64::from(blockchain.latest_block().unwrap().header.index) + 3;
+        transaction.sign(&key);
+
+        match blockchain.add_transaction_to_pool(transaction.clone()) {
+            Err(BlockChainError::TransactionLocked { .. }) => {}
+            other => panic!("expected locked transaction, got {:?}", other),
+        }
+        mine_block(&mut blockchain, &new_key().1);
+        assert!(matches!(
+            blockchain.add_transaction_to_pool(transaction.clone()),
+            Err(BlockChainError::TransactionLocked { .. })
+        ));
+
+        mine_block(&mut blockchain, &new_key().1);
+        blockchain.add_transaction_to_pool(transaction).unwrap();
+        let block = mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(2, block.transactions.len());
+    }
+
+    #[test]
+    fn should_iterate_blocks_in_chain_order() {
+        let mut blockchain = regtest_chain();
+        assert_eq!(None, blockchain.tip_height());
+        for _ in 0..3 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+
+        let indices = blockchain
+            .iter()
+            .map(|block| block.header.index)
+            .collect::<Vec<u32>>();
+        assert_eq!(vec![1, 2, 3], indices);
+        let mut borrowed = vec![];
+        for block in &blockchain {
+            borrowed.push(block.header.index);
+        }
+        assert_eq!(indices, borrowed);
+        assert_eq!(Some(3), blockchain.tip_height());
+    }
+
+    #[test]
+    fn should_build_block_locator() {
+        let mut blockchain = regtest_chain();
+        assert!(blockchain.block_locator().is_empty());
+        for _ in 0..30 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+
+        let indices = blockchain
+            .block_locator()
+            .iter()
+            .map(|hash| blockchain.get_block_by_hash(hash).unwrap().header.index)
+            .collect::<Vec<u32>>();
+        assert_eq!(
+            vec![30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 19, 15, 7, 1],
+            indices
+        );
+    }
+
+    #[test]
+    fn should_space_locator_exponentially() {
+        let mut blockchain = regtest_chain();
+        assert!(blockchain.locator().is_empty());
+        assert!(blockchain.tip_hash().is_none());
+        for _ in 0..20 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+
+        let locator = blockchain.locator();
+        assert_eq!(blockchain.tip_hash().as_ref(), locator.first());
+        assert_eq!(
+            blockchain.iter().next().map(|genesis| &genesis.header.hash),
+            locator.last()
+        );
+        let heights = locator
+            .iter()
+            .map(|hash| blockchain.get_block_by_hash(hash).unwrap().header.index)
+            .collect::<Vec<u32>>();
+        assert_eq!(vec![20, 19, 18, 16, 12, 4, 1], heights);
+    }
+
+    #[test]
+    fn should_find_fork_point_of_chains_sharing_a_prefix() {
+        let mut blockchain = regtest_chain();
+        for _ in 0..12 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+        let fork_point = blockchain
+            .get_block_by_index(8)
+            .unwrap()
+            .header
+            .hash
+            .clone();
+        let mut other = Blockchain::replay(
+            Arc::clone(&blockchain.consensus),
+            blockchain.params.clone(),
+            blockchain.blocks[..8].iter().cloned(),
+        )
+        .unwrap();
+        for _ in 0..5 {
+            mine_block(&mut other, &new_key().1);
+        }
+
+        assert_eq!(
+            Some(fork_point.clone()),
+            blockchain.find_common_ancestor(&other.block_locator())
+        );
+        assert_eq!(
+            Some(fork_point),
+            other.find_common_ancestor(&blockchain.block_locator())
+        );
+        // A sparse locator skips the fork point and lands below it
+        let below = blockchain.find_common_ancestor(&other.locator()).unwrap();
+        assert_eq!(
+            5,
+            blockchain.get_block_by_hash(&below).unwrap().header.index
+        );
+        assert_eq!(
+            blockchain.tip_hash(),
+            blockchain.find_common_ancestor(&blockchain.locator())
+        );
+        let unrelated = fork_blocks(&regtest_chain(), 0, 3);
+        let unrelated_locator = unrelated
+            .iter()
+            .rev()
+            .map(|block| block.header.hash.clone())
+            .collect::<Vec<Hash>>();
+        assert_eq!(None, blockchain.find_common_ancestor(&unrelated_locator));
+    }
+
+    #[test]
+    fn should_reject_oversized_block() {
+        let mut blockchain = regtest_chain();
+        let (_, miner) = new_key();
+        let coinbase = (0..MAX_BLOCK_BYTES / 50)
+            .fold(Transaction::builder(), |builder, _| {
+                builder.output(miner.clone(), Coins::ZERO)
+            })
+            .build()
+            .unwrap();
+        let mut block = Block::new(0, now(), Hash::zero(), vec![coinbase], U256::max_value());
+        block.mine();
+        assert!(block.serialized_size() > MAX_BLOCK_BYTES);
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::BlockTooLarge { .. }) => {}
+            other => panic!("expected block too large, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_halve_block_reward() {
+        assert_eq!(BLOCK_REWARD, regtest_chain().block_reward());
+        assert_eq!(BLOCK_REWARD, Blockchain::block_reward_at(0));
+        assert_eq!(
+            BLOCK_REWARD,
+            Blockchain::block_reward_at(HALVING_INTERVAL - 1)
+        );
+        assert_eq!(
+            Coins::from_whole(25),
+            Blockchain::block_reward_at(HALVING_INTERVAL)
+        );
+        assert_eq!(
+            Coins(BLOCK_REWARD.units() / 8),
+            Blockchain::block_reward_at(3 * HALVING_INTERVAL)
+        );
+        assert_eq!(
+            Coins::ZERO,
+            Blockchain::block_reward_at(40 * HALVING_INTERVAL)
+        );
+        assert_eq!(
+            Coins::ZERO,
+            Blockchain::block_reward_at(64 * HALVING_INTERVAL)
+        );
+        assert_eq!(Coins::ZERO, Blockchain::block_reward_at(usize::MAX));
+    }
+
+    #[test]
+    fn should_import_contiguous_blocks_all_or_nothing() {
+        let mut source = regtest_chain();
+        let (_, bob) = new_key();
+        let (key, utxo) = funded_keys(&mut source, 1).remove(0);
+        source
+            .add_transaction_to_pool(spend(&key, &utxo, &bob, Coins(1_000)))
+            .unwrap();
+        mine_block(&mut source, &new_key().1);
+        let blocks = source.blocks.clone();
+
+        let mut blockchain = regtest_chain();
+        let events = blockchain.subscribe();
+        assert_eq!(
+            Ok(10),
+            blockchain
+                .import_blocks(blocks[..10].to_vec())
+                .map_err(|e| e.to_string())
+        );
+        assert_eq!(10, events.try_iter().count());
+        assert_eq!(source.blocks[9].header.hash, blockchain.tip_hash().unwrap());
+
+        // A gap is refused before anything is applied
+        let mut gapped = blocks[10..20].to_vec();
+        gapped.remove(4);
+        assert!(matches!(
+            blockchain.import_blocks(gapped),
+            Err(BlockChainError::PreviousHashMismatch(_))
+        ));
+        assert_eq!(10, blockchain.len());
+
+        // An invalid last block undoes the whole run
+        let mut rest = blocks[10..].to_vec();
+        let last = rest.last_mut().unwrap();
+        last.transactions[0].outputs[0].value = Coins(u64::MAX);
+        last.header.merkle_root = Block::compute_merkle_root(&last.transactions);
+        last.mine();
+        assert!(matches!(
+            blockchain.import_blocks(rest),
+            Err(BlockChainError::InvalidBlockError { .. })
+        ));
+        assert_eq!(10, blockchain.len());
+        assert_eq!(0, events.try_iter().count());
+        assert!(blockchain.validate().is_ok());
+
+        assert_eq!(
+            Ok(blocks.len() - 10),
+            blockchain
+                .import_blocks(blocks[10..].to_vec())
+                .map_err(|e| e.to_string())
+        );
+        assert_eq!(source.tip_hash(), blockchain.tip_hash());
+        assert_eq!(source.unspent_output, blockchain.unspent_output);
+    }
+
+    #[test]
+    fn should_record_history_of_sender_and_receiver() {
+        let mut blockchain = regtest_chain();
+        let (alice_key, alice) = new_key();
+        let (_, bob) = new_key();
+        let coinbase = mine_block(&mut blockchain, &alice).transactions[0].clone();
+        mature_coinbases(&mut blockchain);
+        let mut transfer = Transaction::builder()
+            .input(coinbase_utxo(blockchain.get_block_by_index(1).unwrap()).0)
+            .output(bob.clone(), Coins::from_whole(20))
+            .output(alice.clone(), Coins::from_whole(29))
+            .build()
+            .unwrap();
+        transfer.sign(&alice_key);
         blockchain
-            .unspent_output
-            .extend(unspent_outputs.iter().map(|output| output.hash()));
-        let transaction = Transaction {
-            inputs: unspent_outputs,
-            outputs: vec![
-                TxOutput {
-                    address: String::from("Bob"),
-                    value: 25.0,
-                },
-                TxOutput {
-                    address: String::from("Bob"),
-                    value: 4.995,
-                },
-            ],
-            timestamp: now(),
+            .add_transaction_to_pool(transfer.clone())
+            .unwrap();
+        mine_block(&mut blockchain, &new_key().1);
+
+        let hashes = |blockchain: &Blockchain, address: &Address| {
+            blockchain
+                .history_of(address)
+                .into_iter()
+                .map(Transaction::hash)
+                .collect::<Vec<Hash>>()
         };
+        assert_eq!(
+            vec![coinbase.hash(), transfer.hash()],
+            hashes(&blockchain, &alice)
+        );
+        assert_eq!(vec![transfer.hash()], hashes(&blockchain, &bob));
+        assert!(hashes(&blockchain, &new_key().1).is_empty());
+
+        // Disconnecting the transfer's block drops it from both histories
+        let competing = fork_blocks(&blockchain, blockchain.len() - 1, 2);
+        assert!(blockchain.try_reorg(&competing).unwrap());
+        assert_eq!(vec![coinbase.hash()], hashes(&blockchain, &alice));
+        assert!(hashes(&blockchain, &bob).is_empty());
+    }
+
+    #[test]
+    fn should_accept_block_mined_from_template() {
+        let mut blockchain = Blockchain::with_params(ChainParams {
+            initial_difficulty: U256::max_value() >> 8,
+            ..ChainParams::regtest()
+        });
+        let funded = funded_keys(&mut blockchain, 1);
+        let (key, utxo) = &funded[0];
+        let transaction = spend(key, utxo, &new_key().1, Coins::from_whole(1));
+        blockchain
+            .add_transaction_to_pool(transaction.clone())
+            .unwrap();
+        let (_, miner) = new_key();
+
+        let template = blockchain.get_block_template(&miner);
+        let again = blockchain.get_block_template(&miner);
+        assert_eq!(template.merkle_root, again.merkle_root);
+        assert_eq!(template.coinbase.hash(), again.coinbase.hash());
+        assert!(template.timestamp >= template.min_timestamp);
+        assert_eq!(
+            vec![transaction.hash()],
+            template
+                .transactions
+                .iter()
+                .map(Transaction::hash)
+                .collect::<Vec<Hash>>()
+        );
+        // The template leaves the pool as it is
+        assert_eq!(1, blockchain.mempool().len());
+
+        let nonce = (0..)
+            .find(|nonce| check_difficulty(&template.header(*nonce).hash, template.target))
+            .unwrap();
+        let tip = blockchain.len();
+        blockchain.submit_block(&template, nonce).unwrap();
+        assert_eq!(tip + 1, blockchain.len());
+        assert_eq!(
+            template.header(nonce),
+            blockchain
+                .get_block_by_index(template.index)
+                .unwrap()
+                .header
+        );
+        assert!(blockchain.mempool().is_empty());
+        assert_eq!(
+            template.coinbase.outputs[0].value,
+            blockchain.balance_of(&miner)
+        );
+        assert!(blockchain.validate().is_ok());
+    }
+
+    #[test]
+    fn should_reject_block_conflicting_with_checkpoint() {
+        let mut honest = regtest_chain();
+        let blocks = (0..3)
+            .map(|_| mine_block(&mut honest, &new_key().1))
+            .collect::<Vec<Block>>();
+        let checkpoint = blocks[1].header.hash.clone();
+        let mut blockchain = Blockchain::with_params(ChainParams {
+            checkpoints: vec![(2, checkpoint.clone())],
+            ..ChainParams::mainnet()
+        });
+        blockchain.aggregate_mined_block(blocks[0].clone()).unwrap();
+
+        let mut conflicting = blockchain.snapshot().create_candidate_block(
+            u64::MAX,
+            new_key().1,
+            Some(U256::max_value()),
+            None,
+        );
+        conflicting.mine();
+        match blockchain.aggregate_mined_block(conflicting.clone()) {
+            Err(BlockChainError::CheckpointMismatch {
+                height,
+                expected,
+                found,
+            }) => {
+                assert_eq!(2, height);
+                assert_eq!(checkpoint, expected);
+                assert_eq!(conflicting.header.hash, found);
+            }
+            other => panic!("expected checkpoint mismatch, got {:?}", other),
+        }
+        for block in &blocks[1..] {
+            blockchain.aggregate_mined_block(block.clone()).unwrap();
+        }
+
+        // A heavier branch replacing the checkpointed block is refused
+        let competing = fork_blocks(&honest, 1, 3);
+        assert!(matches!(
+            blockchain.try_reorg(&competing),
+            Err(BlockChainError::CheckpointMismatch { height: 2, .. })
+        ));
+        assert_eq!(Some(blocks[2].header.hash.clone()), blockchain.tip_hash());
+
+        // So is a shorter yet heavier one that would disconnect it
+        let mut guarded = Blockchain::with_params(ChainParams {
+            checkpoints: vec![(3, blocks[2].header.hash.clone())],
+            ..ChainParams::regtest()
+        });
+        for block in &blocks {
+            guarded.aggregate_mined_block(block.clone()).unwrap();
+        }
+        let mut coinbase = coinbase_to(&new_key().1, BLOCK_REWARD);
+        coinbase.lock_time = 2;
+        let mut heavier = Block::new(
+            2,
+            blocks[0].header.timestamp + 1,
+            blocks[0].header.hash.clone(),
+            vec![coinbase],
+            U256::max_value() / 8,
+        );
+        heavier.mine();
+        match guarded.try_reorg(&[heavier]) {
+            Err(BlockChainError::CheckpointMismatch {
+                height,
+                expected,
+                found,
+            }) => {
+                assert_eq!(3, height);
+                assert_eq!(blocks[2].header.hash, expected);
+                assert_eq!(Hash::zero(), found);
+            }
+            other => panic!("expected checkpoint mismatch, got {:?}", other),
+        }
+        assert_eq!(Some(blocks[2].header.hash.clone()), guarded.tip_hash());
+    }
+
+    #[test]
+    fn should_report_output_spent_once_mined() {
+        let mut blockchain = regtest_chain();
+        let (key, utxo) = funded_keys(&mut blockchain, 1).remove(0);
+        let (outpoint, output) = &utxo;
+        assert!(blockchain.is_unspent(&output.hash()));
+        assert_eq!(Some(output), blockchain.get_utxo(outpoint));
+
+        let transaction = spend(&key, &utxo, &new_key().1, Coins(1_000));
+        blockchain
+            .add_transaction_to_pool(transaction.clone())
+            .unwrap();
+        // Pooled spends leave the output in the set until they are mined
+        assert!(blockchain.get_utxo(outpoint).is_some());
+        mine_block(&mut blockchain, &new_key().1);
+
+        assert!(!blockchain.is_unspent(&output.hash()));
+        assert_eq!(None, blockchain.get_utxo(outpoint));
+        let created = &transaction.spendable_outputs(HashAlgo::Sha256)[0];
+        assert_eq!(Some(&created.1), blockchain.get_utxo(&created.0));
+        assert!(blockchain.is_unspent(&created.1.hash()));
+    }
+
+    #[test]
+    fn should_order_block_transactions_canonically() {
+        let mut blockchain = regtest_chain();
+        let funded = funded_keys(&mut blockchain, 3);
+        let (bob_key, bob) = new_key();
+        let parent = spend(&funded[0].0, &funded[0].1, &bob, Coins(1_000));
+        let child = spend(
+            &bob_key,
+            &parent.spendable_outputs(HashAlgo::Sha256)[0],
+            &new_key().1,
+            Coins(1_000),
+        );
+        let mut transactions = vec![parent.clone(), child.clone()];
+        for (key, utxo) in &funded[1..] {
+            transactions.push(spend(key, utxo, &new_key().1, Coins(1_000)));
+        }
+        let block = block_on_tip(
+            &blockchain,
+            iter::once(coinbase_to(&new_key().1, BLOCK_REWARD))
+                .chain(transactions.iter().cloned())
+                .collect(),
+        );
+
+        // By hash, except that the child follows its parent
+        let hashes = block.transactions[1..]
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<Hash>>();
+        let position = |hash: &Hash| hashes.iter().position(|other| other == hash).unwrap();
+        assert!(position(&parent.hash()) < position(&child.hash()));
+        let mut independent = hashes
+            .iter()
+            .filter(|hash| **hash != child.hash())
+            .cloned()
+            .collect::<Vec<Hash>>();
+        let listed = independent.clone();
+        independent.sort();
+        assert_eq!(independent, listed);
+
+        // Pooled in another order, the candidate lists them the same way
+        let mut snapshot = blockchain.snapshot();
+        for transaction in transactions[2..].iter().rev().chain(&transactions[..2]) {
+            snapshot
+                .add_transaction_to_pool(transaction.clone())
+                .unwrap();
+        }
+        let candidate =
+            snapshot.create_candidate_block(u64::MAX, new_key().1, Some(U256::max_value()), None);
+        assert_eq!(
+            hashes,
+            candidate.transactions[1..]
+                .iter()
+                .map(Transaction::hash)
+                .collect::<Vec<Hash>>()
+        );
+
+        let mut shuffled = block.transactions.clone();
+        shuffled[1..].reverse();
+        let expected = shuffled[1].hash();
+        match blockchain.aggregate_mined_block(block_on_tip_in_order(&blockchain, shuffled)) {
+            Err(BlockChainError::InvalidTransactionOrder(hash)) => assert_eq!(expected, hash),
+            other => panic!("expected invalid transaction order, got {:?}", other),
+        }
+        blockchain.aggregate_mined_block(block).unwrap();
+    }
 
+    #[test]
+    fn should_restore_state_after_rolling_back_a_block() {
+        let mut blockchain = regtest_chain();
+        let (key, utxo) = funded_keys(&mut blockchain, 1).remove(0);
+        let (_, bob) = new_key();
+        let transaction = spend(&key, &utxo, &bob, Coins(1_000));
         blockchain.add_transaction_to_pool(transaction).unwrap();
-        assert_eq!(1, blockchain.transaction_pool.len());
-        assert_eq!(2, blockchain.unspent_output.len());
+
+        let state = |blockchain: &Blockchain| {
+            (
+                serde_json::to_vec(&blockchain.blocks).unwrap(),
+                blockchain.unspent_output.clone(),
+                blockchain.spent_by.clone(),
+                blockchain.tx_index.clone(),
+                blockchain.block_positions.clone(),
+                blockchain.coinbase_heights.clone(),
+                blockchain.address_history.clone(),
+                blockchain.fee_history.clone(),
+            )
+        };
+        let before = state(&blockchain);
+        let block = mine_block(&mut blockchain, &new_key().1);
+        assert!(blockchain.balance_of(&bob) > Coins::ZERO);
+
+        let removed = blockchain.rollback_block().unwrap();
+        assert_eq!(block.header, removed.header);
+        assert!(before == state(&blockchain));
+        assert_eq!(Coins::ZERO, blockchain.balance_of(&bob));
+        assert_eq!(Some(&utxo.1), blockchain.get_utxo(&utxo.0));
+
+        while blockchain.len() > 1 {
+            blockchain.rollback_block().unwrap();
+        }
+        assert!(matches!(
+            blockchain.rollback_block(),
+            Err(BlockChainError::InvalidBlockIndex(_))
+        ));
+        assert_eq!(1, blockchain.len());
+    }
+
+    #[test]
+    fn should_restore_spent_outputs_from_undo_data() {
+        let mut blockchain = regtest_chain();
+        let funded = funded_keys(&mut blockchain, 2);
+        for (key, utxo) in &funded {
+            let transaction = spend(key, utxo, &new_key().1, Coins(1_000));
+            blockchain.add_transaction_to_pool(transaction).unwrap();
+        }
+        let block = mine_block(&mut blockchain, &new_key().1);
+
+        let undo = blockchain.undo_data(&block.header.hash).unwrap();
+        let expected = block.transactions[1..]
+            .iter()
+            .map(|transaction| {
+                let outpoint = transaction.outpoints().remove(0);
+                let (_, utxo) = funded.iter().find(|(_, utxo)| utxo.0 == outpoint).unwrap();
+                utxo.clone()
+            })
+            .collect::<Vec<(OutPoint, TxOutput)>>();
+        assert_eq!(expected, undo);
+        for (outpoint, _) in &undo {
+            assert_eq!(None, blockchain.get_utxo(outpoint));
+        }
+
+        blockchain.rollback_block().unwrap();
+        for (outpoint, output) in undo {
+            assert_eq!(Some(&output), blockchain.get_utxo(&outpoint));
+        }
+        assert_eq!(None, blockchain.undo_data(&block.header.hash));
+    }
+
+    #[test]
+    fn should_hash_candidates_with_different_tags_differently() {
+        let mut blockchain = regtest_chain();
+        let (_, miner) = new_key();
+        let candidate = |tag| {
+            let mut block = blockchain.snapshot().create_candidate_block(
+                u64::MAX,
+                miner.clone(),
+                Some(U256::max_value()),
+                Some(tag),
+            );
+            // Same time and nonce, so only the tag tells them apart
+            block.header.timestamp = 0;
+            block.transactions[0].timestamp = 0;
+            block.set_coinbase_data(tag.as_bytes().to_vec());
+            block
+        };
+        let first = candidate("pool one");
+        let second = candidate("pool two");
+        assert_eq!(Some(&b"pool one"[..]), first.coinbase_data());
+        assert_ne!(first.header.merkle_root, second.header.merkle_root);
+        assert_ne!(first.compute_hash(), second.compute_hash());
+
+        // Changing the data as an extra nonce gives new hashes to try
+        let mut block = first.clone();
+        block.set_coinbase_data([&b"pool one"[..], &1u64.to_le_bytes()].concat());
+        assert_eq!(2, block.transactions[0].outputs.len());
+        assert_ne!(first.compute_hash(), block.compute_hash());
+        block.header.timestamp = now();
+        block.mine();
+        blockchain.aggregate_mined_block(block).unwrap();
+    }
+
+    #[test]
+    fn should_track_identical_outputs_of_different_transactions_apart() {
+        let mut blockchain = regtest_chain();
+        let funded = funded_keys(&mut blockchain, 2);
+        let (bob_key, bob) = new_key();
+        let payments = funded
+            .iter()
+            .map(|(key, utxo)| spend(key, utxo, &bob, Coins(1_000)))
+            .collect::<Vec<Transaction>>();
+        let outputs = payments
+            .iter()
+            .map(|payment| payment.spendable_outputs(HashAlgo::Sha256).remove(0))
+            .collect::<Vec<(OutPoint, TxOutput)>>();
+        assert_eq!(outputs[0].1, outputs[1].1);
+        assert_ne!(outputs[0].0, outputs[1].0);
+        for payment in payments {
+            blockchain.add_transaction_to_pool(payment).unwrap();
+        }
+        mine_block(&mut blockchain, &new_key().1);
+        let value = outputs[0].1.value;
+        assert_eq!(
+            value.checked_add(value).unwrap(),
+            blockchain.balance_of(&bob)
+        );
+
+        // Spending one leaves the other unspent
+        blockchain
+            .add_transaction_to_pool(spend(&bob_key, &outputs[0], &new_key().1, Coins(1_000)))
+            .unwrap();
+        mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(None, blockchain.get_utxo(&outputs[0].0));
+        assert_eq!(Some(&outputs[1].1), blockchain.get_utxo(&outputs[1].0));
+        assert_eq!(value, blockchain.balance_of(&bob));
+        blockchain
+            .add_transaction_to_pool(spend(&bob_key, &outputs[1], &new_key().1, Coins(1_000)))
+            .unwrap();
+        mine_block(&mut blockchain, &new_key().1);
+        assert_eq!(Coins::ZERO, blockchain.balance_of(&bob));
+    }
+
+    #[test]
+    fn should_mine_low_fee_parent_with_high_fee_child() {
+        let mut blockchain = regtest_chain();
+        let funded = funded_keys(&mut blockchain, 3);
+        let (bob_key, bob) = new_key();
+        let parent = spend(&funded[0].0, &funded[0].1, &bob, Coins(1_000));
+        let child = spend(
+            &bob_key,
+            &parent.spendable_outputs(HashAlgo::Sha256)[0],
+            &new_key().1,
+            Coins(500_000),
+        );
+        let others = funded[1..]
+            .iter()
+            .map(|(key, utxo)| spend(key, utxo, &new_key().1, Coins(50_000)))
+            .collect::<Vec<Transaction>>();
+        for transaction in [parent.clone(), child.clone()].into_iter().chain(others) {
+            blockchain.add_transaction_to_pool(transaction).unwrap();
+        }
+        // The child can spend its parent's output while both wait
+        assert_eq!(Coins(500_000), blockchain.fee_of(&child));
+
+        // Room for two: the others each pay more than the parent, but the
+        // child pays enough for both
+        let weight = parent.weight();
+        let mut candidate = blockchain.create_candidate_block(
+            2 * weight,
+            new_key().1,
+            Some(U256::max_value()),
+            None,
+        );
+        assert_eq!(
+            vec![parent.hash(), child.hash()],
+            candidate.transactions[1..]
+                .iter()
+                .map(Transaction::hash)
+                .collect::<Vec<Hash>>()
+        );
+        candidate.mine();
+        blockchain.aggregate_mined_block(candidate).unwrap();
+        assert_eq!(2, blockchain.mempool().len());
+    }
+
+    #[test]
+    fn should_replace_pooled_transaction_paying_a_higher_fee_rate() {
+        let mut blockchain = Blockchain::with_params(ChainParams {
+            replace_by_fee: true,
+            ..ChainParams::regtest()
+        });
+        let (key, utxo) = funded_keys(&mut blockchain, 1).remove(0);
+        let mut without_rbf = blockchain.snapshot();
+        without_rbf.params.replace_by_fee = false;
+        let (bob_key, bob) = new_key();
+        let original = spend(&key, &utxo, &bob, Coins(2_000));
+        let child = spend(
+            &bob_key,
+            &original.spendable_outputs(HashAlgo::Sha256)[0],
+            &new_key().1,
+            Coins(1_000),
+        );
+        blockchain
+            .add_transaction_to_pool(original.clone())
+            .unwrap();
+        blockchain.add_transaction_to_pool(child).unwrap();
+
+        let replacement = spend(&key, &utxo, &new_key().1, Coins(10_000));
+        blockchain
+            .add_transaction_to_pool(replacement.clone())
+            .unwrap();
+        // The child lost its input along with the original
+        assert_eq!(
+            vec![replacement.hash()],
+            blockchain
+                .mempool()
+                .iter()
+                .map(Transaction::hash)
+                .collect::<Vec<Hash>>()
+        );
+
+        // Without the flag the first transaction stays
+        without_rbf.add_transaction_to_pool(original).unwrap();
+        assert!(matches!(
+            without_rbf.add_transaction_to_pool(replacement.clone()),
+            Err(BlockChainError::DoubleSpendingError(_))
+        ));
+        mine_block(&mut blockchain, &new_key().1);
+        assert!(blockchain.get_transaction(&replacement.hash()).is_some());
+    }
+
+    #[test]
+    fn should_reject_conflicting_transaction_paying_a_lower_fee_rate() {
+        let mut blockchain = Blockchain::with_params(ChainParams {
+            replace_by_fee: true,
+            ..ChainParams::regtest()
+        });
+        let (key, utxo) = funded_keys(&mut blockchain, 1).remove(0);
+        let original = spend(&key, &utxo, &new_key().1, Coins(10_000));
+        blockchain
+            .add_transaction_to_pool(original.clone())
+            .unwrap();
+
+        let cheaper = spend(&key, &utxo, &new_key().1, Coins(2_000));
+        match blockchain.add_transaction_to_pool(cheaper) {
+            Err(BlockChainError::DoubleSpendingError(message)) => {
+                assert!(message.contains("higher fee rate"))
+            }
+            other => panic!("expected double spend, got {:?}", other),
+        }
+        assert_eq!(1, blockchain.mempool().len());
+        assert!(blockchain.mempool_contains(&original.hash()));
+    }
+
+    #[test]
+    fn should_keep_replaced_transaction_when_full_pool_rejects_replacement() {
+        let mut blockchain = Blockchain::with_params(ChainParams {
+            replace_by_fee: true,
+            ..ChainParams::regtest()
+        });
+        let mut funded = funded_keys(&mut blockchain, 2);
+        let (key, utxo) = funded.remove(0);
+        let original = spend(&key, &utxo, &new_key().1, Coins(2_000));
+        let other = spend(&funded[0].0, &funded[0].1, &new_key().1, Coins(50_000));
+        blockchain
+            .add_transaction_to_pool(original.clone())
+            .unwrap();
+        blockchain.add_transaction_to_pool(other.clone()).unwrap();
+        blockchain.set_max_pool_size(1);
+
+        // Outbids the original but not the transaction it would evict
+        let replacement = spend(&key, &utxo, &new_key().1, Coins(10_000));
+        match blockchain.add_transaction_to_pool(replacement) {
+            Err(BlockChainError::MempoolFull(_)) => {}
+            other => panic!("expected mempool full, got {:?}", other),
+        }
+        assert_eq!(
+            vec![original.hash(), other.hash()],
+            blockchain
+                .mempool()
+                .iter()
+                .map(Transaction::hash)
+                .collect::<Vec<Hash>>()
+        );
+    }
+
+    #[test]
+    fn should_reject_block_submitted_twice() {
+        let mut blockchain = regtest_chain();
+        let block = mine_block(&mut blockchain, &new_key().1);
+
+        match blockchain.aggregate_mined_block(block.clone()) {
+            Err(BlockChainError::DuplicateBlock(hash)) => assert_eq!(block.header.hash, hash),
+            other => panic!("expected duplicate block, got {:?}", other),
+        }
+        assert_eq!(1, blockchain.len());
+        assert!(blockchain.validate().is_ok());
+    }
+
+    #[test]
+    fn should_pay_reward_of_chain_params() {
+        let params = ChainParams {
+            block_reward: Coins::from_whole(8),
+            halving_interval: 2,
+            ..ChainParams::testnet()
+        };
+        let mut blockchain = Blockchain::with_params(params);
+        let rewards = (0..3)
+            .map(|_| mine_block(&mut blockchain, &new_key().1).transactions[0].outputs[0].value)
+            .collect::<Vec<Coins>>();
+        assert_eq!(
+            vec![
+                Coins::from_whole(8),
+                Coins::from_whole(8),
+                Coins::from_whole(4)
+            ],
+            rewards
+        );
+        assert!(blockchain.validate().is_ok());
+        assert!(blockchain.audit_supply().is_ok());
+
+        // A coinbase worth more than the testnet reward is rejected
+        let mut block = blockchain.create_candidate_block(u64::MAX, new_key().1, None, None);
+        block.transactions[0].outputs[0].value = Coins::from_whole(8);
+        block.header.merkle_root = Block::compute_merkle_root(&block.transactions);
+        block.mine();
+        assert!(matches!(
+            blockchain.aggregate_mined_block(block),
+            Err(BlockChainError::InvalidCoinbaseValue(_))
+        ));
+    }
+
+    #[test]
+    fn should_reject_overpaying_coinbase() {
+        let mut blockchain = regtest_chain();
+        let mut block =
+            blockchain.create_candidate_block(0, new_key().1, Some(U256::max_value()), None);
+        block.transactions[0].outputs[0].value = Coins::from_whole(51);
+        block.header.merkle_root = Block::compute_merkle_root(&block.transactions);
+        block.mine();
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::InvalidCoinbaseValue(_)) => {}
+            other => panic!("expected invalid coinbase value, got {:?}", other),
+        }
+        assert_eq!(0, blockchain.len());
+    }
+
+    #[test]
+    fn should_sort_utxos_by_value_descending() {
+        let (blockchain, alice_key, _) = funded_chain_and_transaction();
+
+        let values = blockchain
+            .utxos_for(&address_of(&alice_key))
+            .into_iter()
+            .map(|(_, output)| output.value)
+            .collect::<Vec<Coins>>();
+        assert_eq!(vec![Coins::from_whole(20), Coins::from_whole(10)], values);
+    }
+
+    #[test]
+    fn should_reject_tampered_output() {
+        let (mut blockchain, alice_key, mut transaction) = funded_chain_and_transaction();
+        transaction.sign(&alice_key);
+        transaction.outputs[0].value = Coins::from_whole(24);
+
+        match blockchain.add_transaction_to_pool(transaction) {
+            Err(BlockChainError::InvalidSignatureError(_)) => {}
+            other => panic!("expected invalid signature, got {:?}", other),
+        }
+        assert_eq!(0, blockchain.transaction_pool.len());
+    }
+
+    #[test]
+    fn should_reject_missing_signature() {
+        let (mut blockchain, _, transaction) = funded_chain_and_transaction();
+
+        match blockchain.add_transaction_to_pool(transaction) {
+            Err(BlockChainError::InvalidSignatureError(_)) => {}
+            other => panic!("expected invalid signature, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_reject_signature_from_other_key() {
+        let (mut blockchain, _, mut transaction) = funded_chain_and_transaction();
+        let (mallory_key, _) = new_key();
+        transaction.sign(&mallory_key);
+
+        match blockchain.add_transaction_to_pool(transaction) {
+            Err(BlockChainError::InvalidSignatureError(_)) => {}
+            other => panic!("expected invalid signature, got {:?}", other),
+        }
     }
 
     #[test]
     fn should_create_candidate_block() {
-        let mut blockchain: Blockchain = Blockchain::new();
-        let block = blockchain.create_candidate_block(5, String::from("Alice"), U256::max_value());
+        let mut blockchain: Blockchain = regtest_chain();
+        let block =
+            blockchain.create_candidate_block(5, new_key().1, Some(U256::max_value()), None);
         println!("{:?}", block);
     }
+
+    #[test]
+    fn should_create_deterministic_genesis() {
+        let (_, miner) = new_key();
+        let difficulty =
+            U256::from_str("0x0fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+                .unwrap();
+        let mut first = Blockchain::with_genesis(miner.clone(), difficulty);
+        let second = Blockchain::with_genesis(miner.clone(), difficulty);
+
+        let genesis = first.latest_block().unwrap().clone();
+        assert_eq!(0, genesis.header.index);
+        assert_eq!(Hash::zero(), genesis.header.prev_block_hash);
+        assert_eq!(
+            genesis.header.hash,
+            second.latest_block().unwrap().header.hash
+        );
+        assert_eq!(1, first.unspent_output.len());
+
+        let candidate = first.create_candidate_block(0, miner, Some(difficulty), None);
+        assert_eq!(1, candidate.header.index);
+        assert_eq!(genesis.header.hash, candidate.header.prev_block_hash);
+    }
+
+    #[test]
+    fn should_get_block_by_index() {
+        let mut blockchain: Blockchain = regtest_chain();
+        assert!(blockchain.latest_block().is_none());
+        assert!(blockchain.get_block_by_index(0).is_none());
+
+        for _ in 0..2 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+
+        assert_eq!(1, blockchain.get_block_by_index(1).unwrap().header.index);
+        assert_eq!(2, blockchain.get_block_by_index(2).unwrap().header.index);
+        assert_eq!(2, blockchain.latest_block().unwrap().header.index);
+        assert!(blockchain.get_block_by_index(0).is_none());
+        assert!(blockchain.get_block_by_index(3).is_none());
+        assert!(blockchain.get_block_by_index(u32::MAX).is_none());
+    }
+
+    #[test]
+    fn should_get_block_by_hash() {
+        let mut blockchain: Blockchain = regtest_chain();
+        let mut hashes = vec![];
+        for _ in 0..2 {
+            hashes.push(mine_block(&mut blockchain, &new_key().1).header.hash);
+        }
+
+        assert_eq!(
+            1,
+            blockchain
+                .get_block_by_hash(&hashes[0])
+                .unwrap()
+                .header
+                .index
+        );
+        assert_eq!(
+            2,
+            blockchain
+                .get_block_by_hash(&hashes[1])
+                .unwrap()
+                .header
+                .index
+        );
+        assert!(blockchain.get_block_by_hash(&Hash::zero()).is_none());
+    }
+
+    #[test]
+    fn should_round_trip_through_file() {
+        let mut blockchain: Blockchain = regtest_chain();
+        for _ in 0..2 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+        let path = std::env::temp_dir().join(format!("ccore-round-trip-{}.json", now()));
+
+        blockchain.save_to_file(&path).unwrap();
+        let loaded = Blockchain::load_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(blockchain.len(), loaded.len());
+        assert_eq!(blockchain.unspent_output, loaded.unspent_output);
+        for (original, restored) in blockchain.blocks.iter().zip(loaded.blocks.iter()) {
+            assert_eq!(original.header.hash, restored.header.hash);
+            assert_eq!(original.transactions.len(), restored.transactions.len());
+        }
+        let tip_hash = &blockchain.latest_block().unwrap().header.hash;
+        assert!(loaded.get_block_by_hash(tip_hash).is_some());
+    }
+
+    #[test]
+    fn should_load_chain_under_its_own_params_and_consensus() {
+        let path = std::env::temp_dir().join(format!("ccore-params-{}.json", now()));
+        // Mainnet would have retargeted these blocks
+        let mut blockchain = regtest_chain();
+        for _ in 0..RETARGET_INTERVAL + 2 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+        blockchain.save_to_file(&path).unwrap();
+        let loaded = Blockchain::load_from_file(&path).unwrap();
+        assert_eq!(&ChainParams::regtest(), loaded.params());
+        assert_eq!(blockchain.len(), loaded.len());
+
+        // Files without params hold mainnet chains
+        let blocks = serde_json::json!({ "blocks": &blockchain.blocks[..3] });
+        std::fs::write(&path, blocks.to_string()).unwrap();
+        let loaded = Blockchain::load_from_file(&path).unwrap();
+        assert_eq!(&ChainParams::mainnet(), loaded.params());
+        assert_eq!(3, loaded.len());
+
+        let mut unmined = Blockchain::with_consensus(Box::new(AlwaysValid));
+        for _ in 0..2 {
+            let mut block = unmined.create_candidate_block(u64::MAX, new_key().1, None, None);
+            unmined.consensus().prepare_candidate(&mut block);
+            unmined.aggregate_mined_block(block).unwrap();
+        }
+        unmined.save_to_file(&path).unwrap();
+        assert!(Blockchain::load_from_file(&path).is_err());
+        let loaded = Blockchain::load_from_file_with(&path, Box::new(AlwaysValid)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(unmined.tip_hash(), loaded.tip_hash());
+    }
+
+    #[test]
+    fn should_refuse_to_load_blocks_that_do_not_link() {
+        let mut blockchain = regtest_chain();
+        for _ in 0..4 {
+            mine_block(&mut blockchain, &new_key().1);
+        }
+        let mut broken = blockchain.snapshot();
+        broken.blocks.remove(1);
+
+        // The block after the gap is reported rather than kept as an orphan
+        match broken.validate() {
+            Err(BlockChainError::InvalidBlockError { index, .. }) => {
+                assert_eq!(broken.blocks[1].header.index, index)
+            }
+            other => panic!("expected invalid block error, got {:?}", other),
+        }
+        let path = std::env::temp_dir().join(format!("ccore-broken-{}.json", now()));
+        broken.save_to_file(&path).unwrap();
+        let loaded = Blockchain::load_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            std::io::ErrorKind::InvalidData,
+            loaded.map(|chain| chain.len()).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn should_reject_block_with_wrong_merkle_root() {
+        let mut blockchain: Blockchain = regtest_chain();
+        let mut block =
+            blockchain.create_candidate_block(0, new_key().1, Some(U256::max_value()), None);
+        block.transactions[0].outputs[0].value = Coins::from_whole(5000);
+        block.mine();
+
+        match blockchain.aggregate_mined_block(block) {
+            Err(BlockChainError::MerkleRootMismatch(_)) => {}
+            other => panic!("expected merkle root mismatch, got {:?}", other),
+        }
+        assert_eq!(0, blockchain.len());
+    }
+
+    /// A chain of `RETARGET_INTERVAL` blocks spaced `interval` apart, all
+    /// with target `difficulty`.
+    fn chain_with_block_interval(interval: TimeStamp, difficulty: U256) -> Blockchain {
+        let mut blockchain = Blockchain::new();
+        for index in 0..RETARGET_INTERVAL {
+            let mut block = Block::new(
+                index as u32,
+                1_000_000 + index as TimeStamp * interval,
+                Hash::zero(),
+                vec![],
+                difficulty,
+            );
+            block.header.hash = block.hash();
+            blockchain.blocks.push(block);
+        }
+        blockchain
+    }
+
+    #[test]
+    fn empty_chain_uses_initial_difficulty() {
+        assert_eq!(INITIAL_DIFFICULTY, Blockchain::new().next_difficulty());
+    }
+
+    #[test]
+    fn should_keep_difficulty_between_adjustments() {
+        let mut blockchain = chain_with_block_interval(1, INITIAL_DIFFICULTY);
+        blockchain.blocks.pop();
+        assert_eq!(INITIAL_DIFFICULTY, blockchain.next_difficulty());
+    }
+
+    #[test]
+    fn should_lower_target_when_blocks_are_fast() {
+        let blockchain = chain_with_block_interval(TARGET_BLOCK_TIME / 2, INITIAL_DIFFICULTY);
+        assert_eq!(INITIAL_DIFFICULTY / 2, blockchain.next_difficulty());
+    }
+
+    #[test]
+    fn should_raise_target_when_blocks_are_slow() {
+        let blockchain = chain_with_block_interval(TARGET_BLOCK_TIME * 2, INITIAL_DIFFICULTY);
+        assert_eq!(INITIAL_DIFFICULTY * 2, blockchain.next_difficulty());
+    }
+
+    #[test]
+    fn should_clamp_adjustment_factor() {
+        let fast = chain_with_block_interval(1, INITIAL_DIFFICULTY);
+        assert_eq!(INITIAL_DIFFICULTY / 4, fast.next_difficulty());
+
+        let slow = chain_with_block_interval(TARGET_BLOCK_TIME * 100, INITIAL_DIFFICULTY);
+        assert_eq!(INITIAL_DIFFICULTY * 4, slow.next_difficulty());
+
+        let easiest = chain_with_block_interval(TARGET_BLOCK_TIME * 100, U256::max_value());
+        assert_eq!(U256::max_value(), easiest.next_difficulty());
+    }
+
+    #[test]
+    fn rejected_block_is_not_indexed_by_hash() {
+        let mut blockchain: Blockchain = regtest_chain();
+        let mut block =
+            blockchain.create_candidate_block(0, new_key().1, Some(U256::max_value()), None);
+        block.mine();
+        block.header.difficulty = U256::zero();
+        let hash = block.header.hash.clone();
+
+        assert!(blockchain.aggregate_mined_block(block).is_err());
+        assert!(blockchain.get_block_by_hash(&hash).is_none());
+    }
 }
@@ -0,0 +1,100 @@
+use primitive_types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::block::MAX_BLOCK_WEIGHT;
+use crate::blockchain::{
+    BLOCK_REWARD, COINBASE_MATURITY, HALVING_INTERVAL, INITIAL_DIFFICULTY, TARGET_BLOCK_TIME,
+};
+use crate::{Coins, Hash, TimeStamp};
+
+/// Rules that differ between networks. The constants of `blockchain` and
+/// `block` are the mainnet values, which also fill in fields missing when
+/// deserializing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChainParams {
+    /// Desired time between blocks, in milliseconds.
+    pub target_block_time: TimeStamp,
+    /// Target used when there is no block to derive a difficulty from.
+    #[serde(with = "crate::block::difficulty_bytes")]
+    pub initial_difficulty: U256,
+    /// Reward before any halving.
+    pub block_reward: Coins,
+    /// Number of blocks after which the block reward halves.
+    pub halving_interval: usize,
+    /// Confirmations a coinbase output needs before it can be spent.
+    pub coinbase_maturity: u32,
+    /// Largest total weight of the transactions in a block.
+    pub max_block_weight: u64,
+    /// Heights whose block must have the given hash, so a new node cannot be
+    /// led onto another history below them.
+    pub checkpoints: Vec<(u32, Hash)>,
+    /// Whether a pooled transaction gives way to one spending the same
+    /// output at a strictly higher fee rate.
+    pub replace_by_fee: bool,
+    /// Whether the target is adjusted every `RETARGET_INTERVAL` blocks. When
+    /// off, every block keeps the target of the first.
+    pub retarget: bool,
+}
+
+impl ChainParams {
+    pub fn mainnet() -> Self {
+        ChainParams {
+            target_block_time: TARGET_BLOCK_TIME,
+            initial_difficulty: INITIAL_DIFFICULTY,
+            block_reward: BLOCK_REWARD,
+            halving_interval: HALVING_INTERVAL,
+            coinbase_maturity: COINBASE_MATURITY,
+            max_block_weight: MAX_BLOCK_WEIGHT,
+            checkpoints: vec![],
+            replace_by_fee: false,
+            retarget: true,
+        }
+    }
+
+    /// Mainnet rules at the easiest target, which is never adjusted, so a
+    /// block is mined with its first hash. Meant for tests.
+    pub fn regtest() -> Self {
+        ChainParams {
+            initial_difficulty: U256::max_value(),
+            retarget: false,
+            ..ChainParams::mainnet()
+        }
+    }
+
+    /// Faster blocks at a 256 times easier starting target, with coinbases
+    /// spendable after ten blocks and halvings every thousand.
+    pub fn testnet() -> Self {
+        ChainParams {
+            target_block_time: 10_000,
+            initial_difficulty: INITIAL_DIFFICULTY << 8,
+            halving_interval: 1_000,
+            coinbase_maturity: 10,
+            ..ChainParams::mainnet()
+        }
+    }
+
+    /// Reward for a block with `height` blocks before it. Reaches zero once
+    /// the base reward has been halved down to nothing.
+    pub fn block_reward_at(&self, height: usize) -> Coins {
+        let halvings = height / self.halving_interval;
+        if halvings >= u64::BITS as usize {
+            return Coins::ZERO;
+        }
+        Coins(self.block_reward.units() >> halvings)
+    }
+
+    /// Hash the block at `height` is checkpointed to, if any.
+    pub fn checkpoint_at(&self, height: u32) -> Option<&Hash> {
+        self.checkpoints
+            .iter()
+            .find(|(checkpoint, _)| *checkpoint == height)
+            .map(|(_, hash)| hash)
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams::mainnet()
+    }
+}
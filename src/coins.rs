@@ -0,0 +1,86 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// An amount of money, counted in the smallest indivisible unit.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Coins(pub u64);
+
+impl Coins {
+    /// Number of smallest units in one whole coin.
+    pub const UNITS_PER_COIN: u64 = 100_000_000;
+
+    pub const ZERO: Coins = Coins(0);
+
+    pub const fn from_whole(coins: u64) -> Coins {
+        Coins(coins * Coins::UNITS_PER_COIN)
+    }
+
+    pub fn units(&self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Coins) -> Option<Coins> {
+        self.0.checked_add(other.0).map(Coins)
+    }
+
+    pub fn checked_sub(self, other: Coins) -> Option<Coins> {
+        self.0.checked_sub(other.0).map(Coins)
+    }
+
+    pub fn saturating_add(self, other: Coins) -> Coins {
+        Coins(self.0.saturating_add(other.0))
+    }
+
+    /// Sum an iterator of amounts, returning `None` on overflow.
+    pub fn checked_sum<I: IntoIterator<Item = Coins>>(amounts: I) -> Option<Coins> {
+        amounts
+            .into_iter()
+            .try_fold(Coins::ZERO, |total, amount| total.checked_add(amount))
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+}
+
+/// Whole coins with all eight decimal places, e.g. `12.50000000`.
+impl fmt::Display for Coins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:08}",
+            self.0 / Coins::UNITS_PER_COIN,
+            self.0 % Coins::UNITS_PER_COIN
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coins;
+
+    #[test]
+    fn from_whole_uses_smallest_unit() {
+        assert_eq!(
+            Coins(150_000_000),
+            Coins::from_whole(1).checked_add(Coins(50_000_000)).unwrap()
+        );
+    }
+
+    #[test]
+    fn checked_operations_do_not_wrap() {
+        assert_eq!(None, Coins(u64::MAX).checked_add(Coins(1)));
+        assert_eq!(None, Coins(1).checked_sub(Coins(2)));
+        assert_eq!(None, Coins::checked_sum(vec![Coins(u64::MAX), Coins(1)]));
+        assert_eq!(Some(Coins(3)), Coins::checked_sum(vec![Coins(1), Coins(2)]));
+    }
+
+    #[test]
+    fn display_shows_whole_coins_and_decimals() {
+        assert_eq!("12.50000000", Coins(1_250_000_000).to_string());
+        assert_eq!("0.00000001", Coins(1).to_string());
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::AtomicBool;
+
+use crate::blockchain::{BlockChainError, Blockchain};
+
+use super::{Block, MineOutcome};
+
+/// Rules deciding who may append a block, on top of the transaction and
+/// linkage checks every chain applies.
+pub trait Consensus: Send + Sync {
+    /// Check `block` before it is connected on top of `chain`.
+    fn validate_block(&self, block: &Block, chain: &Blockchain) -> Result<(), BlockChainError>;
+
+    /// Turn a candidate from `Blockchain::create_candidate_block` into a
+    /// block `validate_block` accepts.
+    fn prepare_candidate(&self, block: &mut Block);
+
+    /// `prepare_candidate`, giving up once `cancel` is set. Engines with no
+    /// search to interrupt just prepare the candidate.
+    fn prepare_candidate_with_cancel(&self, block: &mut Block, cancel: &AtomicBool) -> MineOutcome {
+        let _ = cancel;
+        self.prepare_candidate(block);
+        MineOutcome::Found { hashes_tried: 0 }
+    }
+}
+
+/// The block hash must be below the block's difficulty target. Candidates
+/// are prepared by mining them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProofOfWork;
+
+impl Consensus for ProofOfWork {
+    fn validate_block(&self, block: &Block, _chain: &Blockchain) -> Result<(), BlockChainError> {
+        if !block.verify_pow() {
+            return Err(BlockChainError::ProofOfWorkError(String::from(
+                "Block is not correctly mined",
+            )));
+        }
+        Ok(())
+    }
+
+    fn prepare_candidate(&self, block: &mut Block) {
+        block.mine();
+    }
+
+    fn prepare_candidate_with_cancel(&self, block: &mut Block, cancel: &AtomicBool) -> MineOutcome {
+        block.mine_with_cancel(cancel)
+    }
+}
+
+/// Accepts every block without any work. Candidates are prepared by only
+/// filling in their hash. Only meant for tests and experiments.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlwaysValid;
+
+impl Consensus for AlwaysValid {
+    fn validate_block(&self, _block: &Block, _chain: &Blockchain) -> Result<(), BlockChainError> {
+        Ok(())
+    }
+
+    fn prepare_candidate(&self, block: &mut Block) {
+        block.header.hash = block.compute_hash();
+    }
+}
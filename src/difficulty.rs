@@ -0,0 +1,198 @@
+use primitive_types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::Hash;
+
+/// Sign bit of the compact mantissa. Targets are never negative, so compact
+/// forms with it set are rejected.
+const COMPACT_SIGN_BIT: u32 = 0x0080_0000;
+
+/// Proof-of-work target. A block hash, read as a big-endian integer, must be
+/// below it, so lower targets are harder and `U256::max_value()` accepts
+/// almost any hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Difficulty(U256);
+
+impl Difficulty {
+    /// Expand the compact form used in Bitcoin headers: the high byte is a
+    /// length in bytes and the low 23 bits the leading digits of the target,
+    /// i.e. `target = mantissa * 256^(length - 3)`. `None` if the sign bit
+    /// is set on a non-zero mantissa or the target does not fit 256 bits.
+    pub fn from_bits(bits: u32) -> Option<Difficulty> {
+        let length = bits >> 24;
+        let mantissa = bits & 0x007f_ffff;
+        if bits & COMPACT_SIGN_BIT != 0 && mantissa != 0 {
+            return None;
+        }
+        let target = if length <= 3 {
+            U256::from(mantissa >> (8 * (3 - length)))
+        } else {
+            let shift = 8 * (length as usize - 3);
+            let target = U256::from(mantissa) << shift;
+            if shift >= 256 || target >> shift != U256::from(mantissa) {
+                return None;
+            }
+            target
+        };
+        Some(Difficulty(target))
+    }
+
+    /// The compact form of the target. Only the three leading bytes are
+    /// kept, so converting back may give a slightly lower target.
+    pub fn to_bits(&self) -> u32 {
+        let mut length = (self.0.bits() as u32).div_ceil(8);
+        let mut mantissa = if length <= 3 {
+            self.0.low_u32() << (8 * (3 - length))
+        } else {
+            (self.0 >> (8 * (length as usize - 3))).low_u32()
+        };
+        // Keep the sign bit clear by moving into the next byte
+        if mantissa & COMPACT_SIGN_BIT != 0 {
+            mantissa >>= 8;
+            length += 1;
+        }
+        mantissa | length << 24
+    }
+
+    pub fn target(&self) -> U256 {
+        self.0
+    }
+
+    /// Fewest leading zero bits a hash below the target has. Every hash
+    /// with more passes; with exactly this many, it depends on the bits that
+    /// follow. A zero target, which nothing passes, asks for all 256.
+    pub fn required_leading_bits(&self) -> u32 {
+        if self.0.is_zero() {
+            return 256;
+        }
+        (self.0 - 1).leading_zeros()
+    }
+}
+
+/// Number of zero bits `hash` starts with, read as a big-endian integer:
+/// eight per byte for a hash of zeros.
+pub fn leading_zero_bits(hash: &Hash) -> u32 {
+    let mut bits = 0;
+    for byte in hash.as_bytes() {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+impl From<U256> for Difficulty {
+    fn from(target: U256) -> Self {
+        Difficulty(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{leading_zero_bits, Difficulty};
+    use crate::blockchain::INITIAL_DIFFICULTY;
+    use crate::{check_difficulty, Hash};
+    use primitive_types::U256;
+    use std::str::FromStr;
+
+    fn target(hex: &str) -> U256 {
+        U256::from_str(hex).unwrap()
+    }
+
+    #[test]
+    fn should_expand_compact_bits() {
+        assert_eq!(
+            target("00000000ffff0000000000000000000000000000000000000000000000000000"),
+            Difficulty::from_bits(0x1d00_ffff).unwrap().target()
+        );
+        assert_eq!(
+            U256::from(0x12_3456),
+            Difficulty::from_bits(0x0312_3456).unwrap().target()
+        );
+        assert_eq!(
+            U256::from(0x12),
+            Difficulty::from_bits(0x0112_3456).unwrap().target()
+        );
+        assert_eq!(
+            U256::zero(),
+            Difficulty::from_bits(0x0080_0000).unwrap().target()
+        );
+    }
+
+    #[test]
+    fn should_reject_negative_and_overflowing_bits() {
+        assert_eq!(None, Difficulty::from_bits(0x0492_3456));
+        assert_eq!(None, Difficulty::from_bits(0x2112_3456));
+        assert_eq!(None, Difficulty::from_bits(0xff12_3456));
+    }
+
+    #[test]
+    fn should_compress_targets_to_bits() {
+        let bitcoin = target("00000000ffff0000000000000000000000000000000000000000000000000000");
+        assert_eq!(0x1d00_ffff, Difficulty::from(bitcoin).to_bits());
+        assert_eq!(0x1f0f_ffff, Difficulty::from(INITIAL_DIFFICULTY).to_bits());
+        assert_eq!(
+            0x0312_3456,
+            Difficulty::from(U256::from(0x12_3456)).to_bits()
+        );
+        assert_eq!(0x0200_8000, Difficulty::from(U256::from(0x80)).to_bits());
+        assert_eq!(0, Difficulty::from(U256::zero()).to_bits());
+    }
+
+    #[test]
+    fn should_round_trip_bits() {
+        for bits in [0x1d00_ffff, 0x1f0f_ffff, 0x0312_3456, 0x2100_ffff] {
+            assert_eq!(bits, Difficulty::from_bits(bits).unwrap().to_bits());
+        }
+        assert_eq!(0x2100_ffff, Difficulty::from(U256::max_value()).to_bits());
+    }
+
+    #[test]
+    fn should_count_leading_zero_bits() {
+        let hash = |hex: &str| Hash::from_hex(hex).unwrap();
+        assert_eq!(256, leading_zero_bits(&Hash::zero()));
+        assert_eq!(0, leading_zero_bits(&hash(&"ff".repeat(32))));
+        assert_eq!(
+            255,
+            leading_zero_bits(&hash(&format!("{}01", "00".repeat(31))))
+        );
+        assert_eq!(
+            36,
+            leading_zero_bits(&hash(&format!("000000000f{}", "ff".repeat(27))))
+        );
+        assert_eq!(
+            15,
+            leading_zero_bits(&hash(&format!("0001{}", "00".repeat(30))))
+        );
+    }
+
+    #[test]
+    fn should_require_leading_bits_of_target() {
+        assert_eq!(
+            0,
+            Difficulty::from(U256::max_value()).required_leading_bits()
+        );
+        assert_eq!(256, Difficulty::from(U256::zero()).required_leading_bits());
+        assert_eq!(255, Difficulty::from(U256::from(2)).required_leading_bits());
+        assert_eq!(
+            12,
+            Difficulty::from(INITIAL_DIFFICULTY).required_leading_bits()
+        );
+
+        // A power of two passes exactly the hashes with that many zeros
+        let target = Difficulty::from(U256::one() << 240);
+        assert_eq!(16, target.required_leading_bits());
+        let passing = hash_with_leading_zeros(16);
+        let failing = hash_with_leading_zeros(15);
+        assert!(check_difficulty(&passing, target.target()));
+        assert!(!check_difficulty(&failing, target.target()));
+    }
+
+    /// A hash of ones after `zeros` zero bits.
+    fn hash_with_leading_zeros(zeros: u32) -> Hash {
+        let mut bytes = [0; 32];
+        (U256::max_value() >> zeros).to_big_endian(&mut bytes);
+        Hash::from(bytes.to_vec())
+    }
+}
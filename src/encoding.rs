@@ -0,0 +1,424 @@
+use primitive_types::U256;
+use secp256k1::{ecdsa, PublicKey};
+
+use crate::address::AddressError;
+use crate::block::{Block, BlockHeader};
+use crate::hash::HASH_LEN;
+use crate::hashable::HashAlgo;
+use crate::transaction::{Signature, TxInput, TxOutput};
+use crate::{Address, Coins, Hash, Transaction};
+
+/// Version byte every encoded block starts with.
+pub const ENCODING_VERSION: u8 = 2;
+
+/// Version that wrote the difficulty in little-endian. Still read.
+const LITTLE_ENDIAN_DIFFICULTY_VERSION: u8 = 1;
+
+const ADDRESS_LEN: usize = 21;
+const PUBLIC_KEY_LEN: usize = 33;
+const SIGNATURE_LEN: usize = 64;
+
+/// Set in the block flags byte when the block was pruned to its header.
+const FLAG_PRUNED: u8 = 0x01;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended in the middle of a field.
+    UnexpectedEnd,
+    UnsupportedVersion(u8),
+    /// A count that is not in its shortest form or does not fit 64 bits.
+    InvalidVarInt,
+    /// A byte naming a hash algorithm, flag or optional field that has no
+    /// meaning.
+    InvalidTag(u8),
+    InvalidAddress(AddressError),
+    InvalidSignature,
+    /// Bytes left over after the block.
+    TrailingBytes(usize),
+}
+
+/// Write `block` in the wire layout: the version byte, then the header with
+/// fixed-width integers in little-endian, except the difficulty, which is
+/// big-endian like the hashes it is compared with, and hashes as their 32
+/// raw bytes, then the transactions. Counts and lengths are LEB128 varints. Every
+/// field is written in its single valid form, so equal blocks always give
+/// equal bytes.
+pub(crate) fn encode_block(block: &Block) -> Vec<u8> {
+    let mut out = vec![ENCODING_VERSION];
+    let header = &block.header;
+    out.extend(header.index.to_le_bytes());
+    out.extend(header.timestamp.to_le_bytes());
+    put_hash(&mut out, &header.hash);
+    put_hash(&mut out, &header.prev_block_hash);
+    put_hash(&mut out, &header.merkle_root);
+    out.extend(header.nonce.to_le_bytes());
+    let mut difficulty = [0; 32];
+    header.difficulty.to_big_endian(&mut difficulty);
+    out.extend(difficulty);
+    out.push(match header.hash_algo {
+        HashAlgo::Sha256 => 0,
+        HashAlgo::Blake3 => 1,
+    });
+    out.extend(header.chain_id.to_le_bytes());
+    out.push(if block.pruned { FLAG_PRUNED } else { 0 });
+    put_varint(&mut out, block.transactions.len() as u64);
+    for transaction in &block.transactions {
+        put_transaction(&mut out, transaction);
+    }
+    out
+}
+
+/// Read a block written by `encode_block`. All of `bytes` must be used.
+pub(crate) fn decode_block(bytes: &[u8]) -> Result<Block, DecodeError> {
+    let mut input = Decoder { bytes };
+    let version = input.u8()?;
+    if version != ENCODING_VERSION && version != LITTLE_ENDIAN_DIFFICULTY_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let index = input.u32()?;
+    let timestamp = u128::from_le_bytes(input.array()?);
+    let hash = input.hash()?;
+    let prev_block_hash = input.hash()?;
+    let merkle_root = input.hash()?;
+    let nonce = u64::from_le_bytes(input.array()?);
+    let difficulty = if version == LITTLE_ENDIAN_DIFFICULTY_VERSION {
+        U256::from_little_endian(input.take(32)?)
+    } else {
+        U256::from_big_endian(input.take(32)?)
+    };
+    let hash_algo = match input.u8()? {
+        0 => HashAlgo::Sha256,
+        1 => HashAlgo::Blake3,
+        tag => return Err(DecodeError::InvalidTag(tag)),
+    };
+    let chain_id = input.u32()?;
+    let pruned = match input.u8()? {
+        0 => false,
+        FLAG_PRUNED => true,
+        tag => return Err(DecodeError::InvalidTag(tag)),
+    };
+    let transactions = input.list(Decoder::transaction)?;
+    if !input.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes(input.bytes.len()));
+    }
+    Ok(Block {
+        header: BlockHeader {
+            index,
+            timestamp,
+            hash,
+            prev_block_hash,
+            merkle_root,
+            nonce,
+            difficulty,
+            hash_algo,
+            chain_id,
+        },
+        transactions,
+        pruned,
+    })
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Digests from this crate are all HASH_LEN bytes; decoding assumes as much
+fn put_hash(out: &mut Vec<u8>, hash: &Hash) {
+    debug_assert_eq!(HASH_LEN, hash.len());
+    out.extend(hash.as_bytes());
+}
+
+fn put_transaction(out: &mut Vec<u8>, transaction: &Transaction) {
+    put_varint(out, transaction.inputs.len() as u64);
+    for input in &transaction.inputs {
+        put_hash(out, &input.prev_tx);
+        out.extend(input.index.to_le_bytes());
+        match &input.signature {
+            Some(signature) => {
+                out.push(1);
+                out.extend(signature.public_key.serialize());
+                out.extend(signature.signature.serialize_compact());
+            }
+            None => out.push(0),
+        }
+    }
+    put_varint(out, transaction.outputs.len() as u64);
+    for output in &transaction.outputs {
+        out.extend(output.address.to_bytes());
+        out.extend(output.value.units().to_le_bytes());
+        match &output.data {
+            Some(data) => {
+                out.push(1);
+                put_varint(out, data.len() as u64);
+                out.extend(data);
+            }
+            None => out.push(0),
+        }
+    }
+    out.extend(transaction.timestamp.to_le_bytes());
+    out.extend(transaction.lock_time.to_le_bytes());
+    out.extend(transaction.chain_id.to_le_bytes());
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            let bits = u64::from(byte & 0x7f);
+            if bits << shift >> shift != bits {
+                return Err(DecodeError::InvalidVarInt);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                // a trailing zero group means a shorter form exists
+                if byte == 0 && shift > 0 {
+                    return Err(DecodeError::InvalidVarInt);
+                }
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::InvalidVarInt)
+    }
+
+    fn hash(&mut self) -> Result<Hash, DecodeError> {
+        Ok(Hash::from(self.take(HASH_LEN)?.to_vec()))
+    }
+
+    fn flag(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    /// A varint count followed by that many items. Capacity is bounded by
+    /// the bytes left, as every item takes at least one, so a bogus count
+    /// can't make us allocate more than the input.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Vec<T>, DecodeError> {
+        let count = self.varint()?;
+        let mut items = Vec::with_capacity(count.min(self.bytes.len() as u64) as usize);
+        for _ in 0..count {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn transaction(&mut self) -> Result<Transaction, DecodeError> {
+        let inputs = self.list(Decoder::input)?;
+        let outputs = self.list(Decoder::output)?;
+        Ok(Transaction {
+            inputs,
+            outputs,
+            timestamp: u128::from_le_bytes(self.array()?),
+            lock_time: u64::from_le_bytes(self.array()?),
+            chain_id: self.u32()?,
+        })
+    }
+
+    fn input(&mut self) -> Result<TxInput, DecodeError> {
+        let prev_tx = self.hash()?;
+        let index = self.u32()?;
+        let signature = if self.flag()? {
+            let public_key = PublicKey::from_slice(self.take(PUBLIC_KEY_LEN)?)
+                .map_err(|_| DecodeError::InvalidSignature)?;
+            let signature = ecdsa::Signature::from_compact(self.take(SIGNATURE_LEN)?)
+                .map_err(|_| DecodeError::InvalidSignature)?;
+            Some(Signature {
+                public_key,
+                signature,
+            })
+        } else {
+            None
+        };
+        Ok(TxInput {
+            prev_tx,
+            index,
+            signature,
+        })
+    }
+
+    fn output(&mut self) -> Result<TxOutput, DecodeError> {
+        let address =
+            Address::from_bytes(self.take(ADDRESS_LEN)?).map_err(DecodeError::InvalidAddress)?;
+        let value = Coins(u64::from_le_bytes(self.array()?));
+        let data = if self.flag()? {
+            let len = self.varint()?;
+            let len = usize::try_from(len).map_err(|_| DecodeError::UnexpectedEnd)?;
+            Some(self.take(len)?.to_vec())
+        } else {
+            None
+        };
+        Ok(TxOutput {
+            address,
+            value,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecodeError, ENCODING_VERSION};
+    use crate::transaction::OutPoint;
+    use crate::{Address, Block, Coins, Hash, HashAlgo, Hashable, Transaction};
+    use primitive_types::U256;
+    use secp256k1::rand;
+
+    /// A mined block holding a coinbase and a signed transaction with a
+    /// data output, so every optional field is exercised.
+    fn sample_block() -> Block {
+        let (key, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        let owner = Address::from_public_key(&public_key);
+        let coinbase = Transaction::builder()
+            .output(owner.clone(), Coins::from_whole(50))
+            .chain_id(7)
+            .build()
+            .unwrap();
+        let mut transaction = Transaction::builder()
+            .input(OutPoint {
+                tx_hash: coinbase.hash_with(HashAlgo::Blake3),
+                index: 0,
+            })
+            .output(owner, Coins(4_999_000_000))
+            .data(b"wire".to_vec())
+            .lock_time(300)
+            .chain_id(7)
+            .build()
+            .unwrap();
+        transaction.sign(&key);
+        let mut block = Block::new(
+            3,
+            1_723_939_200_000,
+            Hash::zero(),
+            vec![coinbase, transaction],
+            U256::max_value() >> 4,
+        )
+        .with_hash_algo(HashAlgo::Blake3)
+        .with_chain_id(7);
+        block.mine();
+        block
+    }
+
+    #[test]
+    fn should_round_trip_blocks() {
+        let block = sample_block();
+        let bytes = block.encode();
+        assert_eq!(ENCODING_VERSION, bytes[0]);
+
+        let decoded = Block::decode(&bytes).unwrap();
+        assert_eq!(block.header, decoded.header);
+        assert!(!decoded.pruned);
+        assert_eq!(
+            serde_json::to_value(&block.transactions).unwrap(),
+            serde_json::to_value(&decoded.transactions).unwrap()
+        );
+        assert_eq!(decoded.header.hash, decoded.hash());
+        assert_eq!(bytes, decoded.encode());
+
+        // The first version wrote the difficulty in little-endian
+        let mut legacy = bytes.clone();
+        legacy[0] = 1;
+        legacy[125..157].reverse();
+        assert_eq!(block.header, Block::decode(&legacy).unwrap().header);
+
+        let mut pruned = block.clone();
+        pruned.transactions.clear();
+        pruned.pruned = true;
+        let decoded = Block::decode(&pruned.encode()).unwrap();
+        assert!(decoded.pruned);
+        assert!(decoded.transactions.is_empty());
+    }
+
+    #[test]
+    fn should_be_much_smaller_than_json() {
+        let block = sample_block();
+        assert!(block.encode().len() * 2 < serde_json::to_vec(&block).unwrap().len());
+    }
+
+    #[test]
+    fn should_reject_truncated_input() {
+        let bytes = sample_block().encode();
+        for len in 0..bytes.len() {
+            assert_eq!(
+                Err(DecodeError::UnexpectedEnd),
+                Block::decode(&bytes[..len]).map(|_| ()),
+                "decoded a block from {} of {} bytes",
+                len,
+                bytes.len()
+            );
+        }
+    }
+
+    #[test]
+    fn should_reject_malformed_input_without_panicking() {
+        let bytes = sample_block().encode();
+
+        let mut newer = bytes.clone();
+        newer[0] = ENCODING_VERSION + 1;
+        assert_eq!(
+            Some(DecodeError::UnsupportedVersion(ENCODING_VERSION + 1)),
+            Block::decode(&newer).err()
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Some(DecodeError::TrailingBytes(1)),
+            Block::decode(&trailing).err()
+        );
+
+        // Flip every bit in turn; decoding may succeed or fail but must return
+        for position in 0..bytes.len() {
+            for bit in 0..8 {
+                let mut corrupted = bytes.clone();
+                corrupted[position] ^= 1 << bit;
+                let _ = Block::decode(&corrupted);
+            }
+        }
+        // Counts claiming far more transactions than there are bytes, or
+        // not written in their shortest form
+        let header = &bytes[..1 + 4 + 16 + 3 * 32 + 8 + 32 + 1 + 4 + 1];
+        let huge_count = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(
+            Some(DecodeError::UnexpectedEnd),
+            Block::decode(&[header, &huge_count].concat()).err()
+        );
+        assert_eq!(
+            Some(DecodeError::InvalidVarInt),
+            Block::decode(&[header, &[0x80, 0x00]].concat()).err()
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Length of the SHA-256 digests used for blocks, transactions and outputs.
+pub const HASH_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashError {
+    OddLength(usize),
+    InvalidCharacter(char),
+}
+
+/// A digest, shown and parsed as lowercase hex and serialized the same way.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Hash(Vec<u8>);
+
+impl Hash {
+    /// The all-zero hash, standing in for the parent of the first block.
+    pub fn zero() -> Hash {
+        Hash(vec![0; HASH_LEN])
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Parse hex in either case. Any even number of digits is accepted.
+    pub fn from_hex(s: &str) -> Result<Hash, HashError> {
+        if !s.len().is_multiple_of(2) {
+            return Err(HashError::OddLength(s.len()));
+        }
+        let digits = s
+            .chars()
+            .map(|c| c.to_digit(16).ok_or(HashError::InvalidCharacter(c)))
+            .collect::<Result<Vec<u32>, HashError>>()?;
+        Ok(Hash(
+            digits
+                .chunks(2)
+                .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+                .collect(),
+        ))
+    }
+}
+
+impl From<Vec<u8>> for Hash {
+    fn from(bytes: Vec<u8>) -> Hash {
+        Hash(bytes)
+    }
+}
+
+impl Deref for Hash {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Hash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hash({})", self.to_hex())
+    }
+}
+
+impl FromStr for Hash {
+    type Err = HashError;
+
+    fn from_str(s: &str) -> Result<Hash, HashError> {
+        Hash::from_hex(s)
+    }
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Hash::from_hex(&s).map_err(|error| serde::de::Error::custom(format!("{:?}", error)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hash, HashError};
+
+    #[test]
+    fn should_round_trip_hex() {
+        let text = "e5a01fee14e0ed5c48714f22180f25ad8365b53f9779f79dc4a3d7e93963f94a";
+        let hash: Hash = text.parse().unwrap();
+        assert_eq!(32, hash.len());
+        assert_eq!(text, hash.to_string());
+        assert_eq!(hash, Hash::from_hex(&text.to_uppercase()).unwrap());
+    }
+
+    #[test]
+    fn should_reject_malformed_hex() {
+        assert_eq!(Err(HashError::OddLength(3)), Hash::from_hex("abc"));
+        assert_eq!(Err(HashError::InvalidCharacter('g')), Hash::from_hex("0g"));
+    }
+
+    #[test]
+    fn should_serialize_as_hex_string() {
+        let hash = Hash::from(vec![0xab, 0x01]);
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!("\"ab01\"", json);
+        assert_eq!(hash, serde_json::from_str(&json).unwrap());
+    }
+}
@@ -1,9 +1,56 @@
+use serde::{Deserialize, Serialize};
+
 use super::Hash;
+use crate::primitives::{self, Digest};
+
+/// Hash function behind block hashes, Merkle roots and transaction hashes.
+/// A chain takes it from its genesis block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgo {
+    pub fn digest(&self, bytes: &[u8]) -> Hash {
+        Hash::from(self.primitive()(bytes).to_vec())
+    }
+
+    /// The function of the `primitives` module computing this digest.
+    pub(crate) fn primitive(&self) -> fn(&[u8]) -> Digest {
+        match self {
+            HashAlgo::Sha256 => primitives::sha256,
+            HashAlgo::Blake3 => primitives::blake3,
+        }
+    }
+}
 
 pub trait Hashable {
     fn bytes (&self) -> Vec<u8>;
 
     fn hash( &self) -> Hash {
-        crypto_hash::digest(crypto_hash::Algorithm::SHA256, &self.bytes())
+        self.hash_with(HashAlgo::Sha256)
+    }
+
+    fn hash_with(&self, algo: HashAlgo) -> Hash {
+        algo.digest(&self.bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashAlgo;
+
+    #[test]
+    fn should_match_known_digests() {
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            HashAlgo::Sha256.digest(b"abc").to_hex()
+        );
+        assert_eq!(
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            HashAlgo::Blake3.digest(b"abc").to_hex()
+        );
     }
 }
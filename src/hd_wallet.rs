@@ -0,0 +1,181 @@
+use bip39::Mnemonic;
+use secp256k1::{Scalar, SecretKey};
+
+use crate::wallet::{Wallet, WalletError};
+
+use super::Address;
+
+/// Key used for the master key HMAC, as in BIP32.
+const MASTER_KEY_SALT: &[u8] = b"Bitcoin seed";
+
+/// Offset added to child indices to select hardened derivation.
+const HARDENED_OFFSET: u32 = 0x8000_0000;
+
+const SHA512_BLOCK_BYTES: usize = 128;
+
+fn hmac_sha512(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block_key = if key.len() > SHA512_BLOCK_BYTES {
+        crypto_hash::digest(crypto_hash::Algorithm::SHA512, key)
+    } else {
+        key.to_vec()
+    };
+    block_key.resize(SHA512_BLOCK_BYTES, 0);
+
+    let mut inner = block_key
+        .iter()
+        .map(|byte| byte ^ 0x36)
+        .collect::<Vec<u8>>();
+    inner.extend(message);
+    let mut outer = block_key
+        .iter()
+        .map(|byte| byte ^ 0x5c)
+        .collect::<Vec<u8>>();
+    outer.extend(crypto_hash::digest(crypto_hash::Algorithm::SHA512, &inner));
+    crypto_hash::digest(crypto_hash::Algorithm::SHA512, &outer)
+}
+
+/// Split an HMAC-SHA512 output into a secret key and a chain code.
+fn split_key(digest: &[u8]) -> Option<(SecretKey, [u8; 32])> {
+    let key = SecretKey::from_slice(&digest[..32]).ok()?;
+    let mut chain_code = [0; 32];
+    chain_code.copy_from_slice(&digest[32..]);
+    Some((key, chain_code))
+}
+
+/// A wallet deriving any number of keys from a single seed, following BIP32
+/// hardened derivation: child `i` of the master key is `m/i'`. The seed
+/// phrase is the BIP39 word encoding of the seed itself, so it restores
+/// exactly the same keys.
+pub struct HdWallet {
+    seed: Vec<u8>,
+    master_key: SecretKey,
+    chain_code: [u8; 32],
+}
+
+impl HdWallet {
+    /// Generate a wallet from a fresh random 32-byte seed.
+    pub fn new() -> Self {
+        let seed: [u8; 32] = secp256k1::rand::random();
+        Self::from_seed(&seed).expect("a random 32-byte seed is valid")
+    }
+
+    /// Build the wallet for `seed`, which must be 16 to 32 bytes long and a
+    /// multiple of 4 so it can be written as a seed phrase.
+    pub fn from_seed(seed: &[u8]) -> Result<Self, WalletError> {
+        if !(16..=32).contains(&seed.len()) || !seed.len().is_multiple_of(4) {
+            return Err(WalletError::InvalidSeed(format!(
+                "Seed of {} bytes is not 16 to 32 bytes in steps of 4.",
+                seed.len()
+            )));
+        }
+        let (master_key, chain_code) = split_key(&hmac_sha512(MASTER_KEY_SALT, seed))
+            .ok_or_else(|| WalletError::InvalidSeed(String::from("Seed gives an invalid key.")))?;
+        Ok(HdWallet {
+            seed: seed.to_vec(),
+            master_key,
+            chain_code,
+        })
+    }
+
+    pub fn from_seed_phrase(phrase: &str) -> Result<Self, WalletError> {
+        let mnemonic =
+            Mnemonic::parse(phrase).map_err(|error| WalletError::InvalidSeed(error.to_string()))?;
+        Self::from_seed(&mnemonic.to_entropy())
+    }
+
+    /// The seed as BIP39 English words.
+    pub fn seed_phrase(&self) -> String {
+        Mnemonic::from_entropy(&self.seed)
+            .expect("seed length was checked on creation")
+            .to_string()
+    }
+
+    /// Secret key of the hardened child `index`.
+    pub fn derive_key(&self, index: u32) -> SecretKey {
+        let mut data = vec![0];
+        data.extend(self.master_key.secret_bytes());
+        data.extend((index | HARDENED_OFFSET).to_be_bytes());
+        let digest = hmac_sha512(&self.chain_code, &data);
+        let tweak = Scalar::from_be_bytes(digest[..32].try_into().expect("digest is 64 bytes"))
+            .expect("tweak is below the curve order with overwhelming probability");
+        self.master_key
+            .add_tweak(&tweak)
+            .expect("derived key is valid with overwhelming probability")
+    }
+
+    /// A wallet holding the key of child `index`, able to spend what is sent
+    /// to `derive_address(index)`.
+    pub fn derive_wallet(&self, index: u32) -> Wallet {
+        Wallet::from_secret_key(self.derive_key(index))
+    }
+
+    pub fn derive_address(&self, index: u32) -> Address {
+        self.derive_wallet(index).address()
+    }
+}
+
+impl Default for HdWallet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HdWallet;
+    use crate::blockchain::{Blockchain, COINBASE_MATURITY};
+    use crate::wallet::{Wallet, WalletError};
+    use crate::{Address, ChainParams, Coins};
+
+    fn mine_block(chain: &mut Blockchain, miner: Address) {
+        let mut block = chain.create_candidate_block(u64::MAX, miner, None, None);
+        block.mine();
+        chain.aggregate_mined_block(block).unwrap();
+    }
+
+    #[test]
+    fn should_match_bip32_hardened_child() {
+        // Test vector 1 from BIP32, chain m/0H
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let wallet = HdWallet::from_seed(&seed).unwrap();
+        assert_eq!(
+            "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea",
+            hex::encode(wallet.derive_key(0).secret_bytes())
+        );
+    }
+
+    #[test]
+    fn should_restore_from_seed_phrase() {
+        let wallet = HdWallet::new();
+        let restored = HdWallet::from_seed_phrase(&wallet.seed_phrase()).unwrap();
+        assert_eq!(24, wallet.seed_phrase().split(' ').count());
+        assert_eq!(wallet.derive_address(7), restored.derive_address(7));
+        assert_ne!(wallet.derive_address(0), wallet.derive_address(1));
+        assert!(matches!(
+            HdWallet::from_seed(&[0; 15]),
+            Err(WalletError::InvalidSeed(_))
+        ));
+    }
+
+    #[test]
+    fn should_spend_from_derived_addresses() {
+        let wallet = HdWallet::new();
+        let mut chain = Blockchain::with_params(ChainParams::regtest());
+        mine_block(&mut chain, wallet.derive_address(0));
+        mine_block(&mut chain, wallet.derive_address(1));
+        for _ in 1..COINBASE_MATURITY {
+            mine_block(&mut chain, Wallet::new().address());
+        }
+
+        let bob = Wallet::new();
+        for index in 0..2u64 {
+            let transaction = wallet
+                .derive_wallet(index as u32)
+                .create_transaction(&chain, &bob.address(), Coins::from_whole(10 + index))
+                .unwrap();
+            chain.add_transaction_to_pool(transaction).unwrap();
+        }
+        mine_block(&mut chain, Wallet::new().address());
+        assert_eq!(Coins::from_whole(21), bob.balance(&chain));
+    }
+}
@@ -1,27 +1,79 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 use std::time::{SystemTime, UNIX_EPOCH};
 
+#[cfg(feature = "std")]
 use primitive_types::U256;
 
-pub use crate::block::Block;
-pub use crate::blockchain::Blockchain;
-pub use crate::hashable::Hashable;
-pub use crate::transaction::Transaction;
-pub use crate::transaction::TxOutput;
+#[cfg(feature = "std")]
+pub use crate::{
+    address::Address,
+    block::{Block, BlockHeader, MineOutcome},
+    blockchain::Blockchain,
+    chain_params::ChainParams,
+    coins::Coins,
+    difficulty::Difficulty,
+    hash::Hash,
+    hashable::{HashAlgo, Hashable},
+    merkle::{merkle_root, verify_merkle_proof, MerkleProof},
+    miner::Miner,
+    shared_chain::SharedChain,
+    transaction::{OutPoint, Signature, Transaction, TransactionBuilder, TxInput, TxOutput},
+};
 
+#[cfg(feature = "std")]
 pub fn now() -> u128 {
     let duration = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     duration.as_secs() as u128 * 1000 + duration.subsec_millis() as u128
 }
 
+/// Whether `hash`, read as a big-endian integer, is below `target`. See
+/// `Difficulty` for converting targets to and from their compact form.
+#[cfg(feature = "std")]
 fn check_difficulty(hash: &Hash, target: U256) -> bool {
-    U256::from(hash.as_slice()) < target
+    primitives::check_difficulty(hash.as_bytes(), target)
 }
 
-type Hash = Vec<u8>;
-type Address = String;
+#[cfg(feature = "std")]
 type TimeStamp = u128;
 
+#[cfg(feature = "std")]
+pub mod address;
+#[cfg(feature = "std")]
 pub mod block;
+#[cfg(feature = "std")]
 pub mod blockchain;
+#[cfg(feature = "std")]
+pub mod chain_params;
+#[cfg(feature = "std")]
+pub mod coins;
+#[cfg(feature = "std")]
+pub mod consensus;
+#[cfg(feature = "std")]
+pub mod difficulty;
+#[cfg(feature = "std")]
+pub mod encoding;
+#[cfg(feature = "std")]
+pub mod hash;
+#[cfg(feature = "std")]
 pub mod hashable;
+#[cfg(feature = "std")]
+pub mod hd_wallet;
+#[cfg(feature = "std")]
+pub mod merkle;
+#[cfg(feature = "std")]
+pub mod miner;
+#[cfg(feature = "std")]
+pub mod p2p;
+pub mod primitives;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "std")]
+pub mod shared_chain;
+#[cfg(feature = "std")]
 pub mod transaction;
+#[cfg(feature = "std")]
+pub mod wallet;
@@ -1,4 +1,200 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+use clap::{Parser, Subcommand};
+use secp256k1::SecretKey;
+
+use core::blockchain::Blockchain;
+use core::wallet::Wallet;
+use core::{Address, Coins, Hashable, Transaction};
+
+/// Command line access to a local chain kept on disk.
+#[derive(Parser)]
+#[command(name = "ccore", version = env!("CARGO_PKG_VERSION"))]
+struct Cli {
+    /// Directory holding the chain, pending transactions and wallet keys.
+    #[arg(long, default_value = ".ccore")]
+    data_dir: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a wallet, store its key and print its address.
+    NewWallet,
+    /// Print the coins held by an address.
+    Balance {
+        #[arg(value_parser = parse_address)]
+        address: Address,
+    },
+    /// Pay coins from a wallet created with `new-wallet`. The payment waits
+    /// for the next `mine`.
+    Send {
+        #[arg(value_parser = parse_address)]
+        from: Address,
+        #[arg(value_parser = parse_address)]
+        to: Address,
+        /// Amount in coins, with up to eight decimal places.
+        #[arg(value_parser = parse_amount)]
+        amount: Coins,
+    },
+    /// Mine blocks holding the pending transactions.
+    Mine {
+        #[arg(value_parser = parse_address)]
+        miner: Address,
+        #[arg(long, default_value_t = 1)]
+        blocks: u32,
+        /// Text to carry in the coinbase of each block.
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Print a one line summary of every block.
+    Chain,
+}
+
+fn parse_address(s: &str) -> Result<Address, String> {
+    Address::from_str(s).map_err(|e| format!("invalid address: {:?}", e))
+}
+
+/// Parse whole coins with an optional fraction of up to eight digits.
+fn parse_amount(s: &str) -> Result<Coins, String> {
+    let invalid = || format!("invalid amount: {}", s);
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if fraction.len() > 8 || !(whole.chars().chain(fraction.chars())).all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let whole = if whole.is_empty() {
+        0
+    } else {
+        whole.parse::<u64>().map_err(|_| invalid())?
+    };
+    let fraction = format!("{:0<8}", fraction).parse::<u64>().unwrap_or(0);
+    whole
+        .checked_mul(Coins::UNITS_PER_COIN)
+        .and_then(|units| units.checked_add(fraction))
+        .map(Coins)
+        .ok_or_else(invalid)
+}
+
+/// The chain and pending transactions kept in a data directory.
+struct State {
+    data_dir: PathBuf,
+    chain: Blockchain,
+}
+
+impl State {
+    fn open(data_dir: &Path) -> io::Result<State> {
+        fs::create_dir_all(data_dir.join("wallets"))?;
+        let chain_path = data_dir.join("chain.json");
+        let mut chain = if chain_path.exists() {
+            Blockchain::load_from_file(&chain_path)?
+        } else {
+            Blockchain::new()
+        };
+        let pool_path = data_dir.join("pool.json");
+        if pool_path.exists() {
+            let pool: Vec<Transaction> =
+                serde_json::from_slice(&fs::read(pool_path)?).map_err(io::Error::from)?;
+            for transaction in pool {
+                // Drop whatever no longer fits the chain
+                let _ = chain.add_transaction_to_pool(transaction);
+            }
+        }
+        Ok(State {
+            data_dir: data_dir.to_path_buf(),
+            chain,
+        })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        self.chain.save_to_file(&self.data_dir.join("chain.json"))?;
+        let pool = serde_json::to_vec_pretty(self.chain.mempool())?;
+        fs::write(self.data_dir.join("pool.json"), pool)
+    }
+
+    fn wallet_path(&self, address: &Address) -> PathBuf {
+        self.data_dir
+            .join("wallets")
+            .join(format!("{}.key", address))
+    }
+
+    fn save_wallet(&self, wallet: &Wallet) -> io::Result<()> {
+        let secret = wallet.secret_key().display_secret().to_string();
+        fs::write(self.wallet_path(&wallet.address()), secret)
+    }
+
+    fn load_wallet(&self, address: &Address) -> io::Result<Wallet> {
+        let secret = fs::read_to_string(self.wallet_path(address))?;
+        let secret_key = SecretKey::from_str(secret.trim())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Wallet::from_secret_key(secret_key))
+    }
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    let mut state = State::open(&cli.data_dir).map_err(|e| e.to_string())?;
+    match cli.command {
+        Command::NewWallet => {
+            let wallet = Wallet::new();
+            state.save_wallet(&wallet).map_err(|e| e.to_string())?;
+            println!("{}", wallet.address());
+        }
+        Command::Balance { address } => {
+            println!("{}", state.chain.balance_of(&address));
+        }
+        Command::Send { from, to, amount } => {
+            let wallet = state
+                .load_wallet(&from)
+                .map_err(|e| format!("no wallet for {}: {}", from, e))?;
+            let transaction = wallet
+                .create_transaction(&state.chain, &to, amount)
+                .map_err(|e| format!("{:?}", e))?;
+            let hash = transaction.hash_with(state.chain.hash_algo());
+            state
+                .chain
+                .add_transaction_to_pool(transaction)
+                .map_err(|e| e.to_string())?;
+            state.save().map_err(|e| e.to_string())?;
+            println!("{}", hash);
+        }
+        Command::Mine { miner, blocks, tag } => {
+            for _ in 0..blocks {
+                let mut block = state.chain.create_candidate_block(
+                    u64::MAX,
+                    miner.clone(),
+                    None,
+                    tag.as_deref(),
+                );
+                state.chain.consensus().prepare_candidate(&mut block);
+                println!("{} {}", block.header.index, block.header.hash);
+                state
+                    .chain
+                    .aggregate_mined_block(block)
+                    .map_err(|e| e.to_string())?;
+            }
+            state.save().map_err(|e| e.to_string())?;
+        }
+        Command::Chain => {
+            for block in &state.chain {
+                println!(
+                    "{} {} {} transactions",
+                    block.header.index,
+                    block.header.hash,
+                    block.transactions.len()
+                );
+            }
+        }
+    }
+    Ok(())
+}
 
 fn main() {
-    println!("Hello, world!");
+    if let Err(error) = run(Cli::parse()) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
 }
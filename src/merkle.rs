@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hashable::HashAlgo;
+use crate::primitives::{self, Digest};
+
+use super::Hash;
+
+fn hash_pair(left: &Hash, right: &Hash, algo: HashAlgo) -> Hash {
+    let mut preimage = Vec::with_capacity(left.len() + right.len());
+    preimage.extend(left.as_bytes());
+    preimage.extend(right.as_bytes());
+    algo.digest(&preimage)
+}
+
+/// Compute the root of a binary Merkle tree over `hashes`.
+///
+/// Levels with an odd number of nodes duplicate their last node. An empty
+/// list yields an all-zero hash.
+pub fn merkle_root(hashes: &[Hash]) -> Hash {
+    merkle_root_with(hashes, HashAlgo::Sha256)
+}
+
+/// Like `merkle_root`, hashing each pair of nodes with `algo`.
+pub fn merkle_root_with(hashes: &[Hash], algo: HashAlgo) -> Hash {
+    let leaves = hashes
+        .iter()
+        .map(|hash| Digest::try_from(hash.as_bytes()).expect("hashes are digests"))
+        .collect::<Vec<Digest>>();
+    Hash::from(primitives::merkle_root(&leaves, algo.primitive()).to_vec())
+}
+
+/// Path from a leaf to the root of a Merkle tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Position of the leaf. Bit `n` set means the leaf's ancestor at level
+    /// `n` is a right child, so its sibling goes on the left.
+    pub index: u32,
+    /// Sibling hashes from the leaf level up to just below the root.
+    pub siblings: Vec<Hash>,
+    pub hash_algo: HashAlgo,
+}
+
+/// Build the proof that `hashes[index]` is part of `merkle_root_with(hashes,
+/// algo)`. `None` if `index` is out of range.
+pub fn merkle_proof_with(hashes: &[Hash], index: usize, algo: HashAlgo) -> Option<MerkleProof> {
+    if index >= hashes.len() {
+        return None;
+    }
+    let mut siblings = Vec::new();
+    let mut level = hashes.to_vec();
+    let mut position = index;
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1].clone());
+        }
+        siblings.push(level[position ^ 1].clone());
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1], algo))
+            .collect();
+        position /= 2;
+    }
+    Some(MerkleProof {
+        index: u32::try_from(index).ok()?,
+        siblings,
+        hash_algo: algo,
+    })
+}
+
+/// Check that `proof` leads from `leaf` to `merkle_root`.
+pub fn verify_merkle_proof(leaf: &Hash, proof: &MerkleProof, merkle_root: &Hash) -> bool {
+    if proof.siblings.len() < 32 && proof.index >> proof.siblings.len() != 0 {
+        return false;
+    }
+    let node = proof
+        .siblings
+        .iter()
+        .enumerate()
+        .fold(leaf.clone(), |node, (level, sibling)| {
+            if proof.index >> level & 1 == 1 {
+                hash_pair(sibling, &node, proof.hash_algo)
+            } else {
+                hash_pair(&node, sibling, proof.hash_algo)
+            }
+        });
+    &node == merkle_root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merkle_proof_with, merkle_root, verify_merkle_proof};
+    use crate::hashable::HashAlgo;
+    use crate::Hash;
+
+    fn sha256(data: &[u8]) -> Hash {
+        HashAlgo::Sha256.digest(data)
+    }
+
+    #[test]
+    fn empty_list_has_zero_root() {
+        assert_eq!(Hash::zero(), merkle_root(&[]));
+    }
+
+    #[test]
+    fn single_hash_is_its_own_root() {
+        let leaf = sha256(b"a");
+        assert_eq!(leaf, merkle_root(std::slice::from_ref(&leaf)));
+    }
+
+    #[test]
+    fn matches_known_vectors() {
+        let leaves = vec![sha256(b"a"), sha256(b"b"), sha256(b"c")];
+        assert_eq!(
+            Hash::from_hex("e5a01fee14e0ed5c48714f22180f25ad8365b53f9779f79dc4a3d7e93963f94a")
+                .unwrap(),
+            merkle_root(&leaves[..2])
+        );
+        assert_eq!(
+            Hash::from_hex("d31a37ef6ac14a2db1470c4316beb5592e6afd4465022339adafda76a18ffabe")
+                .unwrap(),
+            merkle_root(&leaves)
+        );
+    }
+
+    #[test]
+    fn proves_every_leaf() {
+        let leaves: Vec<Hash> = (0u8..5).map(|i| sha256(&[i])).collect();
+        let root = merkle_root(&leaves);
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = merkle_proof_with(&leaves, index, HashAlgo::Sha256).unwrap();
+            assert!(verify_merkle_proof(leaf, &proof, &root));
+        }
+        assert_eq!(None, merkle_proof_with(&leaves, 5, HashAlgo::Sha256));
+    }
+
+    #[test]
+    fn rejects_proof_for_other_leaf_or_position() {
+        let leaves: Vec<Hash> = (0u8..4).map(|i| sha256(&[i])).collect();
+        let root = merkle_root(&leaves);
+        let mut proof = merkle_proof_with(&leaves, 1, HashAlgo::Sha256).unwrap();
+        assert!(!verify_merkle_proof(&sha256(b"x"), &proof, &root));
+        proof.index = 2;
+        assert!(!verify_merkle_proof(&leaves[1], &proof, &root));
+        proof.index = 5;
+        assert!(!verify_merkle_proof(&leaves[1], &proof, &root));
+    }
+}
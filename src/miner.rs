@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::blockchain::BlockChainError;
+use crate::shared_chain::SharedChain;
+use crate::{Address, Block, Hash, MineOutcome};
+
+#[derive(Debug)]
+pub enum MineError {
+    /// The chain refused the mined block.
+    Rejected(BlockChainError),
+}
+
+/// Mines blocks paying `address` on top of a shared chain, one
+/// `mine_next` call per block.
+pub struct Miner {
+    address: Address,
+    chain: SharedChain,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Miner {
+    pub fn new(address: Address, chain: SharedChain) -> Self {
+        Miner {
+            address,
+            chain,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Flag making the running `mine_next` drop its candidate and start over
+    /// on the latest tip. Set it when a block arrives from elsewhere.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel)
+    }
+
+    /// Build a candidate on the tip at the chain's next difficulty, prepare
+    /// it with the chain's consensus engine and add it to the chain,
+    /// returning the accepted block.
+    ///
+    /// When cancelled, or when the tip moved before the block was found, the
+    /// candidate's transactions go back to the pool and a fresh candidate is
+    /// mined. If the chain rejects the block they go back as well.
+    pub fn mine_next(&mut self) -> Result<Block, MineError> {
+        let consensus = self.chain.consensus();
+        loop {
+            self.cancel.store(false, Ordering::Relaxed);
+            let mut block =
+                self.chain
+                    .create_candidate_block(u64::MAX, self.address.clone(), None, None);
+            let found = match consensus.prepare_candidate_with_cancel(&mut block, &self.cancel) {
+                MineOutcome::Found { .. } => {
+                    self.chain.tip_hash().unwrap_or_else(Hash::zero) == block.header.prev_block_hash
+                }
+                MineOutcome::Cancelled { .. } => false,
+            };
+            if !found {
+                self.return_to_pool(&block);
+                continue;
+            }
+            return match self.chain.aggregate_mined_block(block.clone()) {
+                Ok(()) => Ok(block),
+                Err(error) => {
+                    self.return_to_pool(&block);
+                    Err(MineError::Rejected(error))
+                }
+            };
+        }
+    }
+
+    // Transactions confirmed meanwhile no longer verify and are dropped
+    fn return_to_pool(&self, block: &Block) {
+        for transaction in block.transactions.iter().skip(1) {
+            let _ = self.chain.add_transaction_to_pool(transaction.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Miner;
+    use crate::consensus::AlwaysValid;
+    use crate::{Address, Blockchain, Coins, Hash, SharedChain};
+    use secp256k1::rand;
+
+    fn new_address() -> Address {
+        let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        Address::from_public_key(&public_key)
+    }
+
+    #[test]
+    fn should_mine_consecutive_blocks() {
+        let shared = SharedChain::new(Blockchain::new());
+        let address = new_address();
+        let mut miner = Miner::new(address.clone(), shared.clone());
+
+        let mut previous_hash = Hash::zero();
+        for _ in 0..3 {
+            let block = miner.mine_next().unwrap();
+            assert_eq!(previous_hash, block.header.prev_block_hash);
+            assert_eq!(Some(block.header.hash.clone()), shared.tip_hash());
+            previous_hash = block.header.hash;
+        }
+        assert_eq!(3, shared.len());
+        assert!(shared.balance_of(&address) > Coins::ZERO);
+        assert!(shared.snapshot().validate().is_ok());
+    }
+
+    #[test]
+    fn should_prepare_candidates_with_chain_consensus() {
+        let shared = SharedChain::new(Blockchain::with_consensus(Box::new(AlwaysValid)));
+        let mut miner = Miner::new(new_address(), shared.clone());
+
+        // Proof of work would almost never take the first nonce three times
+        for _ in 0..3 {
+            let block = miner.mine_next().unwrap();
+            assert_eq!(0, block.header.nonce);
+            assert_eq!(block.compute_hash(), block.header.hash);
+        }
+        assert_eq!(3, shared.len());
+    }
+}
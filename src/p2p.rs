@@ -0,0 +1,391 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::{BlockHeader, MAX_BLOCK_BYTES};
+use crate::blockchain::BlockChainError;
+use crate::shared_chain::SharedChain;
+
+use super::Block;
+use super::Hash;
+use super::Hashable;
+use super::Transaction;
+
+/// Largest frame accepted from a peer, in bytes. Leaves room for a full
+/// block plus its JSON framing.
+pub const MAX_MESSAGE_BYTES: usize = 2 * MAX_BLOCK_BYTES;
+
+/// Most headers sent in answer to a single `GetHeaders`.
+pub const MAX_HEADERS: usize = 2_000;
+
+/// How long a syncing node waits for each answer before giving up on a peer.
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most hashes a node remembers having handled. Once full it forgets them
+/// all; gossip coming around again is then refused by the chain instead.
+pub const MAX_SEEN: usize = 50_000;
+
+/// Messages exchanged between nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    /// Sent on connecting, carrying the address the sender listens on.
+    Hello(SocketAddr),
+    NewTransaction(Transaction),
+    NewBlock(Block),
+    /// Ask for the headers following the latest block in the locator that
+    /// the peer knows, as built by `Blockchain::block_locator`.
+    GetHeaders(Vec<Hash>),
+    Headers(Vec<BlockHeader>),
+    /// Ask for blocks by hash. Each is answered with its own `Block`.
+    GetBlocks(Vec<Hash>),
+    Block(Block),
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    Io(io::Error),
+    /// The peer answered with something other than what was asked for.
+    UnexpectedMessage(String),
+    /// The peer sent blocks our chain refused.
+    InvalidBlock(BlockChainError),
+}
+
+impl From<io::Error> for SyncError {
+    fn from(error: io::Error) -> Self {
+        SyncError::Io(error)
+    }
+}
+
+/// Write `message` as a little-endian `u32` length followed by its JSON.
+pub fn write_message(stream: &mut impl Write, message: &Message) -> io::Result<()> {
+    let body = serde_json::to_vec(message)?;
+    if body.len() > MAX_MESSAGE_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message is too large",
+        ));
+    }
+    stream.write_all(&(body.len() as u32).to_le_bytes())?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+/// Read one frame written by `write_message`. Returns `None` once the peer
+/// closes the connection between frames.
+pub fn read_message(stream: &mut impl Read) -> io::Result<Option<Message>> {
+    let mut length = [0; 4];
+    match stream.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_MESSAGE_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message is too large",
+        ));
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A peer-to-peer node gossiping transactions and blocks for a shared chain.
+/// Clones share the same chain, peers and listening socket.
+#[derive(Clone)]
+pub struct Node {
+    listener: Arc<TcpListener>,
+    local_addr: SocketAddr,
+    chain: SharedChain,
+    peers: Arc<Mutex<HashSet<SocketAddr>>>,
+    /// Hashes of transactions and blocks already handled, so gossip that
+    /// comes back around is not relayed again. At most `MAX_SEEN`.
+    seen: Arc<Mutex<HashSet<Hash>>>,
+}
+
+impl Node {
+    pub fn bind(addr: impl ToSocketAddrs, chain: SharedChain) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Node {
+            local_addr: listener.local_addr()?,
+            listener: Arc::new(listener),
+            chain,
+            peers: Arc::new(Mutex::new(HashSet::new())),
+            seen: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn chain(&self) -> &SharedChain {
+        &self.chain
+    }
+
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        lock(&self.peers).iter().copied().collect()
+    }
+
+    /// Accept connections forever, handling each on its own thread.
+    pub fn run(&self) {
+        for stream in self.listener.incoming().flatten() {
+            let node = self.clone();
+            thread::spawn(move || {
+                // A misbehaving or vanished peer only loses its own connection
+                let _ = node.handle_connection(stream);
+            });
+        }
+    }
+
+    /// Run the node on a background thread.
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let node = self.clone();
+        thread::spawn(move || node.run())
+    }
+
+    /// Add `peer` and introduce ourselves so it relays to us as well.
+    pub fn connect(&self, peer: SocketAddr) -> io::Result<()> {
+        self.send(peer, &Message::Hello(self.local_addr))?;
+        lock(&self.peers).insert(peer);
+        Ok(())
+    }
+
+    /// Add a local transaction to the pool and gossip it to every peer.
+    pub fn announce_transaction(&self, transaction: Transaction) {
+        self.handle_message(Message::NewTransaction(transaction));
+    }
+
+    /// Add a local block to the chain and gossip it to every peer.
+    pub fn announce_block(&self, block: Block) {
+        self.handle_message(Message::NewBlock(block));
+    }
+
+    /// Download every block `peer` has past the latest one we share with it,
+    /// in order, returning how many were added. Blocks extending our tip are
+    /// imported batch by batch; a branch forking below it is collected until
+    /// it holds more work than ours and then switched to, see
+    /// `Blockchain::try_reorg`. A peer that sends a bad block is dropped from
+    /// our peers.
+    pub fn sync(&self, peer: SocketAddr) -> Result<usize, SyncError> {
+        let result = self.sync_from(peer);
+        if let Err(SyncError::InvalidBlock(_) | SyncError::UnexpectedMessage(_)) = result {
+            lock(&self.peers).remove(&peer);
+        }
+        result
+    }
+
+    fn sync_from(&self, peer: SocketAddr) -> Result<usize, SyncError> {
+        let mut stream = TcpStream::connect(peer)?;
+        stream.set_read_timeout(Some(SYNC_TIMEOUT))?;
+        let mut downloaded = 0;
+        // Blocks of a fork that has not outweighed our chain yet
+        let mut branch: Vec<Block> = vec![];
+        loop {
+            let mut locator = self.chain.read().block_locator();
+            if let Some(last) = branch.last() {
+                locator.insert(0, last.header.hash.clone());
+            }
+            write_message(&mut stream, &Message::GetHeaders(locator))?;
+            let headers = match read_message(&mut stream)? {
+                Some(Message::Headers(headers)) => headers,
+                other => {
+                    return Err(SyncError::UnexpectedMessage(format!(
+                        "Expected headers, got {:?}.",
+                        other
+                    )))
+                }
+            };
+            let missing = {
+                let chain = self.chain.read();
+                headers
+                    .into_iter()
+                    .filter(|header| chain.get_block_by_hash(&header.hash).is_none())
+                    .map(|header| header.hash)
+                    .collect::<Vec<Hash>>()
+            };
+            if missing.is_empty() {
+                return Ok(downloaded);
+            }
+
+            write_message(&mut stream, &Message::GetBlocks(missing.clone()))?;
+            for hash in missing {
+                match read_message(&mut stream)? {
+                    Some(Message::Block(block)) if block.header.hash == hash => branch.push(block),
+                    other => {
+                        return Err(SyncError::UnexpectedMessage(format!(
+                            "Expected block {:?}, got {:?}.",
+                            hash, other
+                        )))
+                    }
+                }
+            }
+
+            let mut chain = self.chain.write();
+            let tip_hash = chain.tip_hash().unwrap_or_else(Hash::zero);
+            if branch[0].header.prev_block_hash == tip_hash {
+                downloaded += chain
+                    .import_blocks(std::mem::take(&mut branch))
+                    .map_err(SyncError::InvalidBlock)?;
+            } else if chain.try_reorg(&branch).map_err(SyncError::InvalidBlock)? {
+                downloaded += branch.len();
+                branch.clear();
+            }
+        }
+    }
+
+    /// Headers of our blocks after the latest one in `locator` we know, or
+    /// from the start of the chain if we know none of them.
+    fn headers_after(&self, locator: &[Hash]) -> Vec<BlockHeader> {
+        let chain = self.chain.read();
+        let fork = chain
+            .find_common_ancestor(locator)
+            .and_then(|hash| chain.get_block_by_hash(&hash))
+            .map(|block| block.header.index);
+        chain
+            .iter()
+            .filter(|block| fork.is_none_or(|fork| block.header.index > fork))
+            .take(MAX_HEADERS)
+            .map(|block| block.header.clone())
+            .collect()
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        while let Some(message) = read_message(&mut stream)? {
+            match message {
+                Message::GetHeaders(locator) => {
+                    let headers = self.headers_after(&locator);
+                    write_message(&mut stream, &Message::Headers(headers))?;
+                }
+                Message::GetBlocks(hashes) => {
+                    for hash in hashes.iter().take(MAX_HEADERS) {
+                        let block = self.chain.read().get_block_by_hash(hash).cloned();
+                        if let Some(block) = block {
+                            write_message(&mut stream, &Message::Block(block))?;
+                        }
+                    }
+                }
+                message => self.handle_message(message),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_message(&self, message: Message) {
+        match message {
+            Message::Hello(peer) => {
+                if peer != self.local_addr {
+                    lock(&self.peers).insert(peer);
+                }
+            }
+            Message::NewTransaction(transaction) => {
+                if !self.first_sighting(transaction.hash()) {
+                    return;
+                }
+                let accepted = self
+                    .chain
+                    .add_transaction_to_pool(transaction.clone())
+                    .is_ok();
+                if accepted {
+                    self.broadcast(&Message::NewTransaction(transaction));
+                }
+            }
+            Message::NewBlock(block) => {
+                if !self.first_sighting(block.header.hash.clone()) {
+                    return;
+                }
+                let accepted = self.chain.aggregate_mined_block(block.clone()).is_ok();
+                if accepted {
+                    self.broadcast(&Message::NewBlock(block));
+                }
+            }
+            // Requests and their answers only make sense on a connection
+            Message::GetHeaders(_)
+            | Message::Headers(_)
+            | Message::GetBlocks(_)
+            | Message::Block(_) => {}
+        }
+    }
+
+    /// Remember `hash` as handled, returning whether it was new. See
+    /// `MAX_SEEN`.
+    fn first_sighting(&self, hash: Hash) -> bool {
+        let mut seen = lock(&self.seen);
+        if seen.contains(&hash) {
+            return false;
+        }
+        if seen.len() >= MAX_SEEN {
+            seen.clear();
+        }
+        seen.insert(hash)
+    }
+
+    /// Send `message` to every peer. Peers that can't be reached are skipped.
+    fn broadcast(&self, message: &Message) {
+        for peer in self.peers() {
+            let _ = self.send(peer, message);
+        }
+    }
+
+    fn send(&self, peer: SocketAddr, message: &Message) -> io::Result<()> {
+        let mut stream = TcpStream::connect(peer)?;
+        write_message(&mut stream, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_message, write_message, Message, Node, MAX_SEEN};
+    use crate::{Blockchain, Hash, SharedChain};
+    use std::io::Cursor;
+
+    #[test]
+    fn should_round_trip_framed_messages() {
+        let addr = "127.0.0.1:8333".parse().unwrap();
+        let mut buffer = vec![];
+        write_message(&mut buffer, &Message::Hello(addr)).unwrap();
+        write_message(&mut buffer, &Message::Hello(addr)).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        for _ in 0..2 {
+            match read_message(&mut reader).unwrap() {
+                Some(Message::Hello(read)) => assert_eq!(addr, read),
+                other => panic!("expected hello, got {:?}", other),
+            }
+        }
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn should_forget_seen_hashes_once_full() {
+        let node = Node::bind("127.0.0.1:0", SharedChain::new(Blockchain::new())).unwrap();
+        let hash = |i: usize| Hash::from(i.to_le_bytes().to_vec());
+        for i in 0..MAX_SEEN {
+            assert!(node.first_sighting(hash(i)));
+        }
+        assert!(!node.first_sighting(hash(0)));
+        assert_eq!(MAX_SEEN, node.seen.lock().unwrap().len());
+
+        assert!(node.first_sighting(hash(MAX_SEEN)));
+        assert_eq!(1, node.seen.lock().unwrap().len());
+        assert!(node.first_sighting(hash(0)));
+    }
+
+    #[test]
+    fn should_reject_oversized_frame() {
+        let mut reader = Cursor::new(u32::MAX.to_le_bytes().to_vec());
+        assert!(read_message(&mut reader).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+// Hashing and proof-of-work checks that need neither the standard library
+// nor the chain's own types, so they also build with `default-features =
+// false` for embedded use. `HashAlgo` and the Merkle tree of blocks hash
+// through these functions.
+
+use alloc::vec::Vec;
+
+use primitive_types::U256;
+use sha2::{Digest as _, Sha256};
+
+/// Length of every digest, in bytes.
+pub const DIGEST_LEN: usize = 32;
+
+pub type Digest = [u8; DIGEST_LEN];
+
+/// SHA-256 of `bytes`.
+pub fn sha256(bytes: &[u8]) -> Digest {
+    let mut digest = [0; DIGEST_LEN];
+    digest.copy_from_slice(&Sha256::digest(bytes));
+    digest
+}
+
+/// BLAKE3 of `bytes`.
+pub fn blake3(bytes: &[u8]) -> Digest {
+    *::blake3::hash(bytes).as_bytes()
+}
+
+/// Whether `hash`, read as a big-endian integer, is below `target`.
+pub fn check_difficulty(hash: &[u8], target: U256) -> bool {
+    U256::from_big_endian(hash) < target
+}
+
+/// Root of a binary Merkle tree over `leaves`, hashing the concatenation of
+/// each pair of nodes with `digest`. Levels with an odd number of nodes
+/// duplicate their last node, and no leaves give an all-zero root, as in
+/// `merkle_root_with`.
+pub fn merkle_root(leaves: &[Digest], digest: impl Fn(&[u8]) -> Digest) -> Digest {
+    if leaves.is_empty() {
+        return [0; DIGEST_LEN];
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1]);
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let mut preimage = [0; 2 * DIGEST_LEN];
+                preimage[..DIGEST_LEN].copy_from_slice(&pair[0]);
+                preimage[DIGEST_LEN..].copy_from_slice(&pair[1]);
+                digest(&preimage)
+            })
+            .collect::<Vec<Digest>>();
+    }
+    level[0]
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{blake3, check_difficulty, merkle_root, sha256, Digest};
+    use crate::merkle::merkle_root_with;
+    use crate::{Hash, HashAlgo};
+    use primitive_types::U256;
+
+    #[test]
+    fn should_match_system_digests() {
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            hex::encode(sha256(b"abc"))
+        );
+        assert_eq!(
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            hex::encode(blake3(b"abc"))
+        );
+        for len in 0..300 {
+            let bytes = (0..len).map(|i| i as u8).collect::<Vec<u8>>();
+            assert_eq!(
+                crypto_hash::digest(crypto_hash::Algorithm::SHA256, &bytes),
+                sha256(&bytes)
+            );
+            assert_eq!(HashAlgo::Blake3.digest(&bytes).as_bytes(), blake3(&bytes));
+        }
+    }
+
+    #[test]
+    fn should_match_chain_merkle_root_and_difficulty() {
+        for count in 0..7 {
+            let leaves = (0..count).map(|i| sha256(&[i])).collect::<Vec<Digest>>();
+            let hashes = leaves
+                .iter()
+                .map(|leaf| Hash::from(leaf.to_vec()))
+                .collect::<Vec<Hash>>();
+            assert_eq!(
+                merkle_root_with(&hashes, HashAlgo::Sha256).as_bytes(),
+                merkle_root(&leaves, sha256)
+            );
+        }
+
+        let mut hash = [0; 32];
+        hash[31] = 5;
+        assert!(check_difficulty(&hash, U256::from(6)));
+        assert!(!check_difficulty(&hash, U256::from(5)));
+    }
+}
@@ -0,0 +1,287 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+
+use serde_json::{json, Value};
+
+use crate::blockchain::{BlockChainError, Blockchain};
+use crate::shared_chain::SharedChain;
+use crate::{Address, Hash, Hashable, Transaction};
+
+/// Standard JSON-RPC 2.0 error codes.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// The requested block or transaction is not known.
+pub const NOT_FOUND: i64 = -5;
+/// A transaction spends outputs that are missing or already spent.
+pub const TRANSACTION_ERROR: i64 = -25;
+/// A transaction was refused by the pool.
+pub const TRANSACTION_REJECTED: i64 = -26;
+/// Any other chain error.
+pub const MISC_ERROR: i64 = -1;
+
+/// Largest request body the server reads, in bytes.
+const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<BlockChainError> for RpcError {
+    fn from(error: BlockChainError) -> Self {
+        let code = match error {
+            BlockChainError::InputNotSpendableError(_)
+            | BlockChainError::DoubleSpendingError(_) => TRANSACTION_ERROR,
+            BlockChainError::InsufficientFundsError { .. }
+            | BlockChainError::InvalidTransactionError(_)
+            | BlockChainError::InvalidSignatureError(_)
+            | BlockChainError::DuplicateInput(_)
+            | BlockChainError::ChainIdMismatch { .. }
+            | BlockChainError::ImmatureCoinbase { .. }
+            | BlockChainError::TransactionLocked { .. }
+            | BlockChainError::MempoolFull(_) => TRANSACTION_REJECTED,
+            _ => MISC_ERROR,
+        };
+        RpcError::new(code, error.to_string())
+    }
+}
+
+/// HTTP JSON-RPC server answering queries against a shared chain.
+pub struct RpcServer {
+    listener: TcpListener,
+    chain: SharedChain,
+}
+
+impl RpcServer {
+    pub fn bind(addr: impl ToSocketAddrs, chain: SharedChain) -> io::Result<Self> {
+        Ok(RpcServer {
+            listener: TcpListener::bind(addr)?,
+            chain,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept connections forever, answering each on its own thread.
+    pub fn run(self) {
+        for stream in self.listener.incoming().flatten() {
+            let chain = self.chain.clone();
+            thread::spawn(move || {
+                // A client hanging up mid-request is not our problem
+                let _ = handle_connection(stream, &chain);
+            });
+        }
+    }
+
+    /// Run the server on a background thread.
+    pub fn spawn(self) -> JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+}
+
+fn handle_connection(stream: TcpStream, chain: &SharedChain) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    if !request_line.starts_with("POST ") {
+        return write_response(stream, "405 Method Not Allowed", "");
+    }
+    if content_length > MAX_BODY_BYTES {
+        return write_response(stream, "413 Payload Too Large", "");
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let response = match serde_json::from_slice::<Value>(&body) {
+        Ok(request) => handle_request(chain, &request),
+        Err(error) => error_response(Value::Null, RpcError::new(PARSE_ERROR, error.to_string())),
+    };
+    write_response(stream, "200 OK", &response.to_string())
+}
+
+fn write_response(mut stream: TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Answer a single JSON-RPC request object. Queries only take the chain's
+/// read lock.
+pub fn handle_request(chain: &SharedChain, request: &Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = match request.get("method").and_then(Value::as_str) {
+        Some(method) => method,
+        None => {
+            return error_response(id, RpcError::new(INVALID_REQUEST, "Missing method."));
+        }
+    };
+    let params = request
+        .get("params")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+
+    let result = match method {
+        "getblockcount" => Ok(json!(chain.len())),
+        "getblock" => get_block(&chain.read(), &params),
+        "getbalance" => get_balance(&chain.read(), &params),
+        "getrawtransaction" => get_raw_transaction(&chain.read(), &params),
+        "sendrawtransaction" => send_raw_transaction(&mut chain.write(), &params),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {}.", method),
+        )),
+    };
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+fn first_param(params: &[Value]) -> Result<&Value, RpcError> {
+    params
+        .first()
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing parameter."))
+}
+
+fn hash_param(param: &Value) -> Result<Hash, RpcError> {
+    param
+        .as_str()
+        .and_then(|hash| Hash::from_hex(hash).ok())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Expected a hex encoded hash."))
+}
+
+fn get_block(chain: &Blockchain, params: &[Value]) -> Result<Value, RpcError> {
+    let param = first_param(params)?;
+    let block = match param.as_u64() {
+        Some(index) => u32::try_from(index)
+            .ok()
+            .and_then(|index| chain.get_block_by_index(index)),
+        None => chain.get_block_by_hash(&hash_param(param)?),
+    };
+    let block = block.ok_or_else(|| RpcError::new(NOT_FOUND, "Block not found."))?;
+    Ok(json!(block))
+}
+
+fn get_balance(chain: &Blockchain, params: &[Value]) -> Result<Value, RpcError> {
+    let address = first_param(params)?
+        .as_str()
+        .and_then(|address| Address::from_str(address).ok())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Expected an address."))?;
+    Ok(json!(chain.balance_of(&address).units()))
+}
+
+fn get_raw_transaction(chain: &Blockchain, params: &[Value]) -> Result<Value, RpcError> {
+    let hash = hash_param(first_param(params)?)?;
+    let transaction = chain
+        .get_transaction(&hash)
+        .ok_or_else(|| RpcError::new(NOT_FOUND, "Transaction not found."))?;
+    Ok(json!(transaction))
+}
+
+fn send_raw_transaction(chain: &mut Blockchain, params: &[Value]) -> Result<Value, RpcError> {
+    let transaction = serde_json::from_value::<Transaction>(first_param(params)?.clone())
+        .map_err(|error| RpcError::new(INVALID_PARAMS, error.to_string()))?;
+    let hash = transaction.hash_with(chain.hash_algo());
+    chain.add_transaction_to_pool(transaction)?;
+    Ok(json!(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_request, INVALID_PARAMS, METHOD_NOT_FOUND, NOT_FOUND};
+    use crate::blockchain::Blockchain;
+    use crate::{Block, SharedChain};
+    use primitive_types::U256;
+    use secp256k1::rand;
+    use serde_json::json;
+
+    fn chain_with_genesis() -> SharedChain {
+        let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        let mut chain = Blockchain::new();
+        chain
+            .aggregate_mined_block(Block::genesis(
+                crate::Address::from_public_key(&public_key),
+                U256::max_value(),
+            ))
+            .unwrap();
+        SharedChain::new(chain)
+    }
+
+    fn call(chain: &SharedChain, method: &str, params: serde_json::Value) -> serde_json::Value {
+        handle_request(
+            chain,
+            &json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }),
+        )
+    }
+
+    #[test]
+    fn should_get_block_by_index_and_hash() {
+        let chain = chain_with_genesis();
+        let by_index = call(&chain, "getblock", json!([0]));
+        let hash = chain.read().latest_block().unwrap().header.hash.clone();
+        let by_hash = call(&chain, "getblock", json!([hash]));
+        assert_eq!(by_index["result"], by_hash["result"]);
+        assert_eq!(0, by_index["result"]["index"]);
+    }
+
+    #[test]
+    fn should_report_errors_with_codes() {
+        let chain = chain_with_genesis();
+        assert_eq!(
+            NOT_FOUND,
+            call(&chain, "getblock", json!([7]))["error"]["code"]
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            call(&chain, "getbalance", json!(["nope"]))["error"]["code"]
+        );
+        assert_eq!(
+            METHOD_NOT_FOUND,
+            call(&chain, "stop", json!([]))["error"]["code"]
+        );
+    }
+}
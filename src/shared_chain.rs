@@ -0,0 +1,161 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::blockchain::{BlockChainError, Blockchain};
+use crate::consensus::Consensus;
+use primitive_types::U256;
+
+use crate::{Address, Block, Coins, Hash, Transaction};
+
+/// A chain shared between threads. Clones are handles to the same chain:
+/// queries take a read lock, so they run alongside each other, and changes
+/// take the write lock.
+#[derive(Clone)]
+pub struct SharedChain {
+    chain: Arc<RwLock<Blockchain>>,
+}
+
+impl SharedChain {
+    pub fn new(chain: Blockchain) -> Self {
+        SharedChain {
+            chain: Arc::new(RwLock::new(chain)),
+        }
+    }
+
+    /// Read access for queries without a method of their own. Writers wait
+    /// until the guard is dropped, so keep it briefly.
+    pub fn read(&self) -> RwLockReadGuard<'_, Blockchain> {
+        // A thread panicking while holding a lock leaves the chain as it was
+        // after its last completed change, so keep using it
+        self.chain
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Write access for changes without a method of their own, see `read`.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Blockchain> {
+        self.chain
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn balance_of(&self, address: &Address) -> Coins {
+        self.read().balance_of(address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// A copy of the block at `index`, since the chain may move on once the
+    /// lock is released.
+    pub fn get_block_by_index(&self, index: u32) -> Option<Block> {
+        self.read().get_block_by_index(index).cloned()
+    }
+
+    pub fn tip_hash(&self) -> Option<Hash> {
+        self.read().tip_hash()
+    }
+
+    /// The chain's consensus engine, usable without holding a lock, e.g.
+    /// while mining.
+    pub fn consensus(&self) -> Arc<dyn Consensus> {
+        self.read().shared_consensus()
+    }
+
+    /// See `Blockchain::create_candidate_block`. The selected transactions
+    /// leave the shared pool.
+    pub fn create_candidate_block(
+        &self,
+        max_weight: u64,
+        miner_address: Address,
+        difficulty: Option<U256>,
+        tag: Option<&str>,
+    ) -> Block {
+        self.write()
+            .create_candidate_block(max_weight, miner_address, difficulty, tag)
+    }
+
+    pub fn add_transaction_to_pool(&self, transaction: Transaction) -> Result<(), BlockChainError> {
+        self.write().add_transaction_to_pool(transaction)
+    }
+
+    pub fn aggregate_mined_block(&self, block: Block) -> Result<(), BlockChainError> {
+        self.write().aggregate_mined_block(block)
+    }
+
+    /// A `Blockchain::snapshot` of the chain as it is now.
+    pub fn snapshot(&self) -> Blockchain {
+        self.read().snapshot()
+    }
+}
+
+impl From<Blockchain> for SharedChain {
+    fn from(chain: Blockchain) -> Self {
+        SharedChain::new(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SharedChain;
+    use crate::{Address, Blockchain, Coins};
+    use primitive_types::U256;
+    use secp256k1::rand;
+    use std::thread;
+
+    fn new_address() -> Address {
+        let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+        Address::from_public_key(&public_key)
+    }
+
+    #[test]
+    fn should_share_chain_between_reader_and_writer_threads() {
+        let miner = new_address();
+        let shared = SharedChain::new(Blockchain::with_genesis(miner.clone(), U256::max_value()));
+
+        let writer = {
+            let shared = shared.clone();
+            let miner = miner.clone();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    let mut block = shared.snapshot().create_candidate_block(
+                        u64::MAX,
+                        miner.clone(),
+                        Some(U256::max_value()),
+                        None,
+                    );
+                    block.mine();
+                    shared.aggregate_mined_block(block).unwrap();
+                }
+            })
+        };
+        let readers = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                let miner = miner.clone();
+                thread::spawn(move || {
+                    let mut last_len = 0;
+                    while last_len < 11 {
+                        let len = shared.len();
+                        assert!(len >= last_len);
+                        let tip = shared.get_block_by_index(len as u32 - 1).unwrap();
+                        assert_eq!(len as u32 - 1, tip.header.index);
+                        assert!(shared.balance_of(&miner) > Coins::ZERO);
+                        last_len = len;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(11, shared.len());
+        assert!(shared.snapshot().validate().is_ok());
+    }
+}
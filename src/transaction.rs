@@ -1,78 +1,731 @@
-use crate::Hash;
+use std::collections::HashSet;
+use std::fmt;
+
+use secp256k1::{ecdsa, Message, PublicKey, SecretKey, SECP256K1};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::BlockChainError;
+use crate::hashable::HashAlgo;
+use crate::{now, Hash};
 
 use super::Address;
+use super::Coins;
 use super::Hashable;
 use super::TimeStamp;
 
-#[derive(Debug, Clone)]
+/// Largest payload a data output may carry, in bytes.
+pub const MAX_DATA_BYTES: usize = 80;
+
+/// Weight every transaction carries regardless of its contents.
+pub const BASE_WEIGHT: u64 = 10;
+
+/// Weight of each input, which costs a lookup and a signature check.
+pub const INPUT_WEIGHT: u64 = 100;
+
+/// Weight of each output.
+pub const OUTPUT_WEIGHT: u64 = 40;
+
+/// Most inputs a transaction may spend.
+pub const MAX_TX_INPUTS: usize = 1_000;
+
+/// Most outputs a transaction may create.
+pub const MAX_TX_OUTPUTS: usize = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TxOutput {
     pub address: Address,
-    pub value: f64,
+    pub value: Coins,
+    /// Arbitrary bytes committed to by the transaction. An output carrying
+    /// data holds no value and can never be spent, so it is not added to the
+    /// unspent output set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<u8>>,
+}
+
+impl TxOutput {
+    pub fn is_unspendable(&self) -> bool {
+        self.data.is_some()
+    }
+}
+
+/// Names an output by the hash of the transaction that created it and the
+/// output's position among that transaction's outputs.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct OutPoint {
+    pub tx_hash: Hash,
+    pub index: u32,
+}
+
+/// Shown as the transaction hash and output index separated by a colon.
+impl fmt::Display for OutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.tx_hash, self.index)
+    }
+}
+
+/// Spends output `index` of transaction `prev_tx`. The output's value and
+/// owner are looked up in the unspent output set, so an input can't claim
+/// more than it spends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxInput {
+    pub prev_tx: Hash,
+    pub index: u32,
+    /// Made by the key owning the spent output. `None` until signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
 }
 
-#[derive(Debug, Clone)]
+impl TxInput {
+    /// An unsigned input spending `outpoint`.
+    pub fn new(outpoint: OutPoint) -> Self {
+        TxInput {
+            prev_tx: outpoint.tx_hash,
+            index: outpoint.index,
+            signature: None,
+        }
+    }
+
+    pub fn outpoint(&self) -> OutPoint {
+        OutPoint {
+            tx_hash: self.prev_tx.clone(),
+            index: self.index,
+        }
+    }
+}
+
+/// An ECDSA signature over a transaction, together with the public key that
+/// produced it so the signer can be matched against the spent output's
+/// address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    pub public_key: PublicKey,
+    pub signature: ecdsa::Signature,
+}
+
+impl Signature {
+    /// Check the signature against a transaction's signing hash.
+    pub fn verify(&self, signing_hash: &Hash) -> bool {
+        match Message::from_digest_slice(signing_hash.as_bytes()) {
+            Ok(message) => SECP256K1
+                .verify_ecdsa(&message, &self.signature, &self.public_key)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn signer_address(&self) -> Address {
+        Address::from_public_key(&self.public_key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    NoOutputs,
+    /// A data output carries more than `MAX_DATA_BYTES`.
+    DataTooLarge(usize),
+}
+
+/// Lock times below this are block heights, anything at or above it is a
+/// unix time in seconds.
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
-    pub inputs: Vec<TxOutput>,
+    pub inputs: Vec<TxInput>,
     pub outputs: Vec<TxOutput>,
     pub timestamp: TimeStamp,
+    /// Earliest block height or unix time (see `LOCKTIME_THRESHOLD`) at which
+    /// the transaction may be mined. Zero means no lock.
+    pub lock_time: u64,
+    /// Id of the chain the transaction is meant for. It is signed along with
+    /// the rest, so the transaction can't be replayed on another chain.
+    #[serde(default)]
+    pub chain_id: u32,
 }
 
 impl Transaction {
-    fn compute_inputs(&self) -> f64 {
-        self.inputs.iter().map(|input| input.value).sum()
+    pub fn builder() -> TransactionBuilder {
+        TransactionBuilder::new()
     }
 
-    fn compute_outputs(&self) -> f64 {
-        self.outputs.iter().map(|output| output.value).sum()
+    /// Total value of `spent`, the outputs the inputs spend in input order,
+    /// or `None` if they don't match the inputs one to one.
+    fn compute_inputs(&self, spent: &[TxOutput]) -> Option<Coins> {
+        if spent.len() != self.inputs.len() {
+            return None;
+        }
+        Coins::checked_sum(spent.iter().map(|output| output.value))
     }
 
-    pub fn is_spendable(&self) -> bool {
-        self.compute_inputs() > self.compute_outputs()
+    fn compute_outputs(&self) -> Option<Coins> {
+        Coins::checked_sum(
+            self.outputs
+                .iter()
+                .filter(|output| !output.is_unspendable())
+                .map(|output| output.value),
+        )
+    }
+
+    /// Whether the inputs, spending `spent` in input order, pay for more
+    /// than the outputs.
+    pub fn is_spendable(&self, spent: &[TxOutput]) -> bool {
+        match (self.compute_inputs(spent), self.compute_outputs()) {
+            (Some(inputs), Some(outputs)) => inputs > outputs,
+            _ => false,
+        }
+    }
+
+    /// Amount by which the inputs, spending `spent` in input order, exceed
+    /// the outputs, claimable by the miner. Zero for coinbases and for
+    /// transactions that are not spendable.
+    pub fn fee(&self, spent: &[TxOutput]) -> Coins {
+        match (self.compute_inputs(spent), self.compute_outputs()) {
+            (Some(inputs), Some(outputs)) => inputs.checked_sub(outputs).unwrap_or(Coins::ZERO),
+            _ => Coins::ZERO,
+        }
+    }
+
+    /// Fee paid per serialized byte, in smallest units. See `fee`.
+    pub fn fee_rate(&self, spent: &[TxOutput]) -> f64 {
+        self.fee(spent).units() as f64 / self.serialized_size() as f64
+    }
+
+    /// Size of the transaction in its serialized (JSON) form, in bytes.
+    pub fn serialized_size(&self) -> usize {
+        serde_json::to_vec(self)
+            .expect("transaction is serializable")
+            .len()
+    }
+
+    /// Cost of including the transaction in a block: `BASE_WEIGHT`, plus
+    /// `INPUT_WEIGHT` per input and `OUTPUT_WEIGHT` per output, plus one for
+    /// every byte of output data.
+    pub fn weight(&self) -> u64 {
+        let data_bytes = self
+            .outputs
+            .iter()
+            .filter_map(|output| output.data.as_ref())
+            .map(|data| data.len() as u64)
+            .sum::<u64>();
+        BASE_WEIGHT
+            + INPUT_WEIGHT * self.inputs.len() as u64
+            + OUTPUT_WEIGHT * self.outputs.len() as u64
+            + data_bytes
+    }
+
+    /// Whether every data output holds no value and at most
+    /// `MAX_DATA_BYTES` of data.
+    pub fn has_valid_data_outputs(&self) -> bool {
+        self.outputs.iter().all(|output| match &output.data {
+            Some(data) => output.value == Coins::ZERO && data.len() <= MAX_DATA_BYTES,
+            None => true,
+        })
+    }
+
+    /// The checks that need no chain: input and output counts within
+    /// `MAX_TX_INPUTS` and `MAX_TX_OUTPUTS`, no input listed twice, at least
+    /// one output, a positive value on every spendable output and valid data
+    /// outputs. `Blockchain::verify_transaction` runs them before looking at
+    /// the outputs spent. Coinbases, having no inputs, pass the same way.
+    pub fn verify_structure(&self) -> Result<(), BlockChainError> {
+        // check the transaction stays cheap to hash and validate
+        if self.inputs.len() > MAX_TX_INPUTS {
+            return Err(BlockChainError::InvalidTransactionError(format!(
+                "Transaction has {} inputs, more than the {} allowed.",
+                self.inputs.len(),
+                MAX_TX_INPUTS
+            )));
+        }
+        if self.outputs.len() > MAX_TX_OUTPUTS {
+            return Err(BlockChainError::InvalidTransactionError(format!(
+                "Transaction has {} outputs, more than the {} allowed.",
+                self.outputs.len(),
+                MAX_TX_OUTPUTS
+            )));
+        }
+        // check no output is listed twice, which would count its value twice
+        let mut distinct_inputs = HashSet::new();
+        if let Some(duplicate) = self
+            .outpoints()
+            .into_iter()
+            .find(|outpoint| !distinct_inputs.insert(outpoint.clone()))
+        {
+            return Err(BlockChainError::DuplicateInput(duplicate));
+        }
+        // check there is something to pay and no output pays nothing; Coins
+        // are unsigned, so no value can be negative
+        if self.outputs.is_empty() {
+            return Err(BlockChainError::InvalidTransactionError(String::from(
+                "Transaction has no outputs.",
+            )));
+        }
+        if self
+            .outputs
+            .iter()
+            .any(|output| !output.is_unspendable() && output.value == Coins::ZERO)
+        {
+            return Err(BlockChainError::InvalidTransactionError(String::from(
+                "Spendable outputs must hold a positive value.",
+            )));
+        }
+        // check data outputs hold no value and stay small
+        if !self.has_valid_data_outputs() {
+            return Err(BlockChainError::InvalidTransactionError(format!(
+                "Data outputs must hold no value and at most {} bytes.",
+                MAX_DATA_BYTES
+            )));
+        }
+        Ok(())
     }
 
     pub fn is_coinbase(&self) -> bool {
-        self.inputs.len() == 0
+        self.inputs.is_empty()
     }
 
-    pub fn input_hashes(&self) -> Vec<Hash> {
+    /// Canonical encoding used for both the transaction hash and signing:
+    /// the inputs as outpoints and the outputs, each list prefixed by its
+    /// length as a `u32`, then the timestamp, lock time and chain id. All
+    /// integers are little-endian. Signatures are not included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend((self.inputs.len() as u32).to_le_bytes());
+        for input in &self.inputs {
+            bytes.extend(input.prev_tx.as_bytes());
+            bytes.extend(input.index.to_le_bytes());
+        }
+        bytes.extend((self.outputs.len() as u32).to_le_bytes());
+        for output in &self.outputs {
+            bytes.extend(output.bytes());
+        }
+        bytes.extend(self.timestamp.to_le_bytes());
+        bytes.extend(self.lock_time.to_le_bytes());
+        bytes.extend(self.chain_id.to_le_bytes());
+        bytes
+    }
+
+    /// Whether the lock time has passed for a block at `height` mined after
+    /// `time`, given in unix seconds.
+    pub fn is_final(&self, height: u32, time: u64) -> bool {
+        if self.lock_time < LOCKTIME_THRESHOLD {
+            self.lock_time <= u64::from(height)
+        } else {
+            self.lock_time <= time
+        }
+    }
+
+    /// The outputs spent by the inputs, in input order.
+    pub fn outpoints(&self) -> Vec<OutPoint> {
         self.inputs
             .iter()
-            .map(|input| input.hash())
-            .collect::<Vec<Hash>>()
+            .map(TxInput::outpoint)
+            .collect::<Vec<OutPoint>>()
     }
 
+    /// Hashes of all outputs, data outputs included, in output order.
     pub fn output_hashes(&self) -> Vec<Hash> {
         self.outputs
             .iter()
             .map(|output| output.hash())
             .collect::<Vec<Hash>>()
     }
+
+    /// The outputs that join the unspent output set, named by this
+    /// transaction's hash under `algo` and their position. Data outputs are
+    /// left out but keep their position.
+    pub fn spendable_outputs(&self, algo: HashAlgo) -> Vec<(OutPoint, TxOutput)> {
+        let tx_hash = self.hash_with(algo);
+        self.outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| !output.is_unspendable())
+            .map(|(index, output)| {
+                let outpoint = OutPoint {
+                    tx_hash: tx_hash.clone(),
+                    index: index as u32,
+                };
+                (outpoint, output.clone())
+            })
+            .collect()
+    }
+
+    /// Sign the transaction's canonical bytes with `key` for every input.
+    pub fn sign(&mut self, key: &SecretKey) {
+        for position in 0..self.inputs.len() {
+            self.sign_input(position, key);
+        }
+    }
+
+    /// Sign the transaction's canonical bytes with `key` for the input at
+    /// `position` only, for transactions spending outputs of several keys.
+    /// Signatures are not part of the signed bytes, so inputs may be signed
+    /// in any order.
+    pub fn sign_input(&mut self, position: usize, key: &SecretKey) {
+        let message = Message::from_digest_slice(self.hash().as_bytes())
+            .expect("transaction hash is 32 bytes");
+        self.inputs[position].signature = Some(Signature {
+            public_key: PublicKey::from_secret_key_global(key),
+            signature: SECP256K1.sign_ecdsa(&message, key),
+        });
+    }
+
+    /// Check that every input carries a valid signature from the key owning
+    /// the output it spends, given as `spent` in input order.
+    pub fn has_valid_signatures(&self, spent: &[TxOutput]) -> bool {
+        let signing_hash = self.hash();
+        spent.len() == self.inputs.len()
+            && self.inputs.iter().zip(spent).all(|(input, output)| {
+                input.signature.as_ref().is_some_and(|signature| {
+                    signature.signer_address() == output.address && signature.verify(&signing_hash)
+                })
+            })
+    }
+}
+
+/// One line: the hash, then input and output counts and the total output value.
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self
+            .compute_outputs()
+            .map_or_else(|| String::from("overflow"), |total| total.to_string());
+        write!(
+            f,
+            "Transaction {} ({} inputs, {} outputs, {} coins)",
+            self.hash(),
+            self.inputs.len(),
+            self.outputs.len(),
+            total
+        )
+    }
+}
+
+/// Assembles a `Transaction` one input and output at a time. The result is
+/// unsigned; sign it once it is built.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionBuilder {
+    inputs: Vec<TxInput>,
+    outputs: Vec<TxOutput>,
+    timestamp: Option<TimeStamp>,
+    lock_time: u64,
+    chain_id: u32,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spend the output at `outpoint`, which must be unspent and owned by
+    /// the signer.
+    pub fn input(mut self, outpoint: OutPoint) -> Self {
+        self.inputs.push(TxInput::new(outpoint));
+        self
+    }
+
+    pub fn output(mut self, address: Address, value: Coins) -> Self {
+        self.outputs.push(TxOutput {
+            address,
+            value,
+            data: None,
+        });
+        self
+    }
+
+    /// Add an unspendable output committing to `data`.
+    pub fn data(mut self, data: Vec<u8>) -> Self {
+        self.outputs.push(TxOutput {
+            address: Address::null(),
+            value: Coins::ZERO,
+            data: Some(data),
+        });
+        self
+    }
+
+    pub fn lock_time(mut self, lock_time: u64) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    pub fn chain_id(mut self, chain_id: u32) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    /// Use `timestamp` instead of the time `build` is called.
+    pub fn timestamp(mut self, timestamp: TimeStamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn build(self) -> Result<Transaction, TransactionError> {
+        if self.outputs.is_empty() {
+            return Err(TransactionError::NoOutputs);
+        }
+        if let Some(data) = self
+            .outputs
+            .iter()
+            .filter_map(|output| output.data.as_ref())
+            .find(|data| data.len() > MAX_DATA_BYTES)
+        {
+            return Err(TransactionError::DataTooLarge(data.len()));
+        }
+        Ok(Transaction {
+            inputs: self.inputs,
+            outputs: self.outputs,
+            timestamp: self.timestamp.unwrap_or_else(now),
+            lock_time: self.lock_time,
+            chain_id: self.chain_id,
+        })
+    }
 }
 
 impl Hashable for TxOutput {
     fn bytes(&self) -> Vec<u8> {
         let mut to_bytes = vec![];
-        to_bytes.extend(self.address.as_bytes());
+        to_bytes.extend(self.address.to_bytes());
         to_bytes.extend(self.value.to_le_bytes());
+        // Plain outputs encode as before; data is flagged and length-prefixed
+        if let Some(data) = &self.data {
+            to_bytes.push(1);
+            to_bytes.extend((data.len() as u32).to_le_bytes());
+            to_bytes.extend(data);
+        }
         to_bytes
     }
 }
 
 impl Hashable for Transaction {
     fn bytes(&self) -> Vec<u8> {
-        let mut tx_output = vec![];
-        tx_output.extend(
-            self.inputs
-                .iter()
-                .flat_map(|input| input.bytes())
-                .collect::<Vec<u8>>(),
+        self.to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        OutPoint, Transaction, TransactionError, TxInput, TxOutput, BASE_WEIGHT, INPUT_WEIGHT,
+        MAX_DATA_BYTES, MAX_TX_INPUTS, MAX_TX_OUTPUTS, OUTPUT_WEIGHT,
+    };
+    use crate::blockchain::BlockChainError;
+    use crate::{Address, Coins, Hash, HashAlgo, Hashable};
+    use secp256k1::{PublicKey, SecretKey};
+
+    fn sample_key() -> (SecretKey, Address) {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let address = Address::from_public_key(&PublicKey::from_secret_key_global(&key));
+        (key, address)
+    }
+
+    fn sample_outpoint() -> OutPoint {
+        OutPoint {
+            tx_hash: Hash::zero(),
+            index: 1,
+        }
+    }
+
+    /// The output `sample_transaction` spends.
+    fn sample_spent() -> Vec<TxOutput> {
+        vec![TxOutput {
+            address: sample_key().1,
+            value: Coins(3),
+            data: None,
+        }]
+    }
+
+    fn sample_transaction() -> Transaction {
+        let (_, address) = sample_key();
+        Transaction::builder()
+            .input(sample_outpoint())
+            .output(address, Coins(2))
+            .timestamp(5)
+            .lock_time(7)
+            .chain_id(9)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_weigh_inputs_outputs_and_data() {
+        let transaction = sample_transaction();
+        assert_eq!(
+            BASE_WEIGHT + INPUT_WEIGHT + OUTPUT_WEIGHT,
+            transaction.weight()
         );
-        tx_output.extend(
-            self.outputs
-                .iter()
-                .flat_map(|output| output.bytes())
-                .collect::<Vec<u8>>(),
+
+        let mut with_data = transaction.clone();
+        with_data.outputs.push(TxOutput {
+            address: Address::null(),
+            value: Coins::ZERO,
+            data: Some(vec![0; 12]),
+        });
+        assert_eq!(
+            transaction.weight() + OUTPUT_WEIGHT + 12,
+            with_data.weight()
+        );
+    }
+
+    #[test]
+    fn should_verify_structure_without_a_chain() {
+        let transaction = sample_transaction();
+        assert!(transaction.verify_structure().is_ok());
+        let invalid = |change: &dyn Fn(&mut Transaction)| {
+            let mut transaction = sample_transaction();
+            change(&mut transaction);
+            transaction.verify_structure().unwrap_err()
+        };
+        let message = |error: BlockChainError| match error {
+            BlockChainError::InvalidTransactionError(message) => message,
+            other => panic!("expected invalid transaction, got {:?}", other),
+        };
+
+        let too_many_inputs = invalid(&|transaction| {
+            transaction.inputs = (0..=MAX_TX_INPUTS as u32)
+                .map(|index| {
+                    TxInput::new(OutPoint {
+                        tx_hash: Hash::zero(),
+                        index,
+                    })
+                })
+                .collect();
+        });
+        assert!(message(too_many_inputs).contains("inputs"));
+        let too_many_outputs = invalid(&|transaction| {
+            let output = transaction.outputs[0].clone();
+            transaction.outputs = vec![output; MAX_TX_OUTPUTS + 1];
+        });
+        assert!(message(too_many_outputs).contains("outputs"));
+        match invalid(&|transaction| transaction.inputs.push(TxInput::new(sample_outpoint()))) {
+            BlockChainError::DuplicateInput(outpoint) => assert_eq!(sample_outpoint(), outpoint),
+            other => panic!("expected duplicate input, got {:?}", other),
+        }
+        assert_eq!(
+            "Transaction has no outputs.",
+            message(invalid(&|transaction| transaction.outputs.clear()))
+        );
+        assert_eq!(
+            "Spendable outputs must hold a positive value.",
+            message(invalid(
+                &|transaction| transaction.outputs[0].value = Coins::ZERO
+            ))
         );
-        tx_output
+        let valued_data = invalid(&|transaction| {
+            transaction.outputs.push(TxOutput {
+                address: Address::null(),
+                value: Coins(1),
+                data: Some(vec![0; 4]),
+            })
+        });
+        assert!(message(valued_data).starts_with("Data outputs"));
+    }
+
+    #[test]
+    fn should_reject_building_without_outputs() {
+        let result = Transaction::builder().input(sample_outpoint()).build();
+        assert_eq!(Some(TransactionError::NoOutputs), result.err());
+    }
+
+    #[test]
+    fn should_reject_oversized_data() {
+        let (_, address) = sample_key();
+        let result = Transaction::builder()
+            .output(address, Coins(2))
+            .data(vec![0; MAX_DATA_BYTES + 1])
+            .build();
+        assert_eq!(
+            Some(TransactionError::DataTooLarge(MAX_DATA_BYTES + 1)),
+            result.err()
+        );
+    }
+
+    #[test]
+    fn should_commit_to_data_without_counting_it() {
+        let plain = sample_transaction();
+        let mut with_data = plain.clone();
+        with_data.outputs.push(TxOutput {
+            address: Address::null(),
+            value: Coins::ZERO,
+            data: Some(b"hello".to_vec()),
+        });
+
+        assert_ne!(plain.hash(), with_data.hash());
+        assert_eq!(plain.fee(&sample_spent()), with_data.fee(&sample_spent()));
+        let spendable = with_data.spendable_outputs(HashAlgo::Sha256);
+        assert_eq!(1, spendable.len());
+        assert_eq!(0, spendable[0].0.index);
+        assert!(with_data.has_valid_data_outputs());
+
+        with_data.outputs[1].value = Coins(1);
+        assert!(!with_data.has_valid_data_outputs());
+    }
+
+    #[test]
+    fn should_serialize_to_canonical_bytes() {
+        let transaction = sample_transaction();
+        let address = "00f1d12012406b87afb27f6dd16ac0a76fcdaa55ed";
+        let expected = [
+            "01000000",
+            &"00".repeat(32),
+            "01000000",
+            "01000000",
+            address,
+            "0200000000000000",
+            "05000000000000000000000000000000",
+            "0700000000000000",
+            "09000000",
+        ]
+        .concat();
+        assert_eq!(expected, hex::encode(transaction.to_bytes()));
+    }
+
+    #[test]
+    fn should_hash_differently_under_each_algorithm() {
+        let transaction = sample_transaction();
+        let sha256 = transaction.hash_with(HashAlgo::Sha256);
+        let blake3 = transaction.hash_with(HashAlgo::Blake3);
+
+        assert_ne!(sha256, blake3);
+        assert_eq!(sha256, transaction.hash());
+        assert_eq!(sha256, sample_transaction().hash_with(HashAlgo::Sha256));
+        assert_eq!(blake3, sample_transaction().hash_with(HashAlgo::Blake3));
+    }
+
+    #[test]
+    fn should_display_counts_and_total() {
+        let transaction = sample_transaction();
+        assert_eq!(
+            format!(
+                "Transaction {} (1 inputs, 1 outputs, 0.00000002 coins)",
+                transaction.hash()
+            ),
+            transaction.to_string()
+        );
+    }
+
+    #[test]
+    fn should_value_inputs_by_the_outputs_they_spend() {
+        let transaction = sample_transaction();
+        assert!(transaction.is_spendable(&sample_spent()));
+        assert_eq!(Coins(1), transaction.fee(&sample_spent()));
+
+        let mut cheaper = sample_spent();
+        cheaper[0].value = Coins(2);
+        assert!(!transaction.is_spendable(&cheaper));
+        assert_eq!(Coins::ZERO, transaction.fee(&cheaper));
+        assert!(!transaction.is_spendable(&[]));
+    }
+
+    #[test]
+    fn should_require_signature_from_each_spent_output_owner() {
+        let (key, _) = sample_key();
+        let mut transaction = sample_transaction();
+        assert!(!transaction.has_valid_signatures(&sample_spent()));
+
+        transaction.sign(&key);
+        assert!(transaction.has_valid_signatures(&sample_spent()));
+        assert_eq!(sample_transaction().hash(), transaction.hash());
+
+        let mut foreign = sample_spent();
+        foreign[0].address = Address::null();
+        assert!(!transaction.has_valid_signatures(&foreign));
+        assert!(!transaction.has_valid_signatures(&[]));
     }
 }
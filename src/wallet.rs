@@ -0,0 +1,428 @@
+use std::cmp::Reverse;
+
+use secp256k1::{PublicKey, SecretKey};
+
+use crate::blockchain::Blockchain;
+
+use super::Address;
+use super::Coins;
+use super::Transaction;
+use super::TxOutput;
+use crate::transaction::OutPoint;
+
+/// Fee rate the wallet pays, in smallest units per serialized byte.
+pub const DEFAULT_FEE_RATE: f64 = 2.0;
+
+/// Change smaller than this is added to the fee instead of creating an
+/// output hardly worth the fee to spend later.
+pub const DUST_THRESHOLD: Coins = Coins(546);
+
+/// Estimated serialized size of a transaction with no inputs or outputs but
+/// one signature, and of each input or output on top of that.
+const TX_BASE_BYTES: usize = 324;
+const TX_OUTPUT_BYTES: usize = 70;
+
+/// Outputs chosen to fund a payment, with the fee they pay and the change
+/// left over. A zero change means no change output is needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub inputs: Vec<(OutPoint, TxOutput)>,
+    pub fee: Coins,
+    pub change: Coins,
+}
+
+/// Fee for a single-signer transaction with the given number of inputs and
+/// outputs at `fee_rate` units per byte. Never less than one unit, since a
+/// transaction must spend more than it creates.
+pub fn estimate_fee(inputs: usize, outputs: usize, fee_rate: f64) -> Coins {
+    let size = TX_BASE_BYTES + (inputs + outputs) * TX_OUTPUT_BYTES;
+    Coins(((size as f64 * fee_rate).ceil() as u64).max(1))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletError {
+    /// The wallet's spendable outputs add up to less than the amount plus fee.
+    InsufficientFunds { available: Coins, required: Coins },
+    /// A seed or seed phrase that can't be turned into keys.
+    InvalidSeed(String),
+    /// A payment was asked for without anyone to pay.
+    NoRecipients,
+}
+
+/// A single secp256k1 key pair and the address it controls.
+pub struct Wallet {
+    secret_key: SecretKey,
+    public_key: PublicKey,
+}
+
+impl Wallet {
+    /// Generate a wallet with a fresh random key.
+    pub fn new() -> Self {
+        let (secret_key, public_key) =
+            secp256k1::generate_keypair(&mut secp256k1::rand::thread_rng());
+        Wallet {
+            secret_key,
+            public_key,
+        }
+    }
+
+    pub fn from_secret_key(secret_key: SecretKey) -> Self {
+        Wallet {
+            public_key: PublicKey::from_secret_key_global(&secret_key),
+            secret_key,
+        }
+    }
+
+    pub fn secret_key(&self) -> &SecretKey {
+        &self.secret_key
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from_public_key(&self.public_key)
+    }
+
+    /// Coins in outputs the wallet can spend in the next block.
+    pub fn balance(&self, chain: &Blockchain) -> Coins {
+        chain
+            .utxos_for(&self.address())
+            .iter()
+            .filter(|(outpoint, _)| chain.is_mature(outpoint))
+            .fold(Coins::ZERO, |total, (_, output)| {
+                total.saturating_add(output.value)
+            })
+    }
+
+    /// Choose outputs from `utxos` paying `target` plus the fee at
+    /// `fee_rate`. Prefers the smallest single output that covers it all,
+    /// falling back to adding outputs largest first, each raising the fee by
+    /// the size of one more input. Change below `DUST_THRESHOLD` goes to the
+    /// fee.
+    pub fn select_coins(
+        &self,
+        utxos: &[(OutPoint, TxOutput)],
+        target: Coins,
+        fee_rate: f64,
+    ) -> Result<Selection, WalletError> {
+        select_coins_for(utxos, target, 1, fee_rate)
+    }
+
+    /// Outputs owned by the wallet that may be spent in the next block.
+    fn spendable_utxos(&self, chain: &Blockchain) -> Vec<(OutPoint, TxOutput)> {
+        chain
+            .utxos_for(&self.address())
+            .into_iter()
+            .filter(|(outpoint, _)| chain.is_mature(outpoint))
+            .collect()
+    }
+
+    /// Build and sign a transaction paying `amount` to `to` from the wallet's
+    /// spendable outputs at `DEFAULT_FEE_RATE`, sending any change back to
+    /// the wallet.
+    pub fn create_transaction(
+        &self,
+        chain: &Blockchain,
+        to: &Address,
+        amount: Coins,
+    ) -> Result<Transaction, WalletError> {
+        self.create_batch_transaction(chain, &[(to.clone(), amount)])
+    }
+
+    /// Like `create_transaction`, but pays every recipient in `recipients`
+    /// from one transaction, with one output each in the given order
+    /// followed by the change. Coins are selected for the total of all
+    /// payments, so either everyone is paid or nothing is built.
+    pub fn create_batch_transaction(
+        &self,
+        chain: &Blockchain,
+        recipients: &[(Address, Coins)],
+    ) -> Result<Transaction, WalletError> {
+        if recipients.is_empty() {
+            return Err(WalletError::NoRecipients);
+        }
+        let utxos = self.spendable_utxos(chain);
+        let total = Coins::checked_sum(recipients.iter().map(|(_, amount)| *amount)).ok_or(
+            WalletError::InsufficientFunds {
+                available: self.balance(chain),
+                required: Coins(u64::MAX),
+            },
+        )?;
+        let selection = select_coins_for(&utxos, total, recipients.len(), DEFAULT_FEE_RATE)?;
+
+        let mut builder = selection
+            .inputs
+            .into_iter()
+            .fold(Transaction::builder(), |builder, (outpoint, _)| {
+                builder.input(outpoint)
+            })
+            .chain_id(chain.chain_id());
+        for (to, amount) in recipients {
+            builder = builder.output(to.clone(), *amount);
+        }
+        if selection.change > Coins::ZERO {
+            builder = builder.output(self.address(), selection.change);
+        }
+        let mut transaction = builder.build().expect("transaction pays the recipients");
+        transaction.sign(&self.secret_key);
+        Ok(transaction)
+    }
+}
+
+/// `Wallet::select_coins` for a transaction with `payments` outputs besides
+/// the change.
+fn select_coins_for(
+    utxos: &[(OutPoint, TxOutput)],
+    target: Coins,
+    payments: usize,
+    fee_rate: f64,
+) -> Result<Selection, WalletError> {
+    let mut candidates = utxos.iter().collect::<Vec<&(OutPoint, TxOutput)>>();
+    candidates.sort_by_key(|(_, output)| Reverse(output.value));
+
+    let single = candidates
+        .iter()
+        .rev()
+        .find_map(|utxo| settle(std::slice::from_ref(*utxo), target, payments, fee_rate));
+    if let Some(selection) = single {
+        return Ok(selection);
+    }
+
+    for count in 2..=candidates.len() {
+        let inputs = candidates[..count]
+            .iter()
+            .map(|utxo| (*utxo).clone())
+            .collect::<Vec<(OutPoint, TxOutput)>>();
+        if let Some(selection) = settle(&inputs, target, payments, fee_rate) {
+            return Ok(selection);
+        }
+    }
+
+    let available = candidates.iter().fold(Coins::ZERO, |total, (_, output)| {
+        total.saturating_add(output.value)
+    });
+    Err(WalletError::InsufficientFunds {
+        available,
+        required: target.saturating_add(estimate_fee(candidates.len().max(1), payments, fee_rate)),
+    })
+}
+
+/// Work out fee and change for spending exactly `inputs` on `payments`
+/// outputs worth `target`, or `None` if they don't cover it and the fee.
+fn settle(
+    inputs: &[(OutPoint, TxOutput)],
+    target: Coins,
+    payments: usize,
+    fee_rate: f64,
+) -> Option<Selection> {
+    let selected = Coins::checked_sum(inputs.iter().map(|(_, output)| output.value))?;
+    let fee_with_change = estimate_fee(inputs.len(), payments + 1, fee_rate);
+    let change = selected
+        .checked_sub(target)
+        .and_then(|excess| excess.checked_sub(fee_with_change));
+    if let Some(change) = change.filter(|change| *change >= DUST_THRESHOLD) {
+        return Some(Selection {
+            inputs: inputs.to_vec(),
+            fee: fee_with_change,
+            change,
+        });
+    }
+    let fee = selected.checked_sub(target)?;
+    (fee >= estimate_fee(inputs.len(), payments, fee_rate)).then(|| Selection {
+        inputs: inputs.to_vec(),
+        fee,
+        change: Coins::ZERO,
+    })
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_fee, Wallet, WalletError, DEFAULT_FEE_RATE, DUST_THRESHOLD};
+    use crate::blockchain::{Blockchain, COINBASE_MATURITY};
+    use crate::{Address, ChainParams, Coins, Hash, OutPoint, TxOutput};
+
+    fn mine_block(chain: &mut Blockchain, miner: Address) {
+        let mut block = chain.create_candidate_block(u64::MAX, miner, None, None);
+        block.mine();
+        chain.aggregate_mined_block(block).unwrap();
+    }
+
+    fn mined_chain(owner: &Wallet) -> Blockchain {
+        let mut chain = Blockchain::with_params(ChainParams::regtest());
+        mine_block(&mut chain, owner.address());
+        for _ in 1..COINBASE_MATURITY {
+            mine_block(&mut chain, Wallet::new().address());
+        }
+        chain
+    }
+
+    fn utxos(wallet: &Wallet, values: &[u64]) -> Vec<(OutPoint, TxOutput)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                let outpoint = OutPoint {
+                    tx_hash: Hash::zero(),
+                    index: index as u32,
+                };
+                let output = TxOutput {
+                    address: wallet.address(),
+                    value: Coins(*value),
+                    data: None,
+                };
+                (outpoint, output)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_send_coins_between_wallets() {
+        let alice = Wallet::new();
+        let bob = Wallet::new();
+        let mut chain = mined_chain(&alice);
+        let funds = alice.balance(&chain);
+
+        let transaction = alice
+            .create_transaction(&chain, &bob.address(), Coins::from_whole(20))
+            .unwrap();
+        let spent = chain.resolve_inputs(&transaction).unwrap();
+        assert!(transaction.has_valid_signatures(&spent));
+        let fee = transaction.fee(&spent);
+        chain.add_transaction_to_pool(transaction).unwrap();
+        mine_block(&mut chain, Wallet::new().address());
+
+        assert_eq!(Coins::from_whole(20), bob.balance(&chain));
+        assert_eq!(
+            Coins(funds.units() - Coins::from_whole(20).units() - fee.units()),
+            alice.balance(&chain)
+        );
+    }
+
+    #[test]
+    fn should_pay_several_recipients_in_one_transaction() {
+        let alice = Wallet::new();
+        let recipients = [Wallet::new(), Wallet::new(), Wallet::new()];
+        let mut chain = mined_chain(&alice);
+        let funds = alice.balance(&chain);
+        let payments = recipients
+            .iter()
+            .zip([5, 10, 15])
+            .map(|(wallet, coins)| (wallet.address(), Coins::from_whole(coins)))
+            .collect::<Vec<_>>();
+
+        let transaction = alice.create_batch_transaction(&chain, &payments).unwrap();
+        assert_eq!(4, transaction.outputs.len());
+        let spent = chain.resolve_inputs(&transaction).unwrap();
+        let fee = transaction.fee(&spent);
+        assert_eq!(estimate_fee(spent.len(), 4, DEFAULT_FEE_RATE), fee);
+        chain.add_transaction_to_pool(transaction).unwrap();
+        mine_block(&mut chain, Wallet::new().address());
+
+        for (wallet, (_, amount)) in recipients.iter().zip(&payments) {
+            assert_eq!(*amount, wallet.balance(&chain));
+        }
+        assert_eq!(
+            Coins(funds.units() - Coins::from_whole(30).units() - fee.units()),
+            alice.balance(&chain)
+        );
+
+        let too_much = [
+            (Wallet::new().address(), Coins::from_whole(30)),
+            (Wallet::new().address(), funds),
+        ];
+        assert!(matches!(
+            alice.create_batch_transaction(&chain, &too_much),
+            Err(WalletError::InsufficientFunds { .. })
+        ));
+        assert_eq!(
+            Some(WalletError::NoRecipients),
+            alice.create_batch_transaction(&chain, &[]).err()
+        );
+    }
+
+    #[test]
+    fn should_fail_without_enough_funds() {
+        let alice = Wallet::new();
+        let chain = mined_chain(&alice);
+        let funds = alice.balance(&chain);
+
+        match alice.create_transaction(&chain, &Wallet::new().address(), funds) {
+            Err(WalletError::InsufficientFunds {
+                available,
+                required,
+            }) => {
+                assert_eq!(funds, available);
+                assert!(required > funds);
+            }
+            other => panic!("expected insufficient funds, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_not_spend_immature_coinbase() {
+        let alice = Wallet::new();
+        let mut chain = Blockchain::with_params(ChainParams::regtest());
+        mine_block(&mut chain, alice.address());
+
+        assert_eq!(Coins::ZERO, alice.balance(&chain));
+        assert!(alice
+            .create_transaction(&chain, &Wallet::new().address(), Coins(1))
+            .is_err());
+    }
+
+    #[test]
+    fn should_select_exact_match_without_change() {
+        let wallet = Wallet::new();
+        let fee = estimate_fee(1, 1, DEFAULT_FEE_RATE);
+        let exact = 10_000 + fee.units();
+        let utxos = utxos(&wallet, &[1_000_000, exact, 5_000]);
+
+        let selection = wallet
+            .select_coins(&utxos, Coins(10_000), DEFAULT_FEE_RATE)
+            .unwrap();
+        assert_eq!(vec![Coins(exact)], values(&selection.inputs));
+        assert_eq!(fee, selection.fee);
+        assert_eq!(Coins::ZERO, selection.change);
+    }
+
+    #[test]
+    fn should_add_change_and_fee_per_extra_input() {
+        let wallet = Wallet::new();
+        let utxos = utxos(&wallet, &[3_000, 30_000, 20_000]);
+
+        let selection = wallet
+            .select_coins(&utxos, Coins(40_000), DEFAULT_FEE_RATE)
+            .unwrap();
+        assert_eq!(
+            vec![Coins(30_000), Coins(20_000)],
+            values(&selection.inputs)
+        );
+        assert_eq!(estimate_fee(2, 2, DEFAULT_FEE_RATE), selection.fee);
+        assert_eq!(
+            Coins(50_000 - 40_000 - selection.fee.units()),
+            selection.change
+        );
+    }
+
+    #[test]
+    fn should_donate_dust_change_to_fee() {
+        let wallet = Wallet::new();
+        let fee = estimate_fee(1, 2, DEFAULT_FEE_RATE);
+        let value = 10_000 + fee.units() + DUST_THRESHOLD.units() - 1;
+        let utxos = utxos(&wallet, &[value]);
+
+        let selection = wallet
+            .select_coins(&utxos, Coins(10_000), DEFAULT_FEE_RATE)
+            .unwrap();
+        assert_eq!(Coins::ZERO, selection.change);
+        assert_eq!(Coins(value - 10_000), selection.fee);
+    }
+
+    fn values(utxos: &[(OutPoint, TxOutput)]) -> Vec<Coins> {
+        utxos.iter().map(|(_, output)| output.value).collect()
+    }
+}
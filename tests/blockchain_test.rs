@@ -1,9 +1,18 @@
 use std::str::FromStr;
 
 use primitive_types::U256;
+use secp256k1::rand;
 
-use core::blockchain::Blockchain;
-use core::{now, Block, Transaction, TxOutput};
+use core::blockchain::{Blockchain, COINBASE_MATURITY};
+use core::{
+    now, Address, Block, ChainParams, Coins, Hash, Hashable, OutPoint, Transaction, TxInput,
+    TxOutput,
+};
+
+fn new_address() -> Address {
+    let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    Address::from_public_key(&public_key)
+}
 
 #[test]
 fn test_scenario_1() {
@@ -15,27 +24,31 @@ fn test_scenario_1() {
     let mut genesis_block = Block::new(
         0,
         now(),
-        vec![0; 32],
+        Hash::zero(),
         vec![Transaction {
             inputs: vec![],
             outputs: vec![
                 TxOutput {
-                    address: "Alice".to_owned(),
-                    value: 50.0,
+                    address: new_address(),
+                    value: Coins::from_whole(43),
+                    data: None,
                 },
                 TxOutput {
-                    address: "Bob".to_owned(),
-                    value: 7.0,
+                    address: new_address(),
+                    value: Coins::from_whole(7),
+                    data: None,
                 },
             ],
             timestamp: now(),
+            lock_time: 0,
+            chain_id: 0,
         }],
         difficulty,
     );
 
     genesis_block.mine();
     println!("Mined genesis block {:?}", &genesis_block);
-    let hash = U256::from(genesis_block.hash.as_slice());
+    let hash = U256::from(genesis_block.header.hash.as_bytes());
     assert!(hash < difficulty);
     blockchain
         .aggregate_mined_block(genesis_block)
@@ -47,57 +60,76 @@ fn test_scenario_1() {
 fn test_scenario_2() {
     // Create blockchain and genesis block
     println!("creating the blockchain");
-    let mut blockchain = Blockchain::new();
+    // Keep the genesis target for every block rather than retargeting
+    let mut blockchain = Blockchain::with_params(ChainParams::regtest());
     let difficulty =
         U256::from_str("0x000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
             .expect("Unable to parse u256");
+    let (alice_key, alice_public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
     let first_output = TxOutput {
-        address: "Alice".to_owned(),
-        value: 50.0,
+        address: Address::from_public_key(&alice_public_key),
+        value: Coins::from_whole(43),
+        data: None,
     };
     let second_output = TxOutput {
-        address: "Bob".to_owned(),
-        value: 7.0,
+        address: new_address(),
+        value: Coins::from_whole(7),
+        data: None,
     };
 
-    let mut genesis_block = Block::new(
-        0,
-        now(),
-        vec![0; 32],
-        vec![Transaction {
-            inputs: vec![],
-            outputs: vec![first_output.clone(), second_output.clone()],
-            timestamp: now(),
-        }],
-        difficulty,
-    );
+    let genesis_coinbase = Transaction {
+        inputs: vec![],
+        outputs: vec![first_output.clone(), second_output.clone()],
+        timestamp: now(),
+        lock_time: 0,
+        chain_id: 0,
+    };
+    let first_outpoint = OutPoint {
+        tx_hash: genesis_coinbase.hash(),
+        index: 0,
+    };
+    let mut genesis_block = Block::new(0, now(), Hash::zero(), vec![genesis_coinbase], difficulty);
 
     genesis_block.mine();
     println!("adding genesis block");
     blockchain
         .aggregate_mined_block(genesis_block)
         .expect("Failed to add block!");
+    // Let the genesis coinbase mature
+    for _ in 1..COINBASE_MATURITY {
+        let mut block = blockchain.create_candidate_block(0, new_address(), Some(difficulty), None);
+        block.mine();
+        blockchain
+            .aggregate_mined_block(block)
+            .expect("Failed to add block!");
+    }
     // Add transactions to the pool
-    let transaction = Transaction {
-        inputs: vec![first_output.clone()],
+    let mut transaction = Transaction {
+        inputs: vec![TxInput::new(first_outpoint)],
         outputs: vec![
             TxOutput {
-                address: String::from("ALice"),
-                value: 25.0,
+                address: first_output.address.clone(),
+                value: Coins::from_whole(25),
+                data: None,
             },
             TxOutput {
-                address: String::from("Bob"),
-                value: 4.995,
+                address: second_output.address.clone(),
+                value: Coins(499_500_000),
+                data: None,
             },
         ],
         timestamp: now(),
+        lock_time: 0,
+        chain_id: 0,
     };
+    transaction.sign(&alice_key);
 
     blockchain
         .add_transaction_to_pool(transaction)
         .expect("transaction is not valid");
     // Create and mine block
-    let mut block = blockchain.create_candidate_block(1, String::from("Alice"), difficulty);
+    let mut block =
+        blockchain.create_candidate_block(u64::MAX, new_address(), Some(difficulty), None);
     block.mine();
 
     // Validate block and add to ledger
@@ -105,5 +137,5 @@ fn test_scenario_2() {
     blockchain
         .aggregate_mined_block(block)
         .expect("Block is not valid!");
-    assert_eq!(2, blockchain.len());
+    assert_eq!(COINBASE_MATURITY as usize + 1, blockchain.len());
 }
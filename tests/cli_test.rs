@@ -0,0 +1,36 @@
+use std::path::Path;
+use std::process::Command;
+
+use core::now;
+
+fn ccore(data_dir: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_ccore"))
+        .arg("--data-dir")
+        .arg(data_dir)
+        .args(args)
+        .output()
+        .expect("ccore runs");
+    assert!(
+        output.status.success(),
+        "ccore {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[test]
+fn should_report_balance_of_new_wallet() {
+    let data_dir = std::env::temp_dir().join(format!("ccore-cli-{}", now()));
+
+    let address = ccore(&data_dir, &["new-wallet"]);
+    let empty = ccore(&data_dir, &["balance", &address]);
+    ccore(&data_dir, &["mine", &address]);
+    let mined = ccore(&data_dir, &["balance", &address]);
+    let chain = ccore(&data_dir, &["chain"]);
+    std::fs::remove_dir_all(&data_dir).unwrap();
+
+    assert_eq!("0.00000000", empty);
+    assert_eq!("50.00000000", mined);
+    assert_eq!(1, chain.lines().count());
+}
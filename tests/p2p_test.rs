@@ -0,0 +1,190 @@
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use primitive_types::U256;
+use secp256k1::rand;
+
+use core::blockchain::{Blockchain, COINBASE_MATURITY};
+use core::p2p::{read_message, write_message, Message, Node, SyncError};
+use core::{
+    now, Address, Block, ChainParams, Coins, Hashable, OutPoint, SharedChain, Transaction, TxInput,
+    TxOutput,
+};
+
+fn new_address() -> Address {
+    let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    Address::from_public_key(&public_key)
+}
+
+fn wait_until(condition: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn test_transaction_gossip() {
+    let (alice_key, alice_public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    let genesis = Block::genesis(
+        Address::from_public_key(&alice_public_key),
+        U256::max_value(),
+    );
+    let funding = genesis.transactions[0].outputs[0].clone();
+    let funding_outpoint = OutPoint {
+        tx_hash: genesis.transactions[0].hash(),
+        index: 0,
+    };
+
+    let mut first = Blockchain::with_params(ChainParams::regtest());
+    first
+        .aggregate_mined_block(genesis)
+        .expect("Failed to add block!");
+    for _ in 1..COINBASE_MATURITY {
+        let mut block =
+            first.create_candidate_block(0, new_address(), Some(U256::max_value()), None);
+        block.mine();
+        first
+            .aggregate_mined_block(block)
+            .expect("Failed to add block!");
+    }
+    let mut second = Blockchain::with_params(ChainParams::regtest());
+    for block in &first {
+        second
+            .aggregate_mined_block(block.clone())
+            .expect("Failed to add block!");
+    }
+
+    let first = Node::bind("127.0.0.1:0", SharedChain::new(first)).unwrap();
+    let second = Node::bind("127.0.0.1:0", SharedChain::new(second)).unwrap();
+    first.spawn();
+    second.spawn();
+    first.connect(second.local_addr()).unwrap();
+    assert!(wait_until(|| second.peers().contains(&first.local_addr())));
+
+    let mut transaction = Transaction {
+        inputs: vec![TxInput::new(funding_outpoint)],
+        outputs: vec![TxOutput {
+            address: new_address(),
+            value: Coins(funding.value.units() - 1_000),
+            data: None,
+        }],
+        timestamp: now(),
+        lock_time: 0,
+        chain_id: 0,
+    };
+    transaction.sign(&alice_key);
+    let hash = transaction.hash();
+    first.announce_transaction(transaction);
+
+    assert_eq!(Some(0), first.chain().read().confirmations_of(&hash));
+    assert!(wait_until(
+        || second.chain().read().confirmations_of(&hash) == Some(0)
+    ));
+}
+
+#[test]
+fn test_initial_block_download() {
+    let miner = new_address();
+    let mut synced = Blockchain::with_params(ChainParams::regtest());
+    synced
+        .aggregate_mined_block(Block::genesis(miner.clone(), U256::max_value()))
+        .expect("Failed to add block!");
+    let mut fresh = Blockchain::with_params(ChainParams::regtest());
+    fresh
+        .aggregate_mined_block(Block::genesis(miner, U256::max_value()))
+        .expect("Failed to add block!");
+    for _ in 0..5 {
+        let mut block =
+            synced.create_candidate_block(0, new_address(), Some(U256::max_value()), None);
+        block.mine();
+        synced
+            .aggregate_mined_block(block)
+            .expect("Failed to add block!");
+    }
+    let tip = synced.latest_block().unwrap().header.hash.clone();
+
+    let synced = Node::bind("127.0.0.1:0", SharedChain::new(synced)).unwrap();
+    let fresh = Node::bind("127.0.0.1:0", SharedChain::new(fresh)).unwrap();
+    synced.spawn();
+
+    assert_eq!(5, fresh.sync(synced.local_addr()).expect("Sync failed"));
+    let chain = fresh.chain().read();
+    assert_eq!(6, chain.len());
+    assert_eq!(tip, chain.latest_block().unwrap().header.hash);
+    drop(chain);
+    assert_eq!(0, fresh.sync(synced.local_addr()).expect("Sync failed"));
+}
+
+fn mine_blocks(chain: &mut Blockchain, count: usize) {
+    for _ in 0..count {
+        let mut block = chain.create_candidate_block(0, new_address(), None, None);
+        block.mine();
+        chain
+            .aggregate_mined_block(block)
+            .expect("Failed to add block!");
+    }
+}
+
+#[test]
+fn test_sync_moves_stale_fork_onto_heavier_peer_branch() {
+    let mut honest = Blockchain::with_params(ChainParams::regtest());
+    mine_blocks(&mut honest, 3);
+    let mut stale = honest.snapshot();
+    mine_blocks(&mut stale, 2);
+    let stale_tip = stale.tip_hash();
+    mine_blocks(&mut honest, 2);
+
+    let honest = Node::bind("127.0.0.1:0", SharedChain::new(honest)).unwrap();
+    let stale = Node::bind("127.0.0.1:0", SharedChain::new(stale)).unwrap();
+    honest.spawn();
+
+    // As much work on both sides of the fork keeps our own blocks
+    assert_eq!(0, stale.sync(honest.local_addr()).expect("Sync failed"));
+    assert_eq!(stale_tip, stale.chain().tip_hash());
+
+    mine_blocks(&mut honest.chain().write(), 1);
+    assert_eq!(3, stale.sync(honest.local_addr()).expect("Sync failed"));
+    assert_eq!(honest.chain().tip_hash(), stale.chain().tip_hash());
+    assert_eq!(6, stale.chain().len());
+}
+
+#[test]
+fn test_sync_disconnects_peer_sending_invalid_block() {
+    let miner = new_address();
+    let mut fresh = Blockchain::with_params(ChainParams::regtest());
+    fresh
+        .aggregate_mined_block(Block::genesis(miner, U256::max_value()))
+        .expect("Failed to add block!");
+    let mut bad_block =
+        fresh.create_candidate_block(0, new_address(), Some(U256::max_value()), None);
+    bad_block.mine();
+    bad_block.transactions[0].outputs[0].value = Coins::from_whole(1_000);
+    let fresh = Node::bind("127.0.0.1:0", SharedChain::new(fresh)).unwrap();
+
+    let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    fresh.connect(peer_addr).unwrap();
+    thread::spawn(move || {
+        // The first connection is the hello from `connect`
+        drop(peer.accept().unwrap());
+        let (mut stream, _) = peer.accept().unwrap();
+        read_message(&mut stream).unwrap();
+        let header = bad_block.header().clone();
+        write_message(&mut stream, &Message::Headers(vec![header])).unwrap();
+        read_message(&mut stream).unwrap();
+        write_message(&mut stream, &Message::Block(bad_block)).unwrap();
+    });
+
+    match fresh.sync(peer_addr) {
+        Err(SyncError::InvalidBlock(_)) => {}
+        other => panic!("expected invalid block, got {:?}", other),
+    }
+    assert_eq!(1, fresh.chain().read().len());
+    assert!(!fresh.peers().contains(&peer_addr));
+}
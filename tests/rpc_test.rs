@@ -0,0 +1,70 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use primitive_types::U256;
+use secp256k1::rand;
+use serde_json::Value;
+
+use core::blockchain::Blockchain;
+use core::p2p::Node;
+use core::rpc::RpcServer;
+use core::{Address, Block, ChainParams, Miner, SharedChain};
+
+fn new_address() -> Address {
+    let (_, public_key) = secp256k1::generate_keypair(&mut rand::thread_rng());
+    Address::from_public_key(&public_key)
+}
+
+/// POST `body` to the server at `addr` and return the JSON it answers with.
+fn post(addr: SocketAddr, body: &str) -> Value {
+    let mut stream = TcpStream::connect(addr).expect("Unable to connect");
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        addr,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    let (_, json) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(json).unwrap()
+}
+
+const GET_BLOCK_COUNT: &str = r#"{"jsonrpc":"2.0","id":1,"method":"getblockcount","params":[]}"#;
+
+#[test]
+fn test_getblockcount_round_trip() {
+    let mut blockchain = Blockchain::new();
+    blockchain
+        .aggregate_mined_block(Block::genesis(new_address(), U256::max_value()))
+        .expect("Failed to add block!");
+
+    let server = RpcServer::bind("127.0.0.1:0", SharedChain::new(blockchain))
+        .expect("Unable to bind server");
+    let addr = server.local_addr().unwrap();
+    server.spawn();
+
+    let json = post(addr, GET_BLOCK_COUNT);
+    assert_eq!(1, json["result"]);
+    assert_eq!(1, json["id"]);
+}
+
+#[test]
+fn test_miner_rpc_and_node_share_one_chain() {
+    let shared = SharedChain::new(Blockchain::with_params(ChainParams::regtest()));
+    let server = RpcServer::bind("127.0.0.1:0", shared.clone()).expect("Unable to bind server");
+    let addr = server.local_addr().unwrap();
+    server.spawn();
+    let node = Node::bind("127.0.0.1:0", shared.clone()).expect("Unable to bind node");
+    let mut miner = Miner::new(new_address(), shared);
+
+    for _ in 0..2 {
+        miner.mine_next().expect("Block was rejected");
+    }
+    assert_eq!(2, post(addr, GET_BLOCK_COUNT)["result"]);
+    assert_eq!(2, node.chain().len());
+}